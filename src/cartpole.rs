@@ -0,0 +1,274 @@
+use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use bevy_egui::{
+    egui::{self, Id},
+    EguiContext,
+};
+use bevy_prototype_debug_lines::DebugLines;
+use nalgebra::{Matrix4, Vector4};
+use std::f32::consts::PI;
+
+use crate::{
+    angle_difference, to_rectangular, wrap_angle, DtChangedEvent, IntegratorKind, SimulationClock,
+    A, B, G, LQR,
+};
+
+/// A pole hinged on a cart, the control force pushes the cart horizontally
+#[derive(Component, Debug)]
+pub struct CartPole {
+    cart_x: f32,
+    cart_dx: f32,
+    a: f32,
+    da: f32,
+    length: f32,
+    cart_mass: f32,
+    pole_mass: f32,
+    control: f32,
+    control_power: f32,
+    offset: Vec3,
+}
+
+impl Default for CartPole {
+    fn default() -> Self {
+        Self {
+            cart_x: 0.0,
+            cart_dx: 0.0,
+            a: PI + 0.2,
+            da: 0.0,
+            length: 5.0,
+            cart_mass: 1.0,
+            pole_mass: 0.2,
+            control: Default::default(),
+            control_power: 20.0,
+            offset: Default::default(),
+        }
+    }
+}
+
+impl CartPole {
+    pub fn from_offset(x: f32, y: f32) -> Self {
+        CartPole {
+            offset: Vec3::new(x, y, 0.0),
+            ..default()
+        }
+    }
+
+    fn set_control(&mut self, value: f32) {
+        self.control = value.clamp(-1.0, 1.0);
+    }
+
+    fn state(&self) -> [f32; 4] {
+        [self.cart_x, self.cart_dx, self.a, self.da]
+    }
+
+    /// Coupled cart-pole dynamics, returns (dx, ddx, da, dda)
+    fn derivative(&self, state: [f32; 4]) -> [f32; 4] {
+        let [_, dx, a, da] = state;
+        let (m_c, m_p, l) = (self.cart_mass, self.pole_mass, self.length);
+        let force = self.control * self.control_power;
+
+        // Mass matrix in (x, a)
+        let m11 = m_c + m_p;
+        let m12 = m_p * l * a.cos();
+        let m22 = m_p * l * l;
+
+        let f1 = force + m_p * l * da * da * a.sin();
+        let f2 = -m_p * G * l * a.sin();
+
+        let det = m11 * m22 - m12 * m12;
+        let ddx = (m22 * f1 - m12 * f2) / det;
+        let dda = (m11 * f2 - m12 * f1) / det;
+
+        [dx, ddx, da, dda]
+    }
+
+    /// Discretized linearization about the upright position with state (x, dx, a - π, da)
+    fn get_system(&self, dt: f32) -> (A<4>, B<4>) {
+        let (m_c, m_p, l) = (self.cart_mass, self.pole_mass, self.length);
+
+        #[rustfmt::skip]
+        let a = Matrix4::new(
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, m_p * G / m_c, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+            0.0, 0.0, (m_c + m_p) * G / (m_c * l), 0.0,
+        );
+        let b = Vector4::new(0.0, 1.0 / m_c, 0.0, 1.0 / (m_c * l)) * self.control_power;
+
+        // Second order expansion of the matrix exponential, as for the single pendulum
+        let a_d = Matrix4::identity() + a * dt + a * a * (dt * dt / 2.0);
+        let b_d = b * dt + a * b * (dt * dt / 2.0);
+
+        (a_d, b_d)
+    }
+
+    fn step(&mut self, integrator: IntegratorKind, dt: f32) {
+        let state = self.state();
+
+        let next = match integrator {
+            IntegratorKind::Euler => {
+                let [_, ddx, _, dda] = self.derivative(state);
+                let dx = state[1] + ddx * dt;
+                let da = state[3] + dda * dt;
+                [state[0] + dx * dt, dx, state[2] + da * dt, da]
+            }
+            IntegratorKind::Rk4 => {
+                let offset = |k: [f32; 4], h: f32| {
+                    let mut s = state;
+                    for (s, k) in s.iter_mut().zip(k) {
+                        *s += k * h;
+                    }
+                    s
+                };
+                let k1 = self.derivative(state);
+                let k2 = self.derivative(offset(k1, dt / 2.0));
+                let k3 = self.derivative(offset(k2, dt / 2.0));
+                let k4 = self.derivative(offset(k3, dt));
+
+                let mut next = state;
+                for i in 0..4 {
+                    next[i] += dt / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]);
+                }
+                next
+            }
+        };
+
+        self.cart_x = next[0];
+        self.cart_dx = next[1];
+        self.a = wrap_angle(next[2]);
+        self.da = next[3];
+    }
+
+    fn cart_position(&self) -> Vec3 {
+        self.offset + Vec3::new(self.cart_x, 0.0, 0.0)
+    }
+}
+
+pub fn add_cartpole(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let cartpole = CartPole::from_offset(0.0, -25.0);
+    let lqr = LQR::new(PI, cartpole.get_system(clock.dt));
+
+    commands.spawn((
+        cartpole,
+        lqr,
+        MaterialMesh2dBundle {
+            mesh: meshes
+                .add(shape::Quad::new(Vec2::new(4.0, 2.0)).into())
+                .into(),
+            material: materials.add(ColorMaterial::from(Color::WHITE)),
+            transform: Transform::default(),
+            ..default()
+        },
+    ));
+}
+
+pub fn relinearize_cartpole(
+    mut events: EventReader<DtChangedEvent>,
+    clock: Res<SimulationClock>,
+    mut query: Query<(&CartPole, &mut LQR<4>)>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for (cartpole, mut lqr) in query.iter_mut() {
+        lqr.set_system(cartpole.get_system(clock.dt));
+    }
+}
+
+pub fn control_cartpole_lqr(mut query: Query<(&mut CartPole, &mut LQR<4>)>) {
+    for (mut cartpole, mut lqr) in query.iter_mut() {
+        let Ok(k) = lqr.gain() else {
+            cartpole.set_control(0.0);
+            continue;
+        };
+
+        let x = Vector4::new(
+            cartpole.cart_x,
+            cartpole.cart_dx,
+            angle_difference(cartpole.a, lqr.set_point),
+            cartpole.da,
+        );
+
+        let u = -k * x;
+
+        cartpole.set_control(*u.index(0));
+    }
+}
+
+pub fn move_cartpole(
+    integrator: Res<IntegratorKind>,
+    clock: Res<SimulationClock>,
+    mut query: Query<&mut CartPole>,
+) {
+    for mut cartpole in query.iter_mut() {
+        cartpole.step(*integrator, clock.dt);
+    }
+}
+
+pub fn draw_cartpole(mut lines: ResMut<DebugLines>, mut query: Query<(&mut Transform, &CartPole)>) {
+    for (mut transform, cartpole) in query.iter_mut() {
+        let cart = cartpole.cart_position();
+        transform.translation = cart;
+
+        let (x, y) = to_rectangular(cartpole.length, cartpole.a);
+        lines.line(cart, cart + Vec3::new(x, y, 0.0), 0.0);
+    }
+}
+
+pub fn ui_cartpole(mut egui_context: ResMut<EguiContext>, mut query: Query<&mut CartPole>) {
+    for (i, mut cartpole) in query.iter_mut().enumerate() {
+        egui::Window::new("Cart-pole settings")
+            .id(Id::new(("cartpole", i)))
+            .resizable(true)
+            .default_pos((560.0, 500.0 + 40.0 * i as f32))
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.add(egui::Slider::new(&mut cartpole.length, 0.5..=10.0).text("length"));
+                ui.add(egui::Slider::new(&mut cartpole.cart_mass, 0.1..=5.0).text("Cart mass"));
+                ui.add(egui::Slider::new(&mut cartpole.pole_mass, 0.1..=5.0).text("Pole mass"));
+                ui.add(
+                    egui::Slider::new(&mut cartpole.control_power, 0.0..=50.0)
+                        .text("Control power"),
+                );
+
+                ui.add(egui::Slider::new(&mut cartpole.a, 0.0..=2.0 * PI).text("Angle"));
+                ui.add(egui::Slider::new(&mut cartpole.cart_x, -20.0..=20.0).text("Cart position"));
+
+                ui.label(format!("{}", cartpole.control));
+
+                if ui.button("Reset").clicked() {
+                    let template = CartPole::default();
+                    cartpole.cart_x = template.cart_x;
+                    cartpole.cart_dx = template.cart_dx;
+                    cartpole.a = template.a;
+                    cartpole.da = template.da;
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DEFAULT_DT;
+
+    #[test]
+    fn lqr_balances_from_small_angle() {
+        let mut cartpole = CartPole::default();
+        let mut lqr = LQR::new(PI, cartpole.get_system(DEFAULT_DT));
+        let k = lqr.gain().unwrap();
+
+        for _ in 0..600 {
+            let x = Vector4::from(cartpole.state()) - Vector4::new(0.0, 0.0, lqr.set_point, 0.0);
+            cartpole.set_control(*(-k * x).index(0));
+            cartpole.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        assert!(angle_difference(cartpole.a, PI).abs() < 0.01);
+        assert!(cartpole.cart_x.abs() < 0.1);
+    }
+}
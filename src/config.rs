@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::{
+    spawn_pendulum, Appearance, BangBang, Cascade, Disturbance, ExportSettings, GainSchedule,
+    ImpulseTest, KalmanFilter, LinearizationCheck, LuenbergerObserver, Mpc, NoiseConfig, Park,
+    Pendulum, PeriodicDisturbance, PolePlacement, ReferenceSignal, SetpointRamp, SlidingMode,
+    StepTest, SwingUp, LQR, PID,
+};
+
+const CONFIG_FILE: &str = "pendulums.ron";
+
+/// Everything needed to respawn one pendulum along with its controllers
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PendulumConfig {
+    pub pendulum: Pendulum,
+    /// Picked from the controller when missing
+    pub appearance: Option<Appearance>,
+    pub pid: Option<PID>,
+    pub lqr: Option<LQR>,
+    pub swing_up: Option<SwingUp>,
+    pub noise: Option<NoiseConfig>,
+    pub kalman: Option<KalmanFilter>,
+    pub observer: Option<LuenbergerObserver>,
+    pub ramp: Option<SetpointRamp>,
+    pub reference: Option<ReferenceSignal>,
+    pub pole_placement: Option<PolePlacement>,
+    pub bang_bang: Option<BangBang>,
+    pub mpc: Option<Mpc>,
+    pub gain_schedule: Option<GainSchedule>,
+    pub sliding_mode: Option<SlidingMode>,
+    pub park: Option<Park>,
+    pub cascade: Option<Cascade>,
+    pub disturbance: Option<Disturbance>,
+    pub periodic_disturbance: Option<PeriodicDisturbance>,
+    pub step_test: Option<StepTest>,
+    pub impulse_test: Option<ImpulseTest>,
+    pub linearization: Option<LinearizationCheck>,
+}
+
+pub enum ConfigEvent {
+    Save,
+    Load,
+}
+
+/// Message for the error popup, set when saving or loading fails
+#[derive(Resource, Default)]
+pub struct ConfigError(pub Option<String>);
+
+#[allow(clippy::type_complexity)]
+pub fn handle_config_events(
+    mut commands: Commands,
+    mut events: EventReader<ConfigEvent>,
+    mut error: ResMut<ConfigError>,
+    export: Res<ExportSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<(
+        Entity,
+        (&Pendulum, Option<&Appearance>),
+        Option<&PID>,
+        Option<&LQR>,
+        Option<&SwingUp>,
+        Option<&NoiseConfig>,
+        (Option<&KalmanFilter>, Option<&LuenbergerObserver>),
+        (Option<&SetpointRamp>, Option<&ReferenceSignal>),
+        Option<&PolePlacement>,
+        Option<&BangBang>,
+        Option<&Mpc>,
+        Option<&GainSchedule>,
+        (Option<&SlidingMode>, Option<&Park>, Option<&Cascade>),
+        (Option<&Disturbance>, Option<&PeriodicDisturbance>),
+        (
+            Option<&StepTest>,
+            Option<&ImpulseTest>,
+            Option<&LinearizationCheck>,
+        ),
+    )>,
+) {
+    let path = Path::new(&export.directory).join(CONFIG_FILE);
+
+    for event in events.iter() {
+        match event {
+            ConfigEvent::Save => {
+                let configs: Vec<PendulumConfig> = query
+                    .iter()
+                    .map(
+                        |(
+                            _,
+                            (pendulum, appearance),
+                            pid,
+                            lqr,
+                            swing_up,
+                            noise,
+                            (kalman, observer),
+                            (ramp, reference),
+                            placement,
+                            bang,
+                            mpc,
+                            schedule,
+                            (sliding, park, cascade),
+                            (disturbance, periodic),
+                            (step_test, impulse, linearization),
+                        )| {
+                            PendulumConfig {
+                                pendulum: pendulum.clone(),
+                                appearance: appearance.cloned(),
+                                pid: pid.cloned(),
+                                lqr: lqr.cloned(),
+                                swing_up: swing_up.cloned(),
+                                noise: noise.cloned(),
+                                kalman: kalman.cloned(),
+                                observer: observer.cloned(),
+                                ramp: ramp.cloned(),
+                                reference: reference.cloned(),
+                                pole_placement: placement.cloned(),
+                                bang_bang: bang.cloned(),
+                                mpc: mpc.cloned(),
+                                gain_schedule: schedule.cloned(),
+                                sliding_mode: sliding.cloned(),
+                                park: park.cloned(),
+                                cascade: cascade.cloned(),
+                                disturbance: disturbance.cloned(),
+                                periodic_disturbance: periodic.cloned(),
+                                step_test: step_test.cloned(),
+                                impulse_test: impulse.cloned(),
+                                linearization: linearization.cloned(),
+                            }
+                        },
+                    )
+                    .collect();
+
+                let result = ron::ser::to_string_pretty(&configs, Default::default())
+                    .map_err(|err| err.to_string())
+                    .and_then(|ron| fs::write(&path, ron).map_err(|err| err.to_string()));
+
+                match result {
+                    Ok(()) => info!("Saved config to {}", path.display()),
+                    Err(err) => {
+                        error.0 = Some(format!("Failed to save {}: {}", path.display(), err))
+                    }
+                }
+            }
+            ConfigEvent::Load => {
+                let result = fs::read_to_string(&path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|ron| {
+                        ron::from_str::<Vec<PendulumConfig>>(&ron).map_err(|err| err.to_string())
+                    });
+
+                let configs = match result {
+                    Ok(configs) => configs,
+                    Err(err) => {
+                        error.0 = Some(format!("Failed to load {}: {}", path.display(), err));
+                        continue;
+                    }
+                };
+
+                for (entity, ..) in query.iter() {
+                    commands.entity(entity).despawn();
+                }
+                for config in configs {
+                    spawn_pendulum(&mut commands, &mut meshes, &mut materials, config);
+                }
+                info!("Loaded config from {}", path.display());
+            }
+        }
+    }
+}
+
+pub fn ui_config_error(mut egui_context: ResMut<EguiContext>, mut error: ResMut<ConfigError>) {
+    let Some(message) = error.0.clone() else {
+        return;
+    };
+
+    egui::Window::new("Error")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, (0.0, 0.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.label(message);
+            if ui.button("OK").clicked() {
+                error.0 = None;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trips_through_ron() {
+        let configs = vec![PendulumConfig {
+            pendulum: Pendulum::from_offset(3.0, 0.0),
+            pid: Some(PID::default()),
+            ..default()
+        }];
+
+        let ron = ron::ser::to_string_pretty(&configs, Default::default()).unwrap();
+        let loaded: Vec<PendulumConfig> = ron::from_str(&ron).unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].pendulum.offset, configs[0].pendulum.offset);
+        assert!(loaded[0].pid.is_some());
+        assert!(loaded[0].lqr.is_none());
+    }
+
+    #[test]
+    fn malformed_config_is_an_error() {
+        assert!(ron::from_str::<Vec<PendulumConfig>>("[(pendulum: 3)]").is_err());
+    }
+}
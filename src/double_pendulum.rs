@@ -0,0 +1,220 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Id},
+    EguiContext,
+};
+use bevy_prototype_debug_lines::DebugLines;
+use std::f32::consts::PI;
+
+use crate::{to_rectangular, wrap_angle, IntegratorKind, SimulationClock, G};
+
+/// Two point masses on massless rigid links, actuated at the base joint
+#[derive(Component, Debug)]
+pub struct DoublePendulum {
+    a1: f32,
+    a2: f32,
+    da1: f32,
+    da2: f32,
+    length1: f32,
+    length2: f32,
+    mass1: f32,
+    mass2: f32,
+    control: f32,
+    control_power: f32,
+    offset: Vec3,
+}
+
+impl Default for DoublePendulum {
+    fn default() -> Self {
+        Self {
+            a1: 2.0,
+            a2: 2.5,
+            da1: 0.0,
+            da2: 0.0,
+            length1: 5.0,
+            length2: 5.0,
+            mass1: 1.0,
+            mass2: 1.0,
+            control: Default::default(),
+            control_power: 5.0,
+            offset: Default::default(),
+        }
+    }
+}
+
+impl DoublePendulum {
+    pub fn from_offset(x: f32, y: f32) -> Self {
+        DoublePendulum {
+            offset: Vec3::new(x, y, 0.0),
+            ..default()
+        }
+    }
+
+    pub fn set_control(&mut self, value: f32) {
+        self.control = value.clamp(-1.0, 1.0);
+    }
+
+    /// Positions of the elbow and the tip relative to the pivot
+    fn to_rectangular(&self) -> (Vec3, Vec3) {
+        let (x1, y1) = to_rectangular(self.length1, self.a1);
+        let (x2, y2) = to_rectangular(self.length2, self.a2);
+        let elbow = Vec3::new(x1, y1, 0.0);
+        (elbow, elbow + Vec3::new(x2, y2, 0.0))
+    }
+
+    /// Equations of motion from the Lagrangian, returns (da1, da2, dda1, dda2)
+    ///
+    /// The control torque acts on the base joint only and is scaled by the base link's inertia,
+    /// so `control_power` means the same angular acceleration as on a single `Pendulum`
+    fn derivative(&self, state: [f32; 4]) -> [f32; 4] {
+        let [a1, a2, da1, da2] = state;
+        let (m1, m2, l1, l2) = (self.mass1, self.mass2, self.length1, self.length2);
+        let delta = a1 - a2;
+
+        // Mass matrix
+        let m11 = (m1 + m2) * l1 * l1;
+        let m12 = m2 * l1 * l2 * delta.cos();
+        let m22 = m2 * l2 * l2;
+
+        let torque = self.control * self.control_power * m11;
+        let f1 = -m2 * l1 * l2 * delta.sin() * da2 * da2 - (m1 + m2) * G * l1 * a1.sin() + torque;
+        let f2 = m2 * l1 * l2 * delta.sin() * da1 * da1 - m2 * G * l2 * a2.sin();
+
+        let det = m11 * m22 - m12 * m12;
+        let dda1 = (m22 * f1 - m12 * f2) / det;
+        let dda2 = (m11 * f2 - m12 * f1) / det;
+
+        [da1, da2, dda1, dda2]
+    }
+
+    pub fn step(&mut self, integrator: IntegratorKind, dt: f32) {
+        let state = [self.a1, self.a2, self.da1, self.da2];
+
+        let next = match integrator {
+            IntegratorKind::Euler => {
+                let [_, _, dda1, dda2] = self.derivative(state);
+                let da1 = state[2] + dda1 * dt;
+                let da2 = state[3] + dda2 * dt;
+                [state[0] + da1 * dt, state[1] + da2 * dt, da1, da2]
+            }
+            IntegratorKind::Rk4 => {
+                let offset = |k: [f32; 4], h: f32| {
+                    let mut s = state;
+                    for (s, k) in s.iter_mut().zip(k) {
+                        *s += k * h;
+                    }
+                    s
+                };
+                let k1 = self.derivative(state);
+                let k2 = self.derivative(offset(k1, dt / 2.0));
+                let k3 = self.derivative(offset(k2, dt / 2.0));
+                let k4 = self.derivative(offset(k3, dt));
+
+                let mut next = state;
+                for i in 0..4 {
+                    next[i] += dt / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]);
+                }
+                next
+            }
+        };
+
+        self.a1 = wrap_angle(next[0]);
+        self.a2 = wrap_angle(next[1]);
+        self.da1 = next[2];
+        self.da2 = next[3];
+    }
+}
+
+pub fn add_double_pendulum(mut commands: Commands) {
+    commands.spawn(DoublePendulum::from_offset(-28.0, 0.0));
+}
+
+pub fn move_double_pendulum(
+    integrator: Res<IntegratorKind>,
+    clock: Res<SimulationClock>,
+    mut query: Query<&mut DoublePendulum>,
+) {
+    for mut pendulum in query.iter_mut() {
+        pendulum.step(*integrator, clock.dt);
+    }
+}
+
+pub fn draw_double_pendulum(mut lines: ResMut<DebugLines>, query: Query<&DoublePendulum>) {
+    for pendulum in query.iter() {
+        let (elbow, tip) = pendulum.to_rectangular();
+        lines.line(pendulum.offset, elbow + pendulum.offset, 0.0);
+        lines.line(elbow + pendulum.offset, tip + pendulum.offset, 0.0);
+    }
+}
+
+pub fn ui_double_pendulum(
+    mut egui_context: ResMut<EguiContext>,
+    mut query: Query<&mut DoublePendulum>,
+) {
+    for (i, mut pendulum) in query.iter_mut().enumerate() {
+        egui::Window::new("Double pendulum settings")
+            .id(Id::new(("double pendulum", i)))
+            .resizable(true)
+            .default_pos((20.0, 500.0 + 40.0 * i as f32))
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.add(egui::Slider::new(&mut pendulum.length1, 0.5..=10.0).text("Length 1"));
+                ui.add(egui::Slider::new(&mut pendulum.length2, 0.5..=10.0).text("Length 2"));
+                ui.add(egui::Slider::new(&mut pendulum.mass1, 0.1..=5.0).text("Mass 1"));
+                ui.add(egui::Slider::new(&mut pendulum.mass2, 0.1..=5.0).text("Mass 2"));
+                ui.add(
+                    egui::Slider::new(&mut pendulum.control_power, 0.0..=20.0)
+                        .text("Control power"),
+                );
+
+                ui.add(egui::Slider::new(&mut pendulum.a1, 0.0..=2.0 * PI).text("Angle 1"));
+                ui.add(egui::Slider::new(&mut pendulum.a2, 0.0..=2.0 * PI).text("Angle 2"));
+
+                // Driven by hand only, none of the controllers model two links
+                let mut control = pendulum.control;
+                if ui
+                    .add(egui::Slider::new(&mut control, -1.0..=1.0).text("Base torque"))
+                    .changed()
+                {
+                    pendulum.set_control(control);
+                }
+
+                if ui.button("Reset").clicked() {
+                    let template = DoublePendulum::default();
+                    pendulum.a1 = template.a1;
+                    pendulum.a2 = template.a2;
+                    pendulum.da1 = template.da1;
+                    pendulum.da2 = template.da2;
+                    pendulum.control = template.control;
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn energy(p: &DoublePendulum) -> f32 {
+        let (elbow, tip) = p.to_rectangular();
+        let v1 = p.length1 * p.da1;
+        let v2_sq = v1 * v1
+            + (p.length2 * p.da2).powi(2)
+            + 2.0 * p.length1 * p.length2 * p.da1 * p.da2 * (p.a1 - p.a2).cos();
+        let kinetic = 0.5 * p.mass1 * v1 * v1 + 0.5 * p.mass2 * v2_sq;
+        let potential = p.mass1 * G * elbow.y + p.mass2 * G * tip.y;
+        kinetic + potential
+    }
+
+    #[test]
+    fn undriven_double_pendulum_conserves_energy() {
+        let mut pendulum = DoublePendulum::default();
+        let initial = energy(&pendulum);
+
+        for _ in 0..1000 {
+            pendulum.step(IntegratorKind::Rk4, 0.01);
+        }
+
+        let drift = (energy(&pendulum) - initial).abs() / initial.abs();
+        assert!(drift < 0.01, "energy drifted by {}", drift);
+    }
+}
@@ -0,0 +1,199 @@
+use std::f32::consts::PI;
+
+use crate::{angle_difference, IntegratorKind, Pendulum, DEFAULT_DT, LQR, PID};
+
+/// The error has to stay within this band for the pendulum to count as settled
+const SETTLING_TOLERANCE: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Controller {
+    Pid,
+    Lqr,
+}
+
+/// Settings for a batch run, parsed from the command line
+#[derive(Debug)]
+pub struct Options {
+    pub controller: Controller,
+    pub gain_p: f32,
+    pub gain_i: f32,
+    pub gain_d: f32,
+    pub set_point: f32,
+    pub steps: usize,
+    pub dt: f32,
+    pub integrator: IntegratorKind,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            controller: Controller::Pid,
+            gain_p: -8.0,
+            gain_i: -5.5,
+            gain_d: -4.0,
+            set_point: PI,
+            steps: 1000,
+            dt: DEFAULT_DT,
+            integrator: IntegratorKind::default(),
+        }
+    }
+}
+
+impl Options {
+    /// Parses `--flag value` pairs, flags that are not listed are rejected
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.iter();
+
+        while let Some(flag) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", flag))
+            };
+
+            match flag.as_str() {
+                "--headless" => {}
+                "--controller" => {
+                    options.controller = match value()?.as_str() {
+                        "pid" => Controller::Pid,
+                        "lqr" => Controller::Lqr,
+                        other => return Err(format!("unknown controller {}", other)),
+                    }
+                }
+                "--integrator" => {
+                    options.integrator = match value()?.as_str() {
+                        "euler" => IntegratorKind::Euler,
+                        "rk4" => IntegratorKind::Rk4,
+                        other => return Err(format!("unknown integrator {}", other)),
+                    }
+                }
+                "--gain-p" => options.gain_p = parse_number(flag, value()?)?,
+                "--gain-i" => options.gain_i = parse_number(flag, value()?)?,
+                "--gain-d" => options.gain_d = parse_number(flag, value()?)?,
+                "--set-point" => options.set_point = parse_number(flag, value()?)?,
+                "--steps" => options.steps = parse_number(flag, value()?)?,
+                "--dt" => options.dt = parse_number(flag, value()?)?,
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {} for {}", value, flag))
+}
+
+/// Outcome of a batch run
+#[derive(Debug)]
+pub struct Report {
+    pub final_angle: f32,
+    pub final_error: f32,
+    /// Time after which the error stayed within `SETTLING_TOLERANCE`, `None` if it never did
+    pub settling_time: Option<f32>,
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "final angle: {}", self.final_angle)?;
+        writeln!(f, "final error: {}", self.final_error)?;
+        match self.settling_time {
+            Some(t) => write!(f, "settling time: {}", t),
+            None => write!(f, "settling time: did not settle"),
+        }
+    }
+}
+
+/// Steps a single pendulum with the chosen controller, the same order of operations as the
+/// physics stage but without any Bevy systems
+pub fn run(options: &Options) -> Report {
+    let mut pendulum = Pendulum::default();
+    let mut pid = PID {
+        set_point: options.set_point,
+        proportional_gain: options.gain_p,
+        integral_gain: options.gain_i,
+        derivative_gain: options.gain_d,
+        ..Default::default()
+    };
+    let mut lqr = LQR::new(
+        options.set_point,
+        pendulum.get_system(options.set_point, options.dt),
+    );
+
+    let mut last_unsettled = None;
+
+    for step in 0..options.steps {
+        pendulum.measured_a = pendulum.a;
+        pendulum.measured_da = pendulum.da;
+
+        let control = match options.controller {
+            Controller::Pid => pid.control(&pendulum, options.dt),
+            // Same as the physics stage, no gain means no control
+            Controller::Lqr => lqr
+                .control(&pendulum, (pendulum.measured_a, pendulum.measured_da))
+                .unwrap_or(0.0),
+        };
+        pendulum.set_control(control, options.dt);
+        pendulum.step_delayed(options.integrator, options.dt);
+
+        if angle_difference(pendulum.a, options.set_point).abs() > SETTLING_TOLERANCE {
+            last_unsettled = Some(step);
+        }
+    }
+
+    let settling_time = match last_unsettled {
+        Some(step) if step + 1 == options.steps => None,
+        Some(step) => Some((step + 1) as f32 * options.dt),
+        None => Some(0.0),
+    };
+
+    Report {
+        final_angle: pendulum.a,
+        final_error: angle_difference(pendulum.a, options.set_point),
+        settling_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_gains_and_steps() {
+        let options = Options::parse(&args("--headless --gain-p -8 --steps 2000")).unwrap();
+        assert_eq!(options.gain_p, -8.0);
+        assert_eq!(options.steps, 2000);
+        assert_eq!(options.controller, Controller::Pid);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(Options::parse(&args("--steps")).is_err());
+        assert!(Options::parse(&args("--steps many")).is_err());
+        assert!(Options::parse(&args("--frobnicate")).is_err());
+    }
+
+    #[test]
+    fn default_gains_settle() {
+        for controller in [Controller::Pid, Controller::Lqr] {
+            let report = run(&Options {
+                controller,
+                steps: 2000,
+                ..Default::default()
+            });
+            assert!(report.final_error.abs() < SETTLING_TOLERANCE);
+            assert!(
+                report.settling_time.is_some(),
+                "{:?} did not settle",
+                controller
+            );
+        }
+    }
+}
@@ -1,58 +1,518 @@
-use bevy::{input::mouse::MouseMotion, prelude::*, sprite::MaterialMesh2dBundle};
+mod cartpole;
+mod config;
+mod double_pendulum;
+mod headless;
+mod mpc;
+mod plot_image;
+mod recorder;
+mod telemetry;
+
+use bevy::{
+    ecs::{query::WorldQuery, schedule::ShouldRun},
+    input::mouse::MouseMotion,
+    prelude::*,
+    sprite::MaterialMesh2dBundle,
+};
 use bevy_egui::{
     egui::{
         self,
-        plot::{Legend, Line, Plot, PlotPoints},
+        plot::{HLine, Legend, Line, LineStyle, Plot, PlotPoints, Polygon},
         Id,
     },
     EguiContext, EguiPlugin,
 };
 use bevy_prototype_debug_lines::*;
-use lqr::LQRController;
-use nalgebra::{ArrayStorage, Const, Matrix, Matrix1, Matrix1x2, Matrix2, Matrix2x1};
-use std::f32::consts::PI;
+use cartpole::{
+    add_cartpole, control_cartpole_lqr, draw_cartpole, move_cartpole, relinearize_cartpole,
+    ui_cartpole,
+};
+use config::{handle_config_events, ui_config_error, ConfigError, ConfigEvent, PendulumConfig};
+use double_pendulum::{
+    add_double_pendulum, draw_double_pendulum, move_double_pendulum, ui_double_pendulum,
+};
+use mpc::{control_pendulum_mpc, draw_mpc_prediction, Mpc};
+use nalgebra::{ArrayStorage, Const, DMatrix, DimMin, Matrix, Matrix2, Matrix2x1, Matrix3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use recorder::{
+    handle_recorder_events, record_pendulums, replay_pendulums, Recorder, RecorderEvent,
+    RecorderMode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    f32::consts::{PI, TAU},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use telemetry::{parse_telemetry_port, stream_telemetry, ui_telemetry, Telemetry};
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        match headless::Options::parse(&args) {
+            Ok(options) => println!("{}", headless::run(&options)),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+
+    let seed = match parse_seed(&args) {
+        Ok(seed) => seed,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let telemetry_port = match parse_telemetry_port(&args) {
+        Ok(port) => port,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.9, 0.3, 0.6)))
         .add_plugins(DefaultPlugins)
         .add_plugin(EguiPlugin)
         .add_plugin(DebugLinesPlugin::default())
+        .init_resource::<IntegratorKind>()
+        .init_resource::<SimulationClock>()
+        .init_resource::<SimState>()
+        .init_resource::<RealTimeFactor>()
+        .init_resource::<ExportSettings>()
+        .init_resource::<HistoryCapacity>()
+        .init_resource::<ConfigError>()
+        .init_resource::<ManualInput>()
+        .init_resource::<Grab>()
+        .init_resource::<SetpointNudge>()
+        .init_resource::<PendulumLayout>()
+        .init_resource::<SettlingBand>()
+        .init_resource::<InvariantCheck>()
+        .init_resource::<StabilityIndicator>()
+        .init_resource::<Recorder>()
+        .init_resource::<InitialRanges>()
+        .init_resource::<InitialPresets>()
+        .insert_resource(AppRng::new(seed))
+        .insert_resource(Telemetry::new(telemetry_port))
+        .add_event::<ConfigEvent>()
+        .add_event::<PendulumEvent>()
+        .add_event::<ResetAllEvent>()
+        .add_event::<RandomizeAllEvent>()
+        .add_event::<PendulumResetEvent>()
+        .add_event::<DtChangedEvent>()
+        .add_event::<RecorderEvent>()
+        .add_stage_after(
+            CoreStage::Update,
+            PhysicsStage,
+            SystemStage::parallel().with_run_criteria(fixed_step),
+        )
         .add_startup_system(add_pendulum)
-        .add_system(ui_example)
-        // .add_system(control_pendulum_keyboard)
-        // .add_system(control_pendulum_mouse)
-        .add_system(control_pendulum_pid)
-        .add_system(control_pendulum_lqr)
-        .add_system(move_pendulum)
+        .add_startup_system(add_double_pendulum)
+        .add_startup_system(add_cartpole)
+        .add_system_to_stage(CoreStage::PreUpdate, advance_clock)
+        .add_system(ui_example.label(SendsResets))
+        .add_system(ui_simulation)
+        .add_system(ui_comparison)
+        .add_system(ui_summary)
+        .add_system(ui_telemetry)
+        .add_system(ui_real_time)
+        .add_system(ui_double_pendulum)
+        .add_system(ui_cartpole)
+        .add_system(sim_state_keyboard)
+        .add_system(cycle_selection)
+        .add_system(nudge_setpoint)
+        .add_system(apply_history_capacity)
+        .add_system(handle_config_events)
+        .add_system(handle_pendulum_events)
+        .add_system(reset_all_pendulums.label(SendsResets))
+        .add_system(randomize_all_pendulums.label(SendsResets))
+        .add_system_set(reset_systems())
+        .add_system(relinearize_pendulums)
+        .add_system(relinearize_cartpole)
+        .add_system(ui_config_error)
+        .add_system(handle_recorder_events)
+        .add_system(control_pendulum_keyboard)
+        .add_system(control_pendulum_mouse)
+        .add_system(grab_pendulum)
+        .add_system(check_linearization)
+        .add_system_to_stage(PhysicsStage, measure_pendulum.before(move_pendulum))
+        .add_system_to_stage(PhysicsStage, ramp_setpoints.before(move_pendulum))
+        .add_system_to_stage(
+            PhysicsStage,
+            track_reference
+                .after(ramp_setpoints)
+                .before(observe_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            observe_pendulum
+                .after(measure_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_pid
+                .after(observe_pendulum)
+                .after(ramp_setpoints)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            estimate_pendulum
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_lqr
+                .after(estimate_pendulum)
+                .after(ramp_setpoints)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(PhysicsStage, control_pendulum_swingup.before(move_pendulum))
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_bangbang
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_poleplace
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_scheduled
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_sliding
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_park
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_cascade
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(
+            PhysicsStage,
+            control_pendulum_mpc
+                .after(observe_pendulum)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(PhysicsStage, control_pendulum_manual.before(move_pendulum))
+        .add_system_to_stage(
+            PhysicsStage,
+            identify_friction
+                .after(control_pendulum_manual)
+                .before(move_pendulum),
+        )
+        .add_system_to_stage(PhysicsStage, move_pendulum)
+        .add_system_to_stage(PhysicsStage, history.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, sweep_frequency.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, run_step_test.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, run_impulse_test.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, check_invariants.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, update_stability.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, record_pendulums.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, stream_telemetry.after(move_pendulum))
+        .add_system_to_stage(PhysicsStage, move_double_pendulum)
+        .add_system_to_stage(PhysicsStage, control_cartpole_lqr.before(move_cartpole))
+        .add_system_to_stage(PhysicsStage, move_cartpole)
+        .add_system(replay_pendulums.before(draw_pendulum))
         .add_system(draw_pendulum)
         .add_system(debug_draw)
-        .add_system(history)
+        .add_system(draw_double_pendulum)
+        .add_system(draw_cartpole)
+        .add_system(draw_mpc_prediction)
         .run();
 }
 
-#[derive(Component, Debug)]
+/// Units of `Pendulum::control` and of the limits applied to it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum ControlMode {
+    /// Scaled by `control_power` before it reaches the pendulum
+    #[default]
+    Normalized,
+    /// Torque about the pivot, divided by the inertia of the bob
+    Torque,
+}
+
+impl ControlMode {
+    fn units(self) -> &'static str {
+        match self {
+            ControlMode::Normalized => "normalized",
+            ControlMode::Torque => "N·m",
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 struct Pendulum {
     a: f32,
     da: f32,
     length: f32,
+    /// Mass of the bob, it only matters for torque control since gravity scales with it too
+    mass: f32,
     friction: f32,
+    /// Magnitude of the speed independent friction opposing motion about the pivot
+    coulomb_friction: f32,
+    gravity: f32,
     control: f32,
+    control_mode: ControlMode,
     control_power: f32,
-    control_history: Vec<f32>,
+    control_min: f32,
+    control_max: f32,
+    /// Inputs with a smaller magnitude than this are dropped
+    dead_zone: f32,
+    /// Fastest the control can change, per second
+    max_control_rate: f32,
+    /// Switched off the pendulum swings freely, no controller runs and the control stays at zero
+    control_enabled: bool,
+    /// Physics steps per control update, the control is held in between
+    control_decimation: u32,
+    /// Physics steps since the last control update
+    #[serde(skip)]
+    control_tick: u32,
+    /// Integrator steps each physics step is split into, the control still updates once per step
+    physics_substeps: u32,
+    /// Physics steps between the control being set and it reaching the pendulum
+    control_delay_steps: u32,
+    /// Controls set but not applied yet, oldest first
+    #[serde(skip)]
+    pending_controls: VecDeque<f32>,
+    /// Input that drove the pendulum over the last step
+    #[serde(skip)]
+    applied_control: f32,
+    /// Physical stops, the pendulum swings counterclockwise from `min_angle` to `max_angle`
+    min_angle: Option<f32>,
+    max_angle: Option<f32>,
+    /// Fraction of the speed kept, reversed, when hitting a stop
+    restitution: f32,
+    /// State as seen by the controllers, equal to the true state unless noise is injected
+    measured_a: f32,
+    measured_da: f32,
+    /// Parameters assumed by the controllers, `None` when they match the simulated ones
+    model: Option<PendulumParams>,
+    controller: ControllerKind,
+    #[serde(skip)]
+    control_history: History,
+    #[serde(skip)]
+    angle_history: History,
+    #[serde(skip)]
+    measured_angle_history: History,
+    #[serde(skip)]
+    energy_history: History,
+    #[serde(skip)]
+    disturbance_history: History,
+    /// Integral of control² over simulated time since the last reset
+    #[serde(skip)]
+    effort: f32,
+    /// Number of past bob positions drawn behind it, 0 draws no trail
+    trail_length: usize,
+    /// Bob positions relative to the pivot, oldest first
+    #[serde(skip)]
+    trail: VecDeque<Vec2>,
     offset: Vec3,
 }
 
+/// Which of the controllers on a pendulum gets to write its control input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum ControllerKind {
+    Manual,
+    /// What every pendulum ran before the controller could be picked, so older configs keep it
+    #[default]
+    Pid,
+    Lqr,
+    /// Energy pumping until near the top, then LQR
+    SwingUp,
+    BangBang,
+    PolePlacement,
+    Mpc,
+    GainSchedule,
+    SlidingMode,
+    /// Damping down to rest at the bottom, then off
+    Park,
+    /// Position loop feeding the set point of a velocity loop
+    Cascade,
+}
+
+impl ControllerKind {
+    const ALL: [ControllerKind; 11] = [
+        ControllerKind::Manual,
+        ControllerKind::Pid,
+        ControllerKind::Lqr,
+        ControllerKind::SwingUp,
+        ControllerKind::BangBang,
+        ControllerKind::PolePlacement,
+        ControllerKind::Mpc,
+        ControllerKind::GainSchedule,
+        ControllerKind::SlidingMode,
+        ControllerKind::Park,
+        ControllerKind::Cascade,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ControllerKind::Manual => "Manual",
+            ControllerKind::Pid => "PID",
+            ControllerKind::Lqr => "LQR",
+            ControllerKind::SwingUp => "Swing-up",
+            ControllerKind::BangBang => "Bang-bang",
+            ControllerKind::PolePlacement => "Pole placement",
+            ControllerKind::Mpc => "MPC",
+            ControllerKind::GainSchedule => "Gain schedule",
+            ControllerKind::SlidingMode => "Sliding mode",
+            ControllerKind::Park => "Park",
+            ControllerKind::Cascade => "Cascade",
+        }
+    }
+
+    /// Default color of pendulums spawned with this controller
+    fn color(self) -> [f32; 3] {
+        match self {
+            ControllerKind::Manual => [1.0, 1.0, 1.0],
+            ControllerKind::Pid => [0.9, 0.2, 0.2],
+            ControllerKind::Lqr => [0.25, 0.45, 1.0],
+            ControllerKind::SwingUp => [1.0, 0.6, 0.1],
+            ControllerKind::BangBang => [0.7, 0.3, 0.9],
+            ControllerKind::PolePlacement => [0.2, 0.8, 0.3],
+            ControllerKind::Mpc => [0.2, 0.85, 0.85],
+            ControllerKind::GainSchedule => [0.6, 0.8, 0.2],
+            ControllerKind::SlidingMode => [1.0, 0.4, 0.7],
+            ControllerKind::Park => [0.6, 0.6, 0.6],
+            ControllerKind::Cascade => [0.85, 0.65, 0.4],
+        }
+    }
+}
+
+/// How a pendulum is drawn, so overlapping ones can be told apart
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Appearance {
+    bob_radius: f32,
+    /// Width of the arm in world units
+    thickness: f32,
+    /// Linear RGB of the bob and arm
+    color: [f32; 3],
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Self::for_controller(ControllerKind::Manual)
+    }
+}
+
+impl Appearance {
+    fn for_controller(controller: ControllerKind) -> Self {
+        Self {
+            bob_radius: 1.0,
+            thickness: 0.1,
+            color: controller.color(),
+        }
+    }
+
+    fn color(&self) -> Color {
+        let [r, g, b] = self.color;
+        Color::rgb(r, g, b)
+    }
+}
+
+/// Debug lines are a pixel wide, thicker arms are drawn as this many world units apart parallel
+/// lines, about a pixel at the default zoom
+const ARM_LINE_SPACING: f32 = 0.1;
+
+/// Radius of the dot marking each pivot and of the stability dot next to each bob
+const PIVOT_MARKER_RADIUS: f32 = 0.4;
+const STATUS_MARKER_RADIUS: f32 = 0.3;
+/// Sides of the polygon the dots are drawn as
+const MARKER_SIDES: usize = 12;
+
+/// Segments of a small polygon around `center` with spokes across it, so it reads as a dot
+/// rather than a ring
+fn dot_marker(center: Vec3, radius: f32) -> impl Iterator<Item = (Vec3, Vec3)> {
+    let corner = move |i: usize| {
+        let angle = i as f32 / MARKER_SIDES as f32 * 2.0 * PI;
+        center + Vec3::new(angle.cos(), angle.sin(), 0.0) * radius
+    };
+    let rim = (0..MARKER_SIDES).map(move |i| (corner(i), corner(i + 1)));
+    let spokes = (0..MARKER_SIDES / 2).map(move |i| (corner(i), corner(i + MARKER_SIDES / 2)));
+    rim.chain(spokes)
+}
+
+/// Offsets across the arm in direction `normal` for the parallel lines making up `thickness`
+fn arm_offsets(normal: Vec3, thickness: f32) -> impl Iterator<Item = Vec3> {
+    let count = (thickness / ARM_LINE_SPACING).round().max(1.0) as usize;
+    (0..count).map(move |i| normal * ((i as f32 - (count - 1) as f32 / 2.0) * ARM_LINE_SPACING))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct PendulumParams {
+    length: f32,
+    friction: f32,
+    #[serde(default)]
+    coulomb_friction: f32,
+    gravity: f32,
+    control_power: f32,
+}
+
 impl Default for Pendulum {
     fn default() -> Self {
         Self {
             a: PI + 0.5,
             da: 0.1,
             length: 10.0,
+            mass: 1.0,
             friction: 0.0,
+            coulomb_friction: 0.0,
+            gravity: G,
             control: Default::default(),
+            control_mode: ControlMode::Normalized,
             control_power: 5.0,
+            control_min: -1.0,
+            control_max: 1.0,
+            dead_zone: 0.0,
+            max_control_rate: f32::INFINITY,
+            control_decimation: 1,
+            control_enabled: true,
+            control_tick: 0,
+            physics_substeps: 1,
+            control_delay_steps: 0,
+            pending_controls: VecDeque::new(),
+            applied_control: 0.0,
+            min_angle: None,
+            max_angle: None,
+            restitution: 0.5,
+            measured_a: PI + 0.5,
+            measured_da: 0.1,
+            model: None,
+            controller: Default::default(),
             control_history: Default::default(),
+            angle_history: Default::default(),
+            measured_angle_history: Default::default(),
+            energy_history: Default::default(),
+            disturbance_history: Default::default(),
+            effort: 0.0,
+            trail_length: 100,
+            trail: VecDeque::new(),
             offset: Default::default(),
         }
     }
@@ -70,357 +530,9800 @@ impl Pendulum {
         to_rectangular(self.length, self.a)
     }
 
-    fn set_control(&mut self, value: f32) {
-        self.control = value.clamp(-1.0, 1.0);
+    /// Remembers where the bob is, forgetting positions past `trail_length`
+    fn record_trail(&mut self) {
+        let (x, y) = self.to_rectangular();
+        self.trail.push_back(Vec2::new(x, y));
+        while self.trail.len() > self.trail_length {
+            self.trail.pop_front();
+        }
+    }
+
+    fn accumulate_effort(&mut self, dt: f32) {
+        self.effort += self.control * self.control * dt;
+    }
+
+    /// Energy of the pendulum balanced upright at rest, see `energy`
+    fn upright_energy(&self) -> f32 {
+        2.0 * self.gravity * self.length
+    }
+
+    /// Applies the dead zone and saturation, then slews from the previous control by at most
+    /// `max_control_rate * dt`
+    fn set_control(&mut self, value: f32, dt: f32) {
+        let target = if value.abs() < self.dead_zone {
+            0.0
+        } else {
+            value.clamp(self.control_min, self.control_max)
+        };
+
+        let max_step = self.max_control_rate.max(0.0) * dt;
+        self.control = target.clamp(self.control - max_step, self.control + max_step);
         // self.control = value;
     }
 
-    fn get_system(&self) -> (A, B) {
-        let dt2 = DT.powf(2.0);
+    /// Whether the controllers update on this physics step, never while control is switched off
+    fn control_due(&self) -> bool {
+        self.control_enabled && self.control_tick == 0
+    }
+
+    /// Drops the control along with anything still queued behind the delay, for while control is
+    /// switched off
+    fn release_control(&mut self) {
+        self.control = 0.0;
+        self.pending_controls.clear();
+        self.applied_control = 0.0;
+    }
+
+    /// Counts a physics step towards the next control update
+    fn tick_control(&mut self) {
+        self.control_tick = (self.control_tick + 1) % self.control_decimation.max(1);
+    }
+
+    /// Time the control is held for
+    fn control_period(&self, dt: f32) -> f32 {
+        dt * self.control_decimation.max(1) as f32
+    }
+
+    /// Moment of inertia of a point mass bob on a massless rod
+    fn inertia(&self) -> f32 {
+        self.mass * self.length * self.length
+    }
+
+    /// Angular acceleration per unit of control
+    fn control_gain(&self) -> f32 {
+        match self.control_mode {
+            ControlMode::Normalized => self.control_power,
+            ControlMode::Torque => 1.0 / self.inertia(),
+        }
+    }
+
+    /// Switches units, rescaling the control and everything limiting it so the pendulum is driven
+    /// the same as before
+    fn set_control_mode(&mut self, mode: ControlMode) {
+        let before = self.control_gain();
+        self.control_mode = mode;
+        let after = self.control_gain();
+        if before == 0.0 || after == 0.0 {
+            return;
+        }
+
+        let scale = before / after;
+        self.control *= scale;
+        self.control_min *= scale;
+        self.control_max *= scale;
+        self.dead_zone *= scale;
+        self.max_control_rate *= scale;
+    }
+
+    fn params(&self) -> PendulumParams {
+        PendulumParams {
+            length: self.length,
+            friction: self.friction,
+            coulomb_friction: self.coulomb_friction,
+            gravity: self.gravity,
+            control_power: self.control_gain(),
+        }
+    }
+
+    /// Parameters the controllers design against
+    fn model(&self) -> PendulumParams {
+        self.model.unwrap_or_else(|| self.params())
+    }
+
+    /// Discretized model linearized at rest at `angle`, built from `model` rather than the
+    /// simulated parameters
+    fn get_system(&self, angle: f32, dt: f32) -> (A, B) {
+        let model = self.model();
+        let dt2 = dt.powf(2.0);
+
+        // d(dda)/da of the dynamics, gravity / length at the top and its negative at the bottom
+        let stiffness = -model.gravity * angle.cos() / model.length;
 
         let a = Matrix2::<f32>::new(
-            1.0 + G / (2.0 * self.length) * dt2,
-            DT - self.friction / 2.0 * dt2,
-            G / self.length * DT,
-            1.0 - self.friction * DT,
+            1.0 + stiffness / 2.0 * dt2,
+            dt - model.friction / 2.0 * dt2,
+            stiffness * dt,
+            1.0 - model.friction * dt,
         );
 
-        let b = Matrix2x1::new(self.control_power / 2.0 * dt2, self.control_power * DT);
+        let b = Matrix2x1::new(model.control_power / 2.0 * dt2, model.control_power * dt);
 
         (a, b)
     }
-}
 
-fn to_rectangular(length: f32, angle: f32) -> (f32, f32) {
-    let x = length * angle.sin();
-    let y = -length * angle.cos();
-    (x, y)
-}
-
-#[derive(Component, Default)]
-struct PID {
-    set_point: f32,
-    proportional_gain: f32,
-    integral_gain: f32,
-    derivative_gain: f32,
-    accumulator: f32,
-    accumulator_enabled: bool,
-    error_history: Vec<f32>,
-    accumulator_history: Vec<f32>,
-}
+    /// `get_system` or its zero-order hold counterpart, depending on `method`
+    fn get_discrete_system(&self, angle: f32, dt: f32, method: DiscretizationMethod) -> (A, B) {
+        match method {
+            DiscretizationMethod::Euler => self.get_system(angle, dt),
+            DiscretizationMethod::ZeroOrderHold => {
+                zero_order_hold(self.get_continuous_system(angle), dt)
+            }
+        }
+    }
 
-type A = Matrix<f32, Const<2>, Const<2>, ArrayStorage<f32, 2, 2>>;
-type B = Matrix<f32, Const<2>, Const<1>, ArrayStorage<f32, 2, 1>>;
-type Q = Matrix<f32, Const<2>, Const<2>, ArrayStorage<f32, 2, 2>>;
-type R = Matrix<f32, Const<1>, Const<1>, ArrayStorage<f32, 1, 1>>;
+    /// `get_discrete_system` with the integral of the angle error as a third state, summed over
+    /// steps of `dt`
+    fn get_augmented_system(
+        &self,
+        angle: f32,
+        dt: f32,
+        method: DiscretizationMethod,
+    ) -> (A<3>, B<3>) {
+        let (a, b) = self.get_discrete_system(angle, dt, method);
 
-#[derive(Component)]
-struct LQR {
-    set_point: f32,
-    a: A,
-    b: B,
-    q: Q,
-    r: R,
-}
+        let a = A::<3>::new(
+            a[(0, 0)],
+            a[(0, 1)],
+            0.0,
+            a[(1, 0)],
+            a[(1, 1)],
+            0.0,
+            dt,
+            0.0,
+            1.0,
+        );
+        let b = B::<3>::new(b[0], b[1], 0.0);
 
-impl LQR {
-    fn set_gains(&mut self, pos_cost: f32, vel_cost: f32, power_cost: f32) {
-        self.q = Q::new(pos_cost, 0.0, 0.0, vel_cost);
-        self.r = R::new(power_cost);
+        (a, b)
     }
-}
 
-const DT: f32 = 0.05;
-const G: f32 = 9.8;
+    /// Jacobian of `derivative` at rest at `angle`, built from `model`
+    ///
+    /// Unlike `get_system` this is the continuous model and is only valid near the angle it was
+    /// taken at, so it should be linearized about the controller's set point rather than always
+    /// the top
+    fn get_continuous_system(&self, angle: f32) -> (A, B) {
+        let model = self.model();
 
-fn add_pendulum(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
-    let mut camera_bundle = Camera2dBundle::default();
-    camera_bundle.projection.scale *= 0.1;
-    commands.spawn(camera_bundle);
+        let a = Matrix2::<f32>::new(
+            0.0,
+            1.0,
+            -model.gravity * angle.cos() / model.length,
+            -model.friction,
+        );
+        let b = Matrix2x1::new(0.0, model.control_power);
 
-    commands.spawn((
-        Pendulum::from_offset(-7.0, 0.0),
-        PID {
-            set_point: PI,
-            // set_point: 4.3,
-            proportional_gain: -8.0,
-            integral_gain: -5.5,
-            derivative_gain: -4.0,
-            ..default()
-        },
-        MaterialMesh2dBundle {
-            mesh: meshes.add(shape::Circle::new(1.).into()).into(),
-            material: materials.add(ColorMaterial::from(Color::WHITE)),
-            transform: Transform::default(),
-            ..default()
-        },
-    ));
+        (a, b)
+    }
 
-    let p = Pendulum::from_offset(7.0, 0.0);
+    /// Queues the control and takes the one set `control_delay_steps` steps ago, the last applied
+    /// input is held while the queue fills up
+    fn delay_control(&mut self) -> f32 {
+        self.pending_controls.push_back(self.control);
+        let delay = self.control_delay_steps as usize;
+        while self.pending_controls.len() > delay + 1 {
+            self.pending_controls.pop_front();
+        }
+        if self.pending_controls.len() > delay {
+            self.applied_control = self.pending_controls.pop_front().unwrap();
+        }
+        self.applied_control
+    }
 
-    let (a, b) = p.get_system();
-    let q = Matrix2::identity();
-    let r = Matrix1::identity();
+    /// Steps with the delayed control, the way the physics stage moves the pendulum
+    fn step_delayed(&mut self, integrator: IntegratorKind, dt: f32) {
+        let control = self.delay_control();
+        let substeps = self.physics_substeps.max(1);
+        for _ in 0..substeps {
+            self.integrate(integrator, dt / substeps as f32, control);
+        }
+    }
 
-    commands.spawn((
-        p,
-        LQR {
-            set_point: PI,
-            a,
-            b,
-            q,
-            r,
-        },
-        MaterialMesh2dBundle {
-            mesh: meshes.add(shape::Circle::new(1.).into()).into(),
-            material: materials.add(ColorMaterial::from(Color::WHITE)),
-            transform: Transform::default(),
-            ..default()
-        },
-    ));
-}
+    fn step(&mut self, integrator: IntegratorKind, dt: f32) {
+        self.integrate(integrator, dt, self.control);
+    }
 
-fn acceleration(pendulum: &Pendulum, gravity: f32) -> f32 {
-    -gravity * pendulum.a.sin() / pendulum.length - pendulum.friction * pendulum.da
-}
+    fn integrate(&mut self, integrator: IntegratorKind, dt: f32, control: f32) {
+        let start = self.a;
+        match integrator {
+            IntegratorKind::Euler => {
+                let (_, dda) = derivative(self, self.a, self.da, control);
+                self.da += dda * dt;
+                self.a += self.da * dt;
+            }
+            IntegratorKind::Rk4 => {
+                let (k1a, k1v) = derivative(self, self.a, self.da, control);
+                let (k2a, k2v) = derivative(
+                    self,
+                    self.a + k1a * dt / 2.0,
+                    self.da + k1v * dt / 2.0,
+                    control,
+                );
+                let (k3a, k3v) = derivative(
+                    self,
+                    self.a + k2a * dt / 2.0,
+                    self.da + k2v * dt / 2.0,
+                    control,
+                );
+                let (k4a, k4v) = derivative(self, self.a + k3a * dt, self.da + k3v * dt, control);
 
-fn move_pendulum(mut query: Query<&mut Pendulum>) {
-    for mut pendulum in query.iter_mut() {
-        pendulum.da +=
-            (acceleration(&pendulum, G) + pendulum.control * pendulum.control_power) * DT;
-        pendulum.a += pendulum.da * DT;
-    }
-}
+                self.a += dt / 6.0 * (k1a + 2.0 * k2a + 2.0 * k3a + k4a);
+                self.da += dt / 6.0 * (k1v + 2.0 * k2v + 2.0 * k3v + k4v);
+            }
+        }
 
-fn draw_pendulum(mut query: Query<(&mut Transform, &Pendulum)>) {
-    for (mut transform, pendulum) in query.iter_mut() {
-        let (x, y) = pendulum.to_rectangular();
-        transform.translation = Vec3::new(x, y, 0.0) + pendulum.offset;
+        self.collide(start, self.a - start);
+        self.a = wrap_angle(self.a);
     }
-}
 
-fn history(mut query: Query<(&mut Pendulum, Option<&mut PID>, Option<&mut LQR>)>) {
-    for (mut pendulum, pid, lqr) in query.iter_mut() {
-        let control = pendulum.control;
-        pendulum.control_history.push(control);
+    /// Stops the pendulum at `min_angle` or `max_angle` if the step from `start` went through
+    /// one. Checking the whole swept `moved` rather than where the step ended means a fast
+    /// pendulum cannot skip over a stop
+    fn collide(&mut self, start: f32, moved: f32) {
+        let hit = if let Some(min) = self.min_angle.filter(|_| moved < 0.0) {
+            let room = wrap_angle(start - wrap_angle(min));
+            (-moved > room).then_some(start - room)
+        } else if let Some(max) = self.max_angle.filter(|_| moved > 0.0) {
+            let room = wrap_angle(wrap_angle(max) - start);
+            (moved > room).then_some(start + room)
+        } else {
+            None
+        };
 
-        if let Some(mut pid) = pid {
-            let error = pid.set_point - pendulum.a;
-            pid.error_history.push(error);
-            let acc = pid.accumulator;
-            pid.accumulator_history.push(acc);
+        if let Some(stop) = hit {
+            self.a = stop;
+            self.da *= -self.restitution;
         }
     }
 }
 
-#[allow(dead_code)]
-fn control_pendulum_keyboard(keys: Res<Input<KeyCode>>, mut query: Query<&mut Pendulum>) {
-    let mut input = 0.0;
-    input += if keys.pressed(KeyCode::Left) {
-        -1.0
-    } else {
+/// Wraps an angle into [0, 2π)
+fn wrap_angle(angle: f32) -> f32 {
+    let wrapped = angle.rem_euclid(TAU);
+    // rem_euclid can round up to exactly TAU for tiny negative inputs
+    if wrapped >= TAU {
         0.0
-    };
-    input += if keys.pressed(KeyCode::Right) {
-        1.0
     } else {
-        0.0
-    };
-
-    for mut pendulum in query.iter_mut() {
-        pendulum.set_control(input)
+        wrapped
     }
 }
 
-#[allow(dead_code)]
-fn control_pendulum_mouse(
-    buttons: Res<Input<MouseButton>>,
-    mut motion_evr: EventReader<MouseMotion>,
-    mut query: Query<&mut Pendulum>,
-) {
-    let mut acc = 0.0;
-    for ev in motion_evr.iter() {
-        acc += ev.delta.x;
-    }
+/// Shortest signed angular distance from `to` to `from`, in [-π, π)
+fn angle_difference(from: f32, to: f32) -> f32 {
+    wrap_angle(from - to + PI) - PI
+}
 
-    let input = acc / 5.0;
+fn to_rectangular(length: f32, angle: f32) -> (f32, f32) {
+    let x = length * angle.sin();
+    let y = -length * angle.cos();
+    (x, y)
+}
 
-    if !buttons.pressed(MouseButton::Left) {
-        return;
-    };
+const DEFAULT_HISTORY_CAPACITY: usize = 2000;
 
-    for mut pendulum in query.iter_mut() {
-        pendulum.set_control(input)
-    }
+/// Bounded sample buffer that drops the oldest samples, remembering how many were dropped so the
+/// time of each remaining sample stays correct
+#[derive(Debug, Clone)]
+struct History {
+    samples: VecDeque<f32>,
+    max_len: usize,
+    dropped: usize,
 }
 
-fn control_pendulum_pid(mut query: Query<(&mut Pendulum, &mut PID)>) {
-    for (mut pendulum, mut pid) in query.iter_mut() {
-        // proportional
-        let error = pendulum.a - pid.set_point;
-        let prop = error * pid.proportional_gain;
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            max_len: DEFAULT_HISTORY_CAPACITY,
+            dropped: 0,
+        }
+    }
+}
 
-        // derivative
-        let der = pendulum.da * pid.derivative_gain;
+impl History {
+    fn push(&mut self, value: f32) {
+        self.samples.push_back(value);
+        self.trim();
+    }
 
-        let control = prop + der;
+    fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+        self.trim();
+    }
 
-        // integral
-        if error.abs() < 0.05 {
-            pid.accumulator_enabled = true;
+    fn trim(&mut self) {
+        while self.samples.len() > self.max_len {
+            self.samples.pop_front();
+            self.dropped += 1;
         }
+    }
 
-        if pid.accumulator_enabled {
-            pid.accumulator += error * pid.integral_gain * DT;
-            // pid.accumulator = pid.accumulator.clamp(-1.0, 1.0);
-            pid.accumulator = pid
-                .accumulator
-                .clamp((-1.0 - control).min(0.0), (1.0 - control).max(0.0));
-        }
+    fn clear(&mut self) {
+        self.samples.clear();
+        self.dropped = 0;
+    }
+
+    /// Sample index of the oldest retained sample
+    fn start(&self) -> usize {
+        self.dropped
+    }
+
+    /// One past the sample index of the newest sample
+    fn end(&self) -> usize {
+        self.dropped + self.samples.len()
+    }
+
+    /// Looks up a sample by its index since the last clear
+    fn get(&self, index: usize) -> Option<f32> {
+        index
+            .checked_sub(self.dropped)
+            .and_then(|i| self.samples.get(i))
+            .copied()
+    }
 
-        let control = prop + pid.accumulator + der;
+    /// Iterates retained samples along with their index since the last clear
+    fn iter(&self) -> impl Iterator<Item = (usize, f32)> + '_ {
+        self.samples
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i + self.dropped, *v))
+    }
+}
 
-        pendulum.set_control(control);
+/// Number of samples kept in each history buffer
+#[derive(Resource)]
+struct HistoryCapacity(usize);
+
+impl Default for HistoryCapacity {
+    fn default() -> Self {
+        Self(DEFAULT_HISTORY_CAPACITY)
     }
 }
 
-fn control_pendulum_lqr(mut query: Query<(&mut Pendulum, &mut LQR)>) {
-    for (mut pendulum, lqr) in query.iter_mut() {
-        let mut controller = LQRController::new().unwrap();
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+enum IntegratorKind {
+    /// Semi-implicit Euler, what the simulation has always stepped with
+    #[default]
+    Euler,
+    /// Fourth-order Runge-Kutta, more accurate at larger steps
+    Rk4,
+}
 
-        let k: Matrix1x2<_> = controller
-            .compute_gain(&lqr.a, &lqr.b, &lqr.q, &lqr.r, 1e-7)
-            .unwrap();
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::upper_case_acronyms)]
+struct PID {
+    mode: PidMode,
+    set_point: f32,
+    proportional_gain: f32,
+    integral_gain: f32,
+    derivative_gain: f32,
+    /// Feedback on the angular acceleration, which comes from the model at the measured state so
+    /// noise does not get differentiated twice
+    acceleration_gain: f32,
+    /// Switch the terms off without losing their gains, a disabled integral also empties the
+    /// accumulator
+    p_enabled: bool,
+    i_enabled: bool,
+    d_enabled: bool,
+    a_enabled: bool,
+    /// Proportional, integral, derivative and acceleration gains put aside with "Set baseline"
+    baseline: Option<[f32; 4]>,
+    accumulator: f32,
+    accumulator_enabled: bool,
+    anti_windup: AntiWindup,
+    /// Error the clamp waits for before it starts integrating
+    windup_threshold: f32,
+    /// Back-calculation gain Kt, feeds the amount the output got saturated back into the accumulator
+    tracking_gain: f32,
+    feedforward: Feedforward,
+    /// Time constant of the first-order low-pass on the derivative term, 0 disables filtering
+    derivative_filter_tau: f32,
+    filtered_derivative: f32,
+    form: PidForm,
+    /// Angular velocity held in rate mode, by proportional-integral action on the velocity error
+    da_set_point: f32,
+    rate_proportional_gain: f32,
+    rate_integral_gain: f32,
+    #[serde(skip)]
+    rate_accumulator: f32,
+    /// Error, derivative and acceleration terms and effective proportional gain of the last step,
+    /// differenced by the incremental form
+    #[serde(skip)]
+    previous_error: f32,
+    #[serde(skip)]
+    previous_derivative: f32,
+    #[serde(skip)]
+    previous_acceleration: f32,
+    #[serde(skip)]
+    previous_proportional_gain: f32,
+    /// Saturated output of the last step without feedforward, kept by both forms so switching
+    /// between them does not jump
+    #[serde(skip)]
+    last_output: f32,
+    #[serde(skip)]
+    error_history: History,
+    #[serde(skip)]
+    accumulator_history: History,
+    /// Gains the last `sign_check` ran with and the error ratio it found
+    #[serde(skip)]
+    sign_check: Option<([f32; 4], f32)>,
+    #[serde(skip)]
+    preview: Option<GainPreview>,
+}
 
-        let x = Matrix2x1::new(pendulum.a - lqr.set_point, pendulum.da - 0.0);
+/// Simulated seconds the gain preview looks ahead
+const PREVIEW_DURATION: f32 = 3.0;
 
-        let u = -k * x;
+/// Candidate gains the sliders edit instead of the live ones while previewing, along with the
+/// response they were last simulated to give
+#[derive(Clone)]
+struct GainPreview {
+    gains: [f32; 4],
+    /// Gains `angles` was simulated with, the simulation reruns once they differ from `gains`
+    simulated: Option<[f32; 4]>,
+    /// Angle history index the simulation started from
+    start: usize,
+    /// Angle at the start and after each step of the simulation
+    angles: Vec<f32>,
+}
 
-        pendulum.set_control(*u.index(0));
+impl GainPreview {
+    fn new(gains: [f32; 4]) -> Self {
+        Self {
+            gains,
+            simulated: None,
+            start: 0,
+            angles: Vec::new(),
+        }
     }
 }
 
-fn debug_draw(
-    mut lines: ResMut<DebugLines>,
-    query: Query<(&Pendulum, Option<&PID>, Option<&LQR>)>,
-) {
-    for (pendulum, pid, lqr) in query.iter() {
-        let (x, y) = pendulum.to_rectangular();
-        lines.line(pendulum.offset, Vec3::new(x, y, 0.0) + pendulum.offset, 0.0);
+/// Offset from the set point the sign check starts the sandbox pendulum at
+const SIGN_CHECK_OFFSET: f32 = 0.1;
+/// Simulated seconds the sign check runs the sandbox for
+const SIGN_CHECK_DURATION: f32 = 0.5;
 
-        if let Some(pid) = pid {
-            let (x, y) = to_rectangular(pendulum.length, pid.set_point);
-            lines.line(pendulum.offset, Vec3::new(x, y, 0.0) + pendulum.offset, 0.0);
-        }
+/// How the positional PID keeps the accumulator from winding up while the error is large
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+enum AntiWindup {
+    /// Integrates from the start, the accumulator grows for as long as the output is saturated
+    None,
+    /// Only starts integrating once the error first gets within `windup_threshold`, and holds the
+    /// accumulator at what it takes to saturate the output
+    #[default]
+    Clamp,
+    /// Bleeds the accumulator off by `tracking_gain` while the output is saturated
+    BackCalculation,
+}
 
-        if let Some(lqr) = lqr {
-            let (x, y) = to_rectangular(pendulum.length, lqr.set_point);
-            lines.line(pendulum.offset, Vec3::new(x, y, 0.0) + pendulum.offset, 0.0);
+impl AntiWindup {
+    const ALL: [AntiWindup; 3] = [
+        AntiWindup::None,
+        AntiWindup::Clamp,
+        AntiWindup::BackCalculation,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            AntiWindup::None => "None",
+            AntiWindup::Clamp => "Clamp",
+            AntiWindup::BackCalculation => "Back-calculation",
         }
     }
 }
 
-fn to_points(v: &Vec<f32>) -> PlotPoints {
-    v.iter()
-        .enumerate()
-        .map(|(i, v)| [(i as f64) * (DT as f64), *v as f64])
-        .collect()
+/// What the PID regulates
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+enum PidMode {
+    /// The angle towards `set_point`
+    #[default]
+    Position,
+    /// The angular velocity towards `da_set_point`, the position terms are left alone
+    Rate,
 }
 
-fn ui_example(
-    mut egui_context: ResMut<EguiContext>,
-    mut query: Query<(&mut Pendulum, Option<&mut PID>, Option<&mut LQR>)>,
-) {
-    for (i, (mut pendulum, mut pid, mut lqr)) in query.iter_mut().enumerate() {
-        egui::Window::new("Pendulum settings")
-            .id(Id::new(i))
-            .resizable(true)
-            .default_pos((
-                if i % 2 == 0 { 20.0 } else { 1100.0 },
-                20.0 + 100.0 * (i / 2) as f32,
-            ))
-            .show(egui_context.ctx_mut(), |ui| {
-                ui.label("Pendulum");
-                ui.add(egui::Slider::new(&mut pendulum.length, 0.0..=20.0).text("length"));
-                ui.add(
-                    egui::Slider::new(&mut pendulum.control_power, 0.0..=20.0)
-                        .text("Control power"),
-                );
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug, Serialize, Deserialize)]
+enum PidForm {
+    /// Computes the whole output from the error, its integral and its derivative
+    #[default]
+    Positional,
+    /// Adds the change in output to the last saturated output, so the integral never winds up
+    /// past the limits
+    Incremental,
+}
 
-                ui.add(egui::Slider::new(&mut pendulum.a, 0.0..=2.0 * PI).text("Angle"));
-                ui.add(egui::Slider::new(&mut pendulum.da, -10.0..=10.0).text("Speed"));
+/// Runs `pid` on `pendulum` for `steps` physics steps the way the schedule does, measuring
+/// without noise, and gives the angle after each step
+fn simulate_pid(
+    pid: &mut PID,
+    pendulum: &mut Pendulum,
+    integrator: IntegratorKind,
+    dt: f32,
+    steps: usize,
+) -> Vec<f32> {
+    let mut angles = Vec::with_capacity(steps);
+    for _ in 0..steps {
+        pendulum.measured_a = pendulum.a;
+        pendulum.measured_da = pendulum.da;
+        let control = pid.control(pendulum, dt);
+        pendulum.set_control(control, dt);
+        pendulum.step_delayed(integrator, dt);
+        angles.push(pendulum.a);
+    }
+    angles
+}
+
+impl Default for PID {
+    fn default() -> Self {
+        Self {
+            mode: PidMode::Position,
+            set_point: 0.0,
+            proportional_gain: 0.0,
+            integral_gain: 0.0,
+            derivative_gain: 0.0,
+            acceleration_gain: 0.0,
+            p_enabled: true,
+            i_enabled: true,
+            d_enabled: true,
+            a_enabled: true,
+            baseline: None,
+            accumulator: 0.0,
+            accumulator_enabled: false,
+            anti_windup: AntiWindup::Clamp,
+            windup_threshold: 0.05,
+            tracking_gain: 5.0,
+            feedforward: Default::default(),
+            derivative_filter_tau: 0.0,
+            filtered_derivative: 0.0,
+            form: PidForm::Positional,
+            da_set_point: 1.0,
+            rate_proportional_gain: -1.0,
+            rate_integral_gain: -2.0,
+            rate_accumulator: 0.0,
+            previous_error: 0.0,
+            previous_derivative: 0.0,
+            previous_acceleration: 0.0,
+            previous_proportional_gain: 0.0,
+            last_output: 0.0,
+            error_history: Default::default(),
+            accumulator_history: Default::default(),
+            sign_check: None,
+            preview: None,
+        }
+    }
+}
+
+impl PID {
+    /// Gains that hold the default pendulum upright
+    fn balancing() -> Self {
+        PID {
+            set_point: PI,
+            proportional_gain: -8.0,
+            integral_gain: -5.5,
+            derivative_gain: -4.0,
+            ..default()
+        }
+    }
+
+    fn set_baseline(&mut self) {
+        self.baseline = Some(self.gains());
+    }
+
+    fn restore_baseline(&mut self) {
+        if let Some(gains) = self.baseline {
+            self.set_gains(gains);
+        }
+    }
+
+    /// Proportional, integral, derivative and acceleration gains in the order of `gains`
+    fn set_gains(&mut self, [p, i, d, a]: [f32; 4]) {
+        self.proportional_gain = p;
+        self.integral_gain = i;
+        self.derivative_gain = d;
+        self.acceleration_gain = a;
+    }
+
+    /// Angles over `PREVIEW_DURATION` from the pendulum's current state with `gains`, leaving
+    /// this controller and the pendulum untouched
+    fn predict(
+        &self,
+        gains: [f32; 4],
+        pendulum: &Pendulum,
+        integrator: IntegratorKind,
+        dt: f32,
+    ) -> Vec<f32> {
+        let mut pid = PID {
+            error_history: Default::default(),
+            accumulator_history: Default::default(),
+            preview: None,
+            ..self.clone()
+        };
+        pid.set_gains(gains);
+        let mut sandbox = pendulum.clone();
+
+        let steps = (PREVIEW_DURATION / dt).ceil() as usize;
+        let mut angles = vec![sandbox.a];
+        angles.extend(simulate_pid(&mut pid, &mut sandbox, integrator, dt, steps));
+        angles
+    }
+
+    /// Empties everything integrated so far, the accumulator also waits for the error to come
+    /// close again before it restarts
+    fn clear_integral(&mut self) {
+        self.accumulator = 0.0;
+        self.accumulator_enabled = false;
+        self.rate_accumulator = 0.0;
+        self.last_output = 0.0;
+    }
+
+    fn gains(&self) -> [f32; 4] {
+        [
+            self.proportional_gain,
+            self.integral_gain,
+            self.derivative_gain,
+            self.acceleration_gain,
+        ]
+    }
+
+    /// Error left after `SIGN_CHECK_DURATION` with this controller over the error left without
+    /// any control, starting `SIGN_CHECK_OFFSET` off the set point. Above 1 the control pushes
+    /// away from the set point, which usually means the gains have the wrong sign
+    fn sign_check(&self, pendulum: &Pendulum, integrator: IntegratorKind, dt: f32) -> f32 {
+        let remaining_error = |mut pid: PID| {
+            let mut sandbox = Pendulum {
+                a: wrap_angle(self.set_point + SIGN_CHECK_OFFSET),
+                da: 0.0,
+                control: 0.0,
+                ..pendulum.clone()
+            };
+            sandbox.pending_controls.clear();
+
+            let steps = (SIGN_CHECK_DURATION / dt).ceil() as usize;
+            simulate_pid(&mut pid, &mut sandbox, integrator, dt, steps);
+            angle_difference(sandbox.a, self.set_point).abs()
+        };
+
+        let controlled = PID {
+            accumulator: 0.0,
+            accumulator_enabled: false,
+            filtered_derivative: 0.0,
+            previous_error: 0.0,
+            previous_derivative: 0.0,
+            previous_acceleration: 0.0,
+            last_output: 0.0,
+            error_history: Default::default(),
+            accumulator_history: Default::default(),
+            ..self.clone()
+        };
+        // No gains and no feedforward, so it never pushes at all
+        let uncontrolled = PID {
+            feedforward: Feedforward {
+                enabled: false,
+                ..default()
+            },
+            ..default()
+        };
+        remaining_error(controlled) / remaining_error(uncontrolled).max(f32::EPSILON)
+    }
+
+    fn filter_derivative(&mut self, derivative: f32, dt: f32) -> f32 {
+        let alpha = dt / (self.derivative_filter_tau + dt);
+        self.filtered_derivative += alpha * (derivative - self.filtered_derivative);
+        self.filtered_derivative
+    }
+
+    /// Proportional-integral control of the measured velocity, with the same back-calculation
+    /// anti-windup as the positional form
+    fn rate_control(&mut self, pendulum: &Pendulum, dt: f32) -> f32 {
+        let error = pendulum.measured_da - self.da_set_point;
+        let control = error * self.rate_proportional_gain + self.rate_accumulator;
+
+        let saturated = control.clamp(pendulum.control_min, pendulum.control_max);
+        self.rate_accumulator +=
+            (error * self.rate_integral_gain + (saturated - control) * self.tracking_gain) * dt;
+        control
+    }
+
+    /// Control output for the pendulum's measured state, also advances the integral term
+    fn control(&mut self, pendulum: &Pendulum, dt: f32) -> f32 {
+        if self.mode == PidMode::Rate {
+            return self.rate_control(pendulum, dt);
+        }
+
+        let gain = |enabled: bool, gain: f32| if enabled { gain } else { 0.0 };
+
+        // proportional
+        let error = angle_difference(pendulum.measured_a, self.set_point);
+        let proportional_gain = gain(self.p_enabled, self.proportional_gain);
+        let prop = error * proportional_gain;
+
+        // derivative, the filter keeps running so re-enabling does not kick
+        let der = self.filter_derivative(pendulum.measured_da, dt)
+            * gain(self.d_enabled, self.derivative_gain);
+
+        // acceleration, with the input applied over the last step since this one's is not known yet
+        let (_, dda) = derivative(
+            pendulum,
+            pendulum.measured_a,
+            pendulum.measured_da,
+            pendulum.applied_control,
+        );
+        let acc = dda * gain(self.a_enabled, self.acceleration_gain);
 
-                ui.label(format!("{}", pendulum.control));
+        // integral
+        if self.anti_windup != AntiWindup::Clamp || error.abs() < self.windup_threshold {
+            self.accumulator_enabled = true;
+        }
+        if !self.i_enabled {
+            self.accumulator = 0.0;
+        }
+
+        let feedforward = self.feedforward.control(pendulum, self.set_point);
+        let (low, high) = (
+            pendulum.control_min - feedforward,
+            pendulum.control_max - feedforward,
+        );
+
+        let control = match self.form {
+            PidForm::Positional => {
+                let control = prop + self.accumulator + der + acc + feedforward;
 
-                if ui.button("Reset").clicked() {
-                    let template = Pendulum::default();
-                    pendulum.a = template.a;
-                    pendulum.da = template.da;
-                    pendulum.control_history = Vec::new();
-                    if let Some(pid) = &mut pid {
-                        pid.accumulator = 0.0;
-                        pid.accumulator_enabled = false;
-                        pid.error_history = Vec::new();
-                        pid.accumulator_history = Vec::new();
+                if self.accumulator_enabled && self.i_enabled {
+                    let tracking = if self.anti_windup == AntiWindup::BackCalculation {
+                        let saturated = control.clamp(pendulum.control_min, pendulum.control_max);
+                        (saturated - control) * self.tracking_gain
+                    } else {
+                        0.0
+                    };
+                    self.accumulator += (error * self.integral_gain + tracking) * dt;
+                    if self.anti_windup == AntiWindup::Clamp {
+                        let rest = prop + der + acc;
+                        self.accumulator = self
+                            .accumulator
+                            .clamp((low - rest).min(0.0), (high - rest).max(0.0));
                     }
                 }
 
-                let slider_range = 10.0;
+                self.last_output = (control - feedforward).clamp(low, high);
+                control
+            }
+            PidForm::Incremental => {
+                // The integral lags a step like the positional accumulator so both forms agree
+                let integral = |error: f32| {
+                    if self.accumulator_enabled && self.i_enabled {
+                        error * self.integral_gain * dt
+                    } else {
+                        0.0
+                    }
+                };
+                // A gain that changed, or got switched off, applies to the whole error at once
+                let delta = angle_difference(error, self.previous_error) * proportional_gain
+                    + (proportional_gain - self.previous_proportional_gain) * self.previous_error
+                    + integral(self.previous_error)
+                    + (der - self.previous_derivative)
+                    + (acc - self.previous_acceleration);
+                // Without the integral nothing carries over from the last output
+                let output = if self.i_enabled {
+                    (self.last_output + delta).clamp(low, high)
+                } else {
+                    (prop + der + acc).clamp(low, high)
+                };
 
-                let mut lines = Vec::new();
-                let control_points: PlotPoints = to_points(&pendulum.control_history);
-                lines.push(Line::new(control_points).name("Control"));
+                // What the positional accumulator would hold, for switching back
+                if self.i_enabled {
+                    self.accumulator = output - prop - der - acc + integral(error);
+                }
+                self.last_output = output;
+                output + feedforward
+            }
+        };
 
-                if let Some(mut pid) = pid {
-                    ui.separator();
-                    ui.label("PID");
-                    ui.label(format!("Error: {}", pendulum.a - pid.set_point));
-                    // TODO: There's probably a better way to do this
-                    let old_set_point = pid.set_point;
-                    ui.add(egui::Slider::new(&mut pid.set_point, 0.0..=2.0 * PI).text("Set point"));
-                    if pid.set_point != old_set_point {
-                        pid.accumulator = 0.0;
-                        pid.accumulator_enabled = false;
-                    }
-                    ui.add(
-                        egui::Slider::new(&mut pid.proportional_gain, -slider_range..=slider_range)
-                            .text("Proportional gain"),
-                    );
-                    ui.add(
-                        egui::Slider::new(&mut pid.integral_gain, -slider_range..=slider_range)
-                            .text("Integral gain"),
-                    );
-                    ui.add(
-                        egui::Slider::new(&mut pid.derivative_gain, -slider_range..=slider_range)
-                            .text("Derivative gain"),
-                    );
+        self.previous_error = error;
+        self.previous_derivative = der;
+        self.previous_acceleration = acc;
+        self.previous_proportional_gain = proportional_gain;
+        control
+    }
+}
+
+/// Model based input that holds the pendulum still at the set point against gravity
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Feedforward {
+    enabled: bool,
+    gain: f32,
+    /// Also cancels the model's friction at the measured velocity, on its own switch
+    friction: bool,
+}
+
+impl Default for Feedforward {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            gain: 1.0,
+            friction: false,
+        }
+    }
+}
+
+impl Feedforward {
+    fn control(&self, pendulum: &Pendulum, set_point: f32) -> f32 {
+        let model = pendulum.model();
+        if model.control_power == 0.0 {
+            return 0.0;
+        }
+
+        // Cancels the -gravity sin(a) / length term of the dynamics at a = set_point
+        let gravity = if self.enabled {
+            self.gain * model.gravity * set_point.sin() / model.length
+        } else {
+            0.0
+        };
+        let friction = if self.friction {
+            friction_deceleration(model.friction, model.coulomb_friction, pendulum.measured_da)
+        } else {
+            0.0
+        };
+        (gravity + friction) / model.control_power
+    }
+}
+
+type A<const N: usize = 2> = Matrix<f32, Const<N>, Const<N>, ArrayStorage<f32, N, N>>;
+type B<const N: usize = 2> = Matrix<f32, Const<N>, Const<1>, ArrayStorage<f32, N, 1>>;
+type Q<const N: usize = 2> = Matrix<f32, Const<N>, Const<N>, ArrayStorage<f32, N, N>>;
+type R = Matrix<f32, Const<1>, Const<1>, ArrayStorage<f32, 1, 1>>;
+type K<const N: usize = 2> = Matrix<f32, Const<1>, Const<N>, ArrayStorage<f32, 1, N>>;
+
+/// LQR state feedback over an `N` dimensional linear model, two states (angle, velocity) unless
+/// the plant says otherwise
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+struct LQR<const N: usize = 2> {
+    set_point: f32,
+    a: A<N>,
+    b: B<N>,
+    q: Q<N>,
+    r: R,
+    #[serde(default)]
+    feedforward: Feedforward,
+    /// `a` and `b` are the continuous time model and the gain comes from the continuous ARE
+    #[serde(default)]
+    continuous: bool,
+    /// Costs put aside with "Set baseline"
+    #[serde(default)]
+    baseline: Option<(Q<N>, R)>,
+    /// Relative change of the Riccati solution between iterations below which it counts as
+    /// converged
+    #[serde(default = "default_riccati_tolerance")]
+    tolerance: f32,
+    #[serde(default = "default_riccati_iterations")]
+    max_iterations: usize,
+    #[serde(default)]
+    solver: RiccatiSolver,
+    /// Used by `update_model` for the discrete model
+    #[serde(default)]
+    discretization: DiscretizationMethod,
+    /// How the last discrete solve went, `None` for the continuous model
+    #[serde(skip)]
+    riccati: Option<RiccatiStats>,
+    /// Set point the model was last rebuilt for, `None` until `update_model` runs
+    #[serde(skip)]
+    linearized_at: Option<f32>,
+    #[serde(skip)]
+    error_history: History,
+    #[serde(skip, default = "zero_gain")]
+    k: K<N>,
+    #[serde(skip, default = "needs_solve")]
+    dirty: bool,
+    /// Position, velocity and power costs picked by the last `auto_tune`
+    #[serde(skip)]
+    tuned_costs: Option<[f32; 3]>,
+    /// Why no gain could be computed for the current model
+    #[serde(skip)]
+    error: Option<&'static str>,
+    /// Feed back the integral of the angle error too, which removes the steady state error the
+    /// feedforward otherwise has to cancel
+    #[serde(default)]
+    integral_action: bool,
+    /// Costs on the angle error, velocity and integral of the angle error, the first two follow `q`
+    #[serde(default = "default_integral_q")]
+    integral_q: Q<3>,
+    /// Discrete model augmented with the integral state, `None` for the continuous model
+    #[serde(skip)]
+    augmented: Option<(A<3>, B<3>)>,
+    /// Integral of the angle error over the control periods since the set point last moved
+    #[serde(skip)]
+    integral: f32,
+    /// Set point `integral` was gathered about
+    #[serde(skip)]
+    integral_set_point: Option<f32>,
+    #[serde(skip, default = "zero_gain")]
+    integral_k: K<3>,
+    /// Syntax the model matrices are shown in, `None` while they are hidden
+    #[serde(skip)]
+    matrix_export: Option<MatrixFormat>,
+}
+
+/// Syntax for copying matrices into other tools
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+enum MatrixFormat {
+    #[default]
+    Matlab,
+    NumPy,
+}
+
+impl MatrixFormat {
+    fn name(self) -> &'static str {
+        match self {
+            MatrixFormat::Matlab => "MATLAB",
+            MatrixFormat::NumPy => "NumPy",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            MatrixFormat::Matlab => "m",
+            MatrixFormat::NumPy => "py",
+        }
+    }
+
+    fn comment(self) -> &'static str {
+        match self {
+            MatrixFormat::Matlab => "%",
+            MatrixFormat::NumPy => "#",
+        }
+    }
+}
+
+/// `name = ...` assigning the matrix row by row, full precision so nothing is lost in the copy
+fn format_matrix(name: &str, matrix: &DMatrix<f32>, format: MatrixFormat) -> String {
+    let rows: Vec<Vec<String>> = matrix
+        .row_iter()
+        .map(|row| row.iter().map(|v| format!("{:e}", v)).collect())
+        .collect();
+    match format {
+        MatrixFormat::Matlab => {
+            let rows: Vec<String> = rows.iter().map(|row| row.join(" ")).collect();
+            format!("{} = [{}];", name, rows.join(";\n     "))
+        }
+        MatrixFormat::NumPy => {
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|row| format!("[{}]", row.join(", ")))
+                .collect();
+            format!("{} = np.array([{}])", name, rows.join(",\n              "))
+        }
+    }
+}
+
+fn zero_gain<const N: usize>() -> K<N> {
+    K::zeros()
+}
+
+fn default_integral_q() -> Q<3> {
+    Q::identity()
+}
+
+fn needs_solve() -> bool {
+    true
+}
 
-                    let error_points: PlotPoints = to_points(&pid.error_history);
-                    let accumulator_points: PlotPoints = to_points(&pid.accumulator_history);
+fn default_riccati_tolerance() -> f32 {
+    1e-7
+}
+
+fn default_riccati_iterations() -> usize {
+    RiccatiSolver::default().default_iterations()
+}
+
+/// How the continuous model becomes the discrete one the gain is computed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum DiscretizationMethod {
+    /// `get_system`, one step at constant acceleration
+    #[default]
+    Euler,
+    /// Exact for a control held over the step, from the matrix exponential
+    ZeroOrderHold,
+}
+
+/// Exact discretization of `(a, b)` for an input held constant over `dt`, the top rows of
+/// exp([[A, B], [0, 0]] dt) are [A_d, B_d]
+fn zero_order_hold((a, b): (A, B), dt: f32) -> (A, B) {
+    let mut m = Matrix3::<f32>::zeros();
+    m.fixed_slice_mut::<2, 2>(0, 0).copy_from(&a);
+    m.fixed_slice_mut::<2, 1>(0, 2).copy_from(&b);
+    let exp = (m * dt).exp();
+
+    (
+        exp.fixed_slice::<2, 2>(0, 0).into_owned(),
+        exp.fixed_slice::<2, 1>(0, 2).into_owned(),
+    )
+}
+
+/// How the discrete gain gets computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum RiccatiSolver {
+    /// `discrete_riccati`, squaring its way to the solution in a few dozen iterations
+    #[default]
+    Doubling,
+    /// `dlqr`, the plain recursion, for checking the doubling against
+    Recursion,
+}
+
+impl RiccatiSolver {
+    /// Each doubling step squares the convergence factor, the recursion only multiplies by it
+    fn default_iterations(self) -> usize {
+        match self {
+            RiccatiSolver::Doubling => 100,
+            RiccatiSolver::Recursion => 10000,
+        }
+    }
+}
+
+/// Relative Riccati residual above which a solve that stopped changing is still reported
+const RICCATI_RESIDUAL_LIMIT: f32 = 1e-3;
+
+/// Radians the set point has to move from where the model was built before it is rebuilt, so a
+/// ramp or reference signal does not re-solve the Riccati equation on every step
+const RELINEARIZE_TOLERANCE: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RiccatiStats {
+    iterations: usize,
+    converged: bool,
+    /// Relative residual of the discrete algebraic Riccati equation at the solution
+    residual: f32,
+}
+
+/// Solves the discrete algebraic Riccati equation with the structure-preserving doubling
+/// algorithm, stopping after `max_iterations` if the solution is still changing
+fn discrete_riccati<const N: usize>(
+    (a, b): (&A<N>, &B<N>),
+    (q, r): (&Q<N>, &R),
+    tolerance: f32,
+    max_iterations: usize,
+) -> Result<(Q<N>, RiccatiStats), &'static str>
+where
+    Const<N>: DimMin<Const<N>>,
+{
+    let r_inv = r.try_inverse().ok_or("Power cost must be positive")?;
+    let mut a_k = *a;
+    let mut g_k = b * r_inv * b.transpose();
+    let mut h_k = *q;
+
+    let mut iterations = 0;
+    let mut converged = false;
+    while iterations < max_iterations && !converged {
+        let temp = (Q::<N>::identity() + g_k * h_k)
+            .try_inverse()
+            .ok_or("Riccati iteration hit a singular matrix")?;
+        let h_next = h_k + a_k.transpose() * h_k * temp * a_k;
+        g_k += a_k * temp * g_k * a_k.transpose();
+        a_k = a_k * temp * a_k;
+
+        converged = (h_next - h_k).norm() / h_next.norm() < tolerance;
+        h_k = h_next;
+        iterations += 1;
+    }
+
+    let stats = RiccatiStats {
+        iterations,
+        converged,
+        residual: riccati_residual((a, b), (q, r), &h_k),
+    };
+    Ok((h_k, stats))
+}
+
+/// Discrete LQR from iterating the Riccati difference equation
+/// P ← Q + A^T P A - A^T P B (R + B^T P B)^-1 B^T P A starting at P = Q, much slower to converge
+/// than the doubling but simple enough to trust. Returns the gain (R + B^T P B)^-1 B^T P A along
+/// with the solution
+fn dlqr<const N: usize>(
+    (a, b): (&A<N>, &B<N>),
+    (q, r): (&Q<N>, &R),
+    tolerance: f32,
+    max_iterations: usize,
+) -> Result<(K<N>, Q<N>, RiccatiStats), &'static str> {
+    let gain = |p: &Q<N>| discrete_gain((a, b), (q, r), p);
+
+    let mut p = *q;
+    let mut iterations = 0;
+    let mut converged = false;
+    while iterations < max_iterations && !converged {
+        let k = gain(&p)?;
+        let p_next = q + a.transpose() * p * a - a.transpose() * p * b * k;
+        // Keeps rounding from making it drift away from symmetric
+        let p_next = (p_next + p_next.transpose()) / 2.0;
+
+        converged = (p_next - p).norm() / p_next.norm() < tolerance;
+        p = p_next;
+        iterations += 1;
+    }
+
+    let stats = RiccatiStats {
+        iterations,
+        converged,
+        residual: riccati_residual((a, b), (q, r), &p),
+    };
+    Ok((gain(&p)?, p, stats))
+}
 
-                    lines.push(Line::new(error_points).name("Error"));
-                    lines.push(Line::new(accumulator_points).name("Accumulator"));
+/// Gain (R + B^T P B)^-1 B^T P A that minimizes the cost given the Riccati solution `p`
+fn discrete_gain<const N: usize>(
+    (a, b): (&A<N>, &B<N>),
+    (_, r): (&Q<N>, &R),
+    p: &Q<N>,
+) -> Result<K<N>, &'static str> {
+    let inner = (r + b.transpose() * p * b)
+        .try_inverse()
+        .ok_or("Riccati iteration hit a singular matrix")?;
+    Ok(inner * b.transpose() * p * a)
+}
+
+/// Norm of A^T P A - P - A^T P B (R + B^T P B)^-1 B^T P A + Q relative to P
+fn riccati_residual<const N: usize>((a, b): (&A<N>, &B<N>), (q, r): (&Q<N>, &R), p: &Q<N>) -> f32 {
+    let Some(inner) = (r + b.transpose() * p * b).try_inverse() else {
+        return f32::INFINITY;
+    };
+    let residual =
+        a.transpose() * p * a - p + q - a.transpose() * p * b * inner * b.transpose() * p * a;
+    residual.norm() / p.norm()
+}
+
+impl<const N: usize> LQR<N>
+where
+    Const<N>: DimMin<Const<N>>,
+{
+    fn new(set_point: f32, (a, b): (A<N>, B<N>)) -> Self {
+        Self {
+            set_point,
+            a,
+            b,
+            q: Q::identity(),
+            r: R::identity(),
+            feedforward: Default::default(),
+            continuous: false,
+            baseline: None,
+            tolerance: default_riccati_tolerance(),
+            max_iterations: default_riccati_iterations(),
+            solver: RiccatiSolver::default(),
+            discretization: DiscretizationMethod::default(),
+            riccati: None,
+            linearized_at: None,
+            error_history: Default::default(),
+            k: K::zeros(),
+            dirty: true,
+            tuned_costs: None,
+            error: None,
+            integral_action: false,
+            integral_q: default_integral_q(),
+            augmented: None,
+            integral: 0.0,
+            integral_set_point: None,
+            integral_k: K::zeros(),
+            matrix_export: None,
+        }
+    }
+
+    fn set_system(&mut self, (a, b): (A<N>, B<N>)) {
+        self.a = a;
+        self.b = b;
+        self.dirty = true;
+    }
+
+    fn set_baseline(&mut self) {
+        self.baseline = Some((self.q, self.r));
+    }
+
+    fn restore_baseline(&mut self) {
+        if let Some((q, r)) = self.baseline {
+            self.q = q;
+            self.r = r;
+            self.dirty = true;
+        }
+    }
+
+    /// Returns the feedback gain, only solving the Riccati equation again if the model or costs changed
+    fn gain(&mut self) -> Result<K<N>, &'static str> {
+        if self.dirty {
+            match self.solve() {
+                Ok(k) => {
+                    self.k = k;
+                    self.error = None;
                 }
+                Err(err) => {
+                    warn!("LQR gain unavailable: {}", err);
+                    self.error = Some(err);
+                }
+            }
+            self.dirty = false;
+        }
 
-                if let Some(mut lqr) = lqr {
-                    ui.separator();
-                    ui.label("LQR");
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.k),
+        }
+    }
+
+    fn solve(&mut self) -> Result<K<N>, &'static str> {
+        self.riccati = None;
+        // Caught here so it reads as a bad cost rather than a failed iteration
+        if !(self.r[0] > 0.0 && self.r[0].is_finite()) {
+            return Err("Power cost must be positive");
+        }
+        if !(0..N).all(|i| self.q[(i, i)] >= 0.0) || !self.q.iter().all(|q| q.is_finite()) {
+            return Err("State costs must be finite and non-negative");
+        }
+        // Otherwise the iteration still finds a finite gain, it just does nothing useful
+        if controllability_rank((self.a, self.b)) < N {
+            return Err("Model is not controllable");
+        }
 
-                    ui.label(format!("Error: {}", pendulum.a - PI));
+        let k = if self.continuous {
+            continuous_gain(&self.a, &self.b, &self.q, &self.r)
+                .ok_or("Continuous Riccati equation has no stabilizing solution")?
+        } else {
+            let system = (&self.a, &self.b);
+            let costs = (&self.q, &self.r);
+            let (k, stats) = match self.solver {
+                RiccatiSolver::Doubling => {
+                    let (p, stats) =
+                        discrete_riccati(system, costs, self.tolerance, self.max_iterations)?;
+                    (discrete_gain(system, costs, &p)?, stats)
+                }
+                RiccatiSolver::Recursion => {
+                    let (k, _, stats) = dlqr(system, costs, self.tolerance, self.max_iterations)?;
+                    (k, stats)
                 }
+            };
+            self.riccati = Some(stats);
+            if !stats.converged {
+                return Err("Riccati iteration did not converge");
+            }
+            k
+        };
 
-                Plot::new("My Plot")
-                    .legend(Legend::default())
-                    .view_aspect(2.0)
-                    .show(ui, |plot_ui| {
-                        for line in lines {
-                            plot_ui.line(line);
-                        }
-                    });
-            });
+        if !k.iter().all(|k| k.is_finite()) {
+            return Err("Riccati iteration did not converge");
+        }
+        if self.integral_action {
+            self.integral_k = self.solve_integral()?;
+        }
+        Ok(k)
+    }
+
+    /// Gain over the augmented model, solved the same way as the two state one
+    fn solve_integral(&mut self) -> Result<K<3>, &'static str> {
+        let system = self
+            .augmented
+            .ok_or("Integral action needs the discrete model")?;
+        let mut augmented = LQR::<3>::new(self.set_point, system);
+        augmented.q = self.integral_q;
+        augmented.r = self.r;
+        augmented.tolerance = self.tolerance;
+        augmented.max_iterations = self.max_iterations;
+        augmented.solver = self.solver;
+
+        let k = augmented.solve();
+        // The augmented solve is the one the controller uses
+        self.riccati = augmented.riccati;
+        k
+    }
+}
+
+/// Solves the continuous algebraic Riccati equation with the matrix sign function of the
+/// Hamiltonian and returns the gain R^-1 B^T P
+fn continuous_gain<const N: usize>(a: &A<N>, b: &B<N>, q: &Q<N>, r: &R) -> Option<K<N>> {
+    let r_inv = r.try_inverse()?;
+    let s = b * r_inv * b.transpose();
+
+    let mut z = DMatrix::<f32>::zeros(2 * N, 2 * N);
+    z.slice_mut((0, 0), (N, N)).copy_from(a);
+    z.slice_mut((0, N), (N, N)).copy_from(&-s);
+    z.slice_mut((N, 0), (N, N)).copy_from(&-q);
+    z.slice_mut((N, N), (N, N)).copy_from(&-a.transpose());
+
+    // Newton iteration with determinant scaling converges to sign(H)
+    for _ in 0..100 {
+        let inverse = z.clone().try_inverse()?;
+        let scale = z.determinant().abs().powf(1.0 / (2 * N) as f32);
+        let next = (&z / scale + inverse * scale) * 0.5;
+        let change = (&next - &z).norm() / next.norm();
+        z = next;
+        if change < 1e-6 {
+            break;
+        }
+    }
+
+    // P solves [W12; W22 + I] P = -[W11 + I; W21] in the least squares sense
+    let identity = DMatrix::<f32>::identity(N, N);
+    let mut lhs = DMatrix::<f32>::zeros(2 * N, N);
+    lhs.slice_mut((0, 0), (N, N))
+        .copy_from(&z.slice((0, N), (N, N)));
+    lhs.slice_mut((N, 0), (N, N))
+        .copy_from(&(z.slice((N, N), (N, N)) + &identity));
+    let mut rhs = DMatrix::<f32>::zeros(2 * N, N);
+    rhs.slice_mut((0, 0), (N, N))
+        .copy_from(&-(z.slice((0, 0), (N, N)) + &identity));
+    rhs.slice_mut((N, 0), (N, N))
+        .copy_from(&-z.slice((N, 0), (N, N)));
+
+    let p = (lhs.transpose() * &lhs).try_inverse()? * lhs.transpose() * rhs;
+    let k = r_inv * b.transpose() * p;
+
+    k.iter()
+        .all(|k| k.is_finite())
+        .then(|| K::from_iterator(k.iter().copied()))
+}
+
+impl LQR {
+    /// The model, costs and gain the controller currently uses as a script for `format`, headed
+    /// by the parameters the model was built from. With integral action on the discrete model
+    /// these are the augmented ones
+    fn matrices(&self, model: &PendulumParams, dt: f32, format: MatrixFormat) -> String {
+        let comment = format.comment();
+        let kind = if self.continuous {
+            "continuous time".to_string()
+        } else {
+            let method = match self.discretization {
+                DiscretizationMethod::Euler => "Euler",
+                DiscretizationMethod::ZeroOrderHold => "zero-order hold",
+            };
+            format!("discrete time, dt = {} s, {}", dt, method)
+        };
+        let mut lines = vec![
+            format!(
+                "{} Pendulum linearized at {} rad, {}",
+                comment,
+                self.linearized_at.unwrap_or(self.set_point),
+                kind
+            ),
+            format!(
+                "{} length {}, friction {}, gravity {}, control power {}",
+                comment, model.length, model.friction, model.gravity, model.control_power
+            ),
+        ];
+        let augmented = self.augmented.filter(|_| self.integral_action);
+        lines.push(if augmented.is_some() {
+            format!(
+                "{} State is [angle error, angular velocity, angle error integral]",
+                comment
+            )
+        } else {
+            format!("{} State is [angle error, angular velocity]", comment)
+        });
+        if format == MatrixFormat::NumPy {
+            lines.push("import numpy as np".to_string());
+        }
+
+        let dynamic = |m: &[f32], rows: usize| DMatrix::from_column_slice(rows, m.len() / rows, m);
+        let (a, b, q, k) = match &augmented {
+            Some((a, b)) => (
+                dynamic(a.as_slice(), 3),
+                dynamic(b.as_slice(), 3),
+                dynamic(self.integral_q.as_slice(), 3),
+                dynamic(self.integral_k.as_slice(), 1),
+            ),
+            None => (
+                dynamic(self.a.as_slice(), 2),
+                dynamic(self.b.as_slice(), 2),
+                dynamic(self.q.as_slice(), 2),
+                dynamic(self.k.as_slice(), 1),
+            ),
+        };
+        lines.push(format_matrix("A", &a, format));
+        lines.push(format_matrix("B", &b, format));
+        lines.push(format_matrix("Q", &q, format));
+        lines.push(format_matrix("R", &dynamic(self.r.as_slice(), 1), format));
+        if self.error.is_none() && !self.dirty {
+            lines.push(format_matrix("K", &k, format));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Whether the set point moved far enough from the last model to rebuild it
+    fn needs_relinearizing(&self) -> bool {
+        self.linearized_at
+            .is_none_or(|at| angle_difference(self.set_point, at).abs() > RELINEARIZE_TOLERANCE)
+    }
+
+    /// Rebuilds the model from the pendulum, continuous or discrete to match `continuous`
+    fn update_model(&mut self, pendulum: &Pendulum, dt: f32) {
+        let system = if self.continuous {
+            pendulum.get_continuous_system(self.set_point)
+        } else {
+            pendulum.get_discrete_system(self.set_point, dt, self.discretization)
+        };
+        self.set_system(system);
+        self.augmented = (!self.continuous)
+            .then(|| pendulum.get_augmented_system(self.set_point, dt, self.discretization));
+        self.linearized_at = Some(self.set_point);
+    }
+
+    fn set_gains(&mut self, pos_cost: f32, vel_cost: f32, power_cost: f32) {
+        self.set_q(pos_cost, 0.0, vel_cost);
+        self.r = R::new(power_cost);
+    }
+
+    /// Symmetric state cost, `cross_cost` weights the product of the angle and velocity errors
+    fn set_q(&mut self, pos_cost: f32, cross_cost: f32, vel_cost: f32) {
+        self.q = Q::<2>::new(pos_cost, cross_cost, cross_cost, vel_cost);
+        self.integral_q
+            .fixed_slice_mut::<2, 2>(0, 0)
+            .copy_from(&self.q);
+        self.dirty = true;
+    }
+
+    fn set_integral_cost(&mut self, integral_cost: f32) {
+        self.integral_q[(2, 2)] = integral_cost;
+        self.dirty = true;
+    }
+
+    /// Whether no state has a negative cost, the Riccati solution may not exist otherwise
+    fn q_semidefinite(&self) -> bool {
+        let q = &self.q;
+        q[(0, 0)] >= 0.0 && q[(1, 1)] >= 0.0 && q.determinant() >= 0.0
+    }
+
+    /// Settling time plus weighted control effort of a simulated response from `TUNING_OFFSET`
+    /// off the set point, responses that never settle count as taking twice the horizon. Scored
+    /// against the default `SETTLING_BAND` so the tuning does not move with the plotted band
+    fn tuning_cost(&self, pendulum: &Pendulum, dt: f32) -> f32 {
+        let mut lqr = self.clone();
+        lqr.update_model(pendulum, dt);
+        let mut pendulum = Pendulum {
+            a: self.set_point + TUNING_OFFSET,
+            da: 0.0,
+            ..pendulum.clone()
+        };
+
+        let steps = (TUNING_HORIZON / dt) as usize;
+        let mut errors = Vec::with_capacity(steps);
+        let mut effort = 0.0;
+        for _ in 0..steps {
+            let Ok(control) = lqr.control(&pendulum, (pendulum.a, pendulum.da)) else {
+                return f32::INFINITY;
+            };
+            pendulum.set_control(control, dt);
+            effort += pendulum.control * pendulum.control * dt;
+            pendulum.step(IntegratorKind::Rk4, dt);
+            errors.push(angle_difference(lqr.set_point, pendulum.a));
+        }
+
+        let settling_time = step_metrics(&errors, dt, SETTLING_BAND)
+            .and_then(|metrics| metrics.settling_time)
+            .unwrap_or(2.0 * TUNING_HORIZON);
+        settling_time + TUNING_EFFORT_WEIGHT * effort
+    }
+
+    /// Grid search over the costs, applies the combination with the lowest `tuning_cost`
+    fn auto_tune(&mut self, pendulum: &Pendulum, dt: f32) -> [f32; 3] {
+        let mut best = ([1.0, 1.0, 1.0], f32::INFINITY);
+        for pos_cost in TUNING_GRID {
+            for vel_cost in TUNING_GRID {
+                for power_cost in TUNING_GRID {
+                    let mut candidate = self.clone();
+                    candidate.set_gains(pos_cost, vel_cost, power_cost);
+                    let cost = candidate.tuning_cost(pendulum, dt);
+                    if cost < best.1 {
+                        best = ([pos_cost, vel_cost, power_cost], cost);
+                    }
+                }
+            }
+        }
+
+        let [pos_cost, vel_cost, power_cost] = best.0;
+        self.set_gains(pos_cost, vel_cost, power_cost);
+        self.tuned_costs = Some(best.0);
+        best.0
+    }
+
+    /// State feedback for the given estimate of the pendulum's angle and angular velocity, along
+    /// with the integral of the angle error with `integral_action`
+    fn control(&mut self, pendulum: &Pendulum, (a, da): (f32, f32)) -> Result<f32, &'static str> {
+        // What was gathered about the old set point would only kick the new one
+        if self.integral_set_point != Some(self.set_point) {
+            self.integral = 0.0;
+            self.integral_set_point = Some(self.set_point);
+        }
+        let error = angle_difference(a, self.set_point);
+        let k = self.gain()?;
+        let u = if self.integral_action {
+            -self.integral_k * B::<3>::new(error, da, self.integral)
+        } else {
+            -k * Matrix2x1::new(error, da)
+        };
+        Ok(*u.index(0) + self.feedforward.control(pendulum, self.set_point))
+    }
+
+    /// Adds the angle error over one control period to the integral state, unless `control` is
+    /// already saturated and the integral would only push it further past the limit
+    fn integrate(&mut self, pendulum: &Pendulum, a: f32, control: f32, dt: f32) {
+        if !self.integral_action {
+            return;
+        }
+        let step = angle_difference(a, self.set_point) * dt;
+        let push = -self.integral_k[2] * step;
+        let winding_up = (control >= pendulum.control_max && push > 0.0)
+            || (control <= pendulum.control_min && push < 0.0);
+        if !winding_up {
+            self.integral += step;
+        }
+    }
+}
+
+/// Gaussian noise added to the state the controllers measure, the simulation itself stays exact
+#[derive(Component, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct NoiseConfig {
+    angle_stddev: f32,
+    velocity_stddev: f32,
+}
+
+/// Reads the optional `--seed N` of the windowed app, any other arguments are ignored
+fn parse_seed(args: &[String]) -> Result<u64, String> {
+    match args.iter().position(|arg| arg == "--seed") {
+        Some(i) => {
+            let value = args.get(i + 1).ok_or("missing value for --seed")?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid value {} for --seed", value))
+        }
+        None => Ok(0),
+    }
+}
+
+/// Seeded generator shared by everything random so a run can be reproduced from its seed
+#[derive(Resource)]
+struct AppRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Default for AppRng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl AppRng {
+    fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Starts the sequence over from `seed`
+    fn reseed(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    /// Uniform sample in `[min, max)`, `min` when the range is empty
+    fn uniform(&mut self, (min, max): (f32, f32)) -> f32 {
+        if min < max {
+            self.rng.gen_range(min..max)
+        } else {
+            min
+        }
+    }
+
+    /// Normally distributed sample via the Box-Muller transform
+    fn normal(&mut self, stddev: f32) -> f32 {
+        if stddev == 0.0 {
+            return 0.0;
+        }
+
+        let u1: f32 = 1.0 - self.rng.gen::<f32>();
+        let u2: f32 = self.rng.gen();
+        stddev * (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+    }
+}
+
+/// Moves the controller set point toward `target` at no more than `max_rate` rad/s
+#[derive(Component, Clone, Serialize, Deserialize)]
+struct SetpointRamp {
+    target: f32,
+    current: f32,
+    max_rate: f32,
+    /// Where the ramp set off from toward the target it had then, sizing the settling band
+    #[serde(skip)]
+    departed: Option<(f32, f32)>,
+}
+
+impl ResetState for SetpointRamp {
+    /// Starts over at the target rather than carrying on from wherever the ramp had got to
+    fn reset(&mut self) {
+        self.current = self.target;
+        self.departed = None;
+    }
+}
+
+impl SetpointRamp {
+    fn new(set_point: f32) -> Self {
+        Self {
+            target: set_point,
+            current: set_point,
+            max_rate: 1.0,
+            departed: None,
+        }
+    }
+
+    fn advance(&mut self, dt: f32) -> f32 {
+        if self.departed.map(|(_, target)| target) != Some(self.target) {
+            self.departed = Some((self.current, self.target));
+        }
+        let max_step = self.max_rate * dt;
+        // The short way round, so crossing the 0/2π seam does not swing through π
+        let remaining = angle_difference(self.target, self.current);
+        self.current = if remaining.abs() <= max_step {
+            self.target
+        } else {
+            wrap_angle(self.current + remaining.clamp(-max_step, max_step))
+        };
+        self.current
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum ReferenceKind {
+    #[default]
+    Constant,
+    Sine,
+    Square,
+}
+
+/// Moving set point `center + amplitude * wave(frequency * t)` for the controllers to track,
+/// applied after any `SetpointRamp`
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ReferenceSignal {
+    kind: ReferenceKind,
+    center: f32,
+    amplitude: f32,
+    /// In Hz
+    frequency: f32,
+    #[serde(skip)]
+    time: f32,
+    #[serde(skip)]
+    history: History,
+}
+
+impl Default for ReferenceSignal {
+    fn default() -> Self {
+        Self {
+            kind: ReferenceKind::Sine,
+            center: PI,
+            amplitude: 0.3,
+            frequency: 0.2,
+            time: 0.0,
+            history: Default::default(),
+        }
+    }
+}
+
+impl ReferenceSignal {
+    fn value(&self) -> f32 {
+        let phase = TAU * self.frequency * self.time;
+        let wave = match self.kind {
+            ReferenceKind::Constant => 0.0,
+            ReferenceKind::Sine => phase.sin(),
+            ReferenceKind::Square => phase.sin().signum(),
+        };
+        wrap_angle(self.center + self.amplitude * wave)
+    }
+
+    /// Returns the set point for the coming step and moves the time on
+    fn advance(&mut self, dt: f32) -> f32 {
+        let set_point = self.value();
+        self.time += dt;
+        set_point
+    }
+}
+
+impl ResetState for ReferenceSignal {
+    fn reset(&mut self) {
+        self.time = 0.0;
+        self.history.clear();
+    }
+}
+
+/// Estimates the state from noisy measurements using the linear model from `get_system`
+///
+/// The estimate is kept as a deviation from the top, which is where that model is linearized
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct KalmanFilter {
+    x_hat: Matrix2x1<f32>,
+    p: Matrix2<f32>,
+    q: Matrix2<f32>,
+    r: Matrix2<f32>,
+    #[serde(skip)]
+    estimate_history: History,
+}
+
+impl Default for KalmanFilter {
+    fn default() -> Self {
+        Self {
+            x_hat: Matrix2x1::zeros(),
+            p: Matrix2::identity(),
+            q: Matrix2::from_diagonal_element(1e-4),
+            r: Matrix2::new(0.01, 0.0, 0.0, 0.1),
+            estimate_history: Default::default(),
+        }
+    }
+}
+
+impl KalmanFilter {
+    fn predict(&mut self, (a, b): (A, B), control: f32) {
+        self.x_hat = a * self.x_hat + b * control;
+        self.p = a * self.p * a.transpose() + self.q;
+    }
+
+    fn update(&mut self, measured_a: f32, measured_da: f32) {
+        let (a, da) = self.estimate();
+        let innovation = Matrix2x1::new(angle_difference(measured_a, a), measured_da - da);
+
+        let Some(s_inv) = (self.p + self.r).try_inverse() else {
+            return;
+        };
+        let k = self.p * s_inv;
+
+        self.x_hat += k * innovation;
+        self.p = (Matrix2::identity() - k) * self.p;
+    }
+
+    fn estimate(&self) -> (f32, f32) {
+        (wrap_angle(PI + self.x_hat[0]), self.x_hat[1])
+    }
+}
+
+impl ResetState for KalmanFilter {
+    fn reset(&mut self) {
+        let template = KalmanFilter::default();
+        self.x_hat = template.x_hat;
+        self.p = template.p;
+        self.estimate_history.clear();
+    }
+}
+
+/// Reconstructs the velocity from angle measurements alone with the model linearized about the
+/// top, each step corrects the model prediction by the angle error through the gain `L`
+///
+/// When enabled the estimate replaces the measured velocity, so the controllers never see the
+/// true one
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct LuenbergerObserver {
+    enabled: bool,
+    /// Discrete poles of the estimation error, faster than the controller's
+    poles: [f32; 2],
+    x_hat: Matrix2x1<f32>,
+    /// Why the last gain computation failed, if it did
+    #[serde(skip)]
+    error: Option<&'static str>,
+    #[serde(skip)]
+    estimate_history: History,
+    #[serde(skip)]
+    velocity_history: History,
+}
+
+impl Default for LuenbergerObserver {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            poles: [0.5, 0.6],
+            x_hat: Matrix2x1::zeros(),
+            error: None,
+            estimate_history: Default::default(),
+            velocity_history: Default::default(),
+        }
+    }
+}
+
+impl LuenbergerObserver {
+    /// Predicts with the applied control, then corrects with the measured angle
+    fn update(&mut self, (a, b): (A, B), control: f32, measured_a: f32) {
+        let l = match observer_gain(a, self.poles) {
+            Ok(l) => l,
+            Err(err) => {
+                self.error = Some(err);
+                return;
+            }
+        };
+        self.error = None;
+
+        let predicted = a * self.x_hat + b * control;
+        let innovation = angle_difference(measured_a, PI) - predicted[0];
+        self.x_hat = predicted + l * innovation;
+    }
+
+    fn estimate(&self) -> (f32, f32) {
+        (wrap_angle(PI + self.x_hat[0]), self.x_hat[1])
+    }
+}
+
+impl ResetState for LuenbergerObserver {
+    fn reset(&mut self) {
+        self.x_hat = Matrix2x1::zeros();
+        self.estimate_history.clear();
+        self.velocity_history.clear();
+    }
+}
+
+/// State feedback with the gain chosen to put the discrete closed-loop poles at `poles`
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct PolePlacement {
+    set_point: f32,
+    poles: [f32; 2],
+    /// Why the last gain computation failed, if it did
+    #[serde(skip)]
+    error: Option<&'static str>,
+}
+
+impl Default for PolePlacement {
+    fn default() -> Self {
+        Self {
+            set_point: PI,
+            poles: [0.9, 0.85],
+            error: None,
+        }
+    }
+}
+
+/// Number of singular values above a tolerance relative to the largest, zero for NaN input
+fn numerical_rank(m: DMatrix<f32>) -> usize {
+    if !m.iter().all(|m| m.is_finite()) {
+        return 0;
+    }
+    let singular_values = m.singular_values();
+    let tolerance = 1e-6 * singular_values.max();
+    singular_values.iter().filter(|s| **s > tolerance).count()
+}
+
+/// Rank of the controllability matrix [B, AB, .., A^(N-1)B], `N` if every state can be reached
+fn controllability_rank<const N: usize>((a, b): (A<N>, B<N>)) -> usize {
+    let mut c = DMatrix::<f32>::zeros(N, N);
+    let mut column = b;
+    for i in 0..N {
+        c.set_column(i, &column);
+        column = a * column;
+    }
+    numerical_rank(c)
+}
+
+/// Rank of the observability matrix [C; CA] when only the angle is measured
+fn observability_rank(a: &A) -> usize {
+    let c = K::<2>::new(1.0, 0.0);
+    let o = Matrix2::from_rows(&[c, c * a]);
+    numerical_rank(DMatrix::from_iterator(2, 2, o.iter().copied()))
+}
+
+fn is_controllable(system: (A, B)) -> bool {
+    controllability_rank(system) == 2
+}
+
+/// Ackermann's formula, K = [0 1] [B, AB]^-1 φ(A), where φ is the desired characteristic polynomial
+fn ackermann((a, b): (A, B), [p1, p2]: [f32; 2]) -> Result<K, &'static str> {
+    if !is_controllable((a, b)) {
+        return Err("System is not controllable");
+    }
+
+    let c_inv = Matrix2::from_columns(&[b, a * b])
+        .try_inverse()
+        .ok_or("System is not controllable")?;
+    let phi = a * a - a * (p1 + p2) + Matrix2::identity() * (p1 * p2);
+
+    Ok(K::<2>::new(0.0, 1.0) * c_inv * phi)
+}
+
+/// Ackermann's formula for the observer, L = φ(A) [CA; CA²]^-1 [0 1]' with C = [1 0]. The
+/// correction uses the measurement after the prediction, so the error evolves as (A - LCA)
+fn observer_gain(a: A, [p1, p2]: [f32; 2]) -> Result<Matrix2x1<f32>, &'static str> {
+    if observability_rank(&a) < 2 {
+        return Err("System is not observable");
+    }
+
+    let c = K::<2>::new(1.0, 0.0);
+    let o_inv = Matrix2::from_rows(&[c * a, c * a * a])
+        .try_inverse()
+        .ok_or("System is not observable")?;
+    let phi = a * a - a * (p1 + p2) + Matrix2::identity() * (p1 * p2);
+
+    Ok(phi * o_inv * Matrix2x1::new(0.0, 1.0))
+}
+
+/// On-off control at full power, holding the last direction while the error is inside the
+/// hysteresis band to avoid chatter
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct BangBang {
+    set_point: f32,
+    hysteresis: f32,
+    last_sign: f32,
+}
+
+impl Default for BangBang {
+    fn default() -> Self {
+        Self {
+            set_point: PI,
+            hysteresis: 0.05,
+            last_sign: 0.0,
+        }
+    }
+}
+
+impl ResetState for BangBang {
+    fn reset(&mut self) {
+        self.last_sign = 0.0;
+    }
+}
+
+impl BangBang {
+    fn update(&mut self, error: f32) -> f32 {
+        if error > self.hysteresis / 2.0 {
+            self.last_sign = -1.0;
+        } else if error < -self.hysteresis / 2.0 {
+            self.last_sign = 1.0;
+        }
+
+        self.last_sign
+    }
+}
+
+/// Drives the state onto the surface s = da + lambda * (a - set_point), where the error decays
+/// exponentially at rate lambda. The switching term is saturated inside a boundary layer around
+/// the surface to reduce chatter
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct SlidingMode {
+    set_point: f32,
+    lambda: f32,
+    /// Control applied away from the surface
+    eta: f32,
+    /// Half width of the boundary layer in which the control goes linear in s, too thin and the
+    /// fixed step chatters across the surface anyway
+    boundary_layer: f32,
+    #[serde(skip)]
+    surface_history: History,
+}
+
+impl Default for SlidingMode {
+    fn default() -> Self {
+        Self {
+            set_point: PI,
+            lambda: 2.0,
+            eta: 1.0,
+            boundary_layer: 0.5,
+            surface_history: Default::default(),
+        }
+    }
+}
+
+impl ResetState for SlidingMode {
+    fn reset(&mut self) {
+        self.surface_history.clear();
+    }
+}
+
+impl SlidingMode {
+    fn surface(&self, (a, da): (f32, f32)) -> f32 {
+        da + self.lambda * angle_difference(a, self.set_point)
+    }
+
+    fn control(&self, s: f32) -> f32 {
+        let switching = if self.boundary_layer > 0.0 {
+            (s / self.boundary_layer).clamp(-1.0, 1.0)
+        } else {
+            s.signum()
+        };
+        -self.eta * switching
+    }
+}
+
+/// Brings the pendulum to rest hanging straight down, mostly by damping the swing and letting
+/// gravity do the rest. The control switches off once the pendulum is close to still at the
+/// bottom and comes back on if it gets pushed out of the angle band
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Park {
+    /// Control per rad/s of angular velocity
+    damping: f32,
+    /// Light pull towards the bottom, so a pendulum balanced exactly upright still tips over
+    stiffness: f32,
+    angle_threshold: f32,
+    velocity_threshold: f32,
+    #[serde(skip)]
+    parked: bool,
+}
+
+impl Default for Park {
+    fn default() -> Self {
+        Self {
+            damping: 0.5,
+            stiffness: 0.1,
+            angle_threshold: 0.05,
+            velocity_threshold: 0.05,
+            parked: false,
+        }
+    }
+}
+
+impl ResetState for Park {
+    fn reset(&mut self) {
+        self.parked = false;
+    }
+}
+
+impl Park {
+    fn control(&mut self, (a, da): (f32, f32)) -> f32 {
+        let error = angle_difference(a, 0.0);
+        if error.abs() > self.angle_threshold {
+            self.parked = false;
+        } else if da.abs() < self.velocity_threshold {
+            self.parked = true;
+        }
+
+        if self.parked {
+            0.0
+        } else {
+            -self.stiffness * error - self.damping * da
+        }
+    }
+}
+
+/// Two nested loops, a proportional one on the angle whose output, clamped, is the velocity set
+/// point of a proportional-integral one on the angular velocity. The inner loop rejects
+/// disturbances before they show up in the angle
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Cascade {
+    set_point: f32,
+    /// rad/s of velocity command per rad of angle error
+    outer_gain: f32,
+    /// Largest velocity the outer loop asks for, either way
+    max_velocity: f32,
+    inner_proportional_gain: f32,
+    inner_integral_gain: f32,
+    /// Back-calculation gain of the inner integral, as on the PID
+    tracking_gain: f32,
+    feedforward: Feedforward,
+    #[serde(skip)]
+    accumulator: f32,
+    #[serde(skip)]
+    velocity_command: f32,
+    #[serde(skip)]
+    error_history: History,
+    #[serde(skip)]
+    velocity_error_history: History,
+}
+
+impl Default for Cascade {
+    fn default() -> Self {
+        Self {
+            set_point: PI,
+            outer_gain: 2.0,
+            max_velocity: 2.0,
+            inner_proportional_gain: 4.0,
+            inner_integral_gain: 1.0,
+            tracking_gain: 5.0,
+            feedforward: Default::default(),
+            accumulator: 0.0,
+            velocity_command: 0.0,
+            error_history: Default::default(),
+            velocity_error_history: Default::default(),
+        }
+    }
+}
+
+impl ResetState for Cascade {
+    /// Empties the inner loop integral and the recorded errors
+    fn reset(&mut self) {
+        self.accumulator = 0.0;
+        self.velocity_command = 0.0;
+        self.error_history.clear();
+        self.velocity_error_history.clear();
+    }
+}
+
+impl Cascade {
+    /// Angle and velocity errors of the two loops for the measured state
+    fn errors(&mut self, (a, da): (f32, f32)) -> (f32, f32) {
+        let error = angle_difference(a, self.set_point);
+        self.velocity_command =
+            (-self.outer_gain * error).clamp(-self.max_velocity, self.max_velocity);
+        (error, da - self.velocity_command)
+    }
+
+    fn control(&mut self, pendulum: &Pendulum, dt: f32) -> f32 {
+        let (error, velocity_error) = self.errors((pendulum.measured_a, pendulum.measured_da));
+        self.error_history.push(error);
+        self.velocity_error_history.push(velocity_error);
+
+        let feedforward = self.feedforward.control(pendulum, self.set_point);
+        let control =
+            feedforward - self.inner_proportional_gain * velocity_error - self.accumulator;
+
+        let saturated = control.clamp(pendulum.control_min, pendulum.control_max);
+        self.accumulator += (velocity_error * self.inner_integral_gain
+            - (saturated - control) * self.tracking_gain)
+            * dt;
+        control
+    }
+}
+
+/// LQR gains designed at several angles, interpolated by the current angle so the feedback
+/// matches the local linearization over a wider part of the swing
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct GainSchedule {
+    set_point: f32,
+    /// Breakpoint angles in increasing order
+    angles: Vec<f32>,
+    feedforward: Feedforward,
+    /// One gain per breakpoint, empty until computed for the pendulum's model
+    #[serde(skip)]
+    gains: Vec<K>,
+    /// Why the gains could not be computed, cleared when the model changes
+    #[serde(skip)]
+    error: Option<&'static str>,
+}
+
+impl Default for GainSchedule {
+    fn default() -> Self {
+        Self {
+            set_point: PI,
+            angles: (-3..=3).map(|i| PI + 0.3 * i as f32).collect(),
+            feedforward: Default::default(),
+            gains: Vec::new(),
+            error: None,
+        }
+    }
+}
+
+impl GainSchedule {
+    fn compute_gains(&mut self, pendulum: &Pendulum, dt: f32) -> Result<(), &'static str> {
+        let gains = self
+            .angles
+            .iter()
+            .map(|&angle| LQR::new(angle, pendulum.get_system(angle, dt)).gain())
+            .collect::<Result<_, _>>();
+
+        self.error = gains.as_ref().err().copied();
+        self.gains = gains?;
+        Ok(())
+    }
+
+    /// Linear interpolation between the two breakpoints around `a`, held constant past the ends
+    fn gain(&self, a: f32) -> K {
+        let upper = self.angles.partition_point(|&angle| angle < a);
+        if upper == 0 {
+            return self.gains[0];
+        }
+        if upper == self.angles.len() {
+            return self.gains[upper - 1];
+        }
+
+        let (a0, a1) = (self.angles[upper - 1], self.angles[upper]);
+        let t = (a - a0) / (a1 - a0);
+        self.gains[upper - 1] * (1.0 - t) + self.gains[upper] * t
+    }
+}
+
+/// External angular acceleration acting on the pendulum regardless of the controller
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Disturbance {
+    /// Applied all the time
+    constant: f32,
+    /// Added on top of the constant part for `duration` seconds after a kick
+    torque: f32,
+    duration: f32,
+    #[serde(skip)]
+    remaining: f32,
+    /// Total disturbance over the last step, for plotting
+    #[serde(skip)]
+    applied: f32,
+}
+
+impl Default for Disturbance {
+    fn default() -> Self {
+        Self {
+            constant: 0.0,
+            torque: 20.0,
+            duration: 0.1,
+            remaining: 0.0,
+            applied: 0.0,
+        }
+    }
+}
+
+impl ResetState for Disturbance {
+    fn reset(&mut self) {
+        self.remaining = 0.0;
+        self.applied = 0.0;
+    }
+}
+
+impl Disturbance {
+    fn kick(&mut self) {
+        self.remaining = self.duration;
+    }
+
+    /// Returns the disturbance to apply over the next `dt` and counts down any active kick
+    fn advance(&mut self, dt: f32) -> f32 {
+        // Only the part of the step the kick overlaps counts, so short kicks still land whole
+        let kick = self.torque * self.remaining.min(dt) / dt;
+        self.remaining = (self.remaining - dt).max(0.0);
+        self.applied = self.constant + kick;
+        self.applied
+    }
+}
+
+/// Sinusoidal angular acceleration, for probing how well a controller rejects disturbances at a
+/// given frequency
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct PeriodicDisturbance {
+    amplitude: f32,
+    /// In Hz
+    frequency: f32,
+    #[serde(skip)]
+    time: f32,
+    #[serde(skip)]
+    applied: f32,
+}
+
+impl Default for PeriodicDisturbance {
+    fn default() -> Self {
+        Self {
+            amplitude: 0.0,
+            frequency: 0.2,
+            time: 0.0,
+            applied: 0.0,
+        }
+    }
+}
+
+impl ResetState for PeriodicDisturbance {
+    fn reset(&mut self) {
+        self.time = 0.0;
+        self.applied = 0.0;
+    }
+}
+
+impl PeriodicDisturbance {
+    /// Returns the disturbance at the middle of the next `dt` and moves the phase on
+    fn advance(&mut self, dt: f32) -> f32 {
+        self.applied = self.amplitude * (TAU * self.frequency * (self.time + dt / 2.0)).sin();
+        self.time += dt;
+        self.applied
+    }
+}
+
+/// Height above the bottom the pendulum is released from when identifying friction
+const IDENTIFICATION_AMPLITUDE: f32 = 0.5;
+/// Swing peaks recorded before fitting
+const IDENTIFICATION_PEAKS: usize = 6;
+/// Simulated seconds after which the fit is attempted with whatever peaks were found
+const IDENTIFICATION_TIMEOUT: f32 = 120.0;
+
+/// Free swing experiment that fits the viscous friction coefficient from how fast the swing
+/// decays, the controller is switched off until it finishes
+///
+/// Coulomb friction is not modeled by the fit and shows up as extra viscous friction
+#[derive(Component)]
+struct FrictionIdentification {
+    /// Controller handed back once the fit is done
+    controller: ControllerKind,
+    released: bool,
+    running: bool,
+    time: f32,
+    /// Last two deviations from the bottom, for spotting peaks
+    recent: [f32; 2],
+    /// Time and height of each swing peak on the positive side
+    peaks: Vec<(f32, f32)>,
+    estimate: Option<f32>,
+    error: Option<&'static str>,
+}
+
+impl FrictionIdentification {
+    fn new(controller: ControllerKind) -> Self {
+        Self {
+            controller,
+            released: false,
+            running: true,
+            time: 0.0,
+            recent: [IDENTIFICATION_AMPLITUDE; 2],
+            peaks: Vec::new(),
+            estimate: None,
+            error: None,
+        }
+    }
+
+    /// Advances the experiment by one physics step, holding the control at zero while it runs
+    fn update(&mut self, pendulum: &mut Pendulum, dt: f32) {
+        if !self.running {
+            return;
+        }
+        pendulum.control = 0.0;
+
+        if !self.released {
+            self.released = true;
+            pendulum.controller = ControllerKind::Manual;
+            pendulum.a = IDENTIFICATION_AMPLITUDE;
+            pendulum.da = 0.0;
+            return;
+        }
+
+        let x = angle_difference(pendulum.a, 0.0);
+        let [x0, x1] = self.recent;
+        if x1 > x0 && x1 >= x && x1 > 0.0 {
+            self.peaks.push((self.time - dt, x1));
+        }
+        self.recent = [x1, x];
+        self.time += dt;
+
+        if self.peaks.len() >= IDENTIFICATION_PEAKS || self.time > IDENTIFICATION_TIMEOUT {
+            self.finish(pendulum);
+        }
+    }
+
+    /// Stops a running experiment without fitting anything
+    fn cancel(&mut self, pendulum: &mut Pendulum) {
+        if self.running {
+            self.running = false;
+            pendulum.controller = self.controller;
+            self.error = Some("Cancelled by a reset");
+        }
+    }
+
+    fn finish(&mut self, pendulum: &mut Pendulum) {
+        self.running = false;
+        pendulum.controller = self.controller;
+
+        match log_decrement_friction(&self.peaks) {
+            Some(friction) => {
+                pendulum.friction = friction;
+                self.estimate = Some(friction);
+            }
+            None => self.error = Some("Not enough swings to fit the decay"),
+        }
+    }
+}
+
+/// The swing of dda = -g/L sin(a) - c da decays as exp(-c t / 2), so c is twice the log decrement
+/// per second between the first and last peak
+fn log_decrement_friction(peaks: &[(f32, f32)]) -> Option<f32> {
+    let (&(t0, x0), &(tn, xn)) = (peaks.first()?, peaks.last()?);
+    if tn <= t0 || x0 <= 0.0 || xn <= 0.0 {
+        return None;
+    }
+    Some((2.0 * (x0 / xn).ln() / (tn - t0)).max(0.0))
+}
+
+/// Lowest and highest reference frequency of a sweep in Hz, the points in between are log spaced
+const SWEEP_RANGE: (f32, f32) = (0.1, 2.0);
+const SWEEP_POINTS: usize = 8;
+/// Cycles at each frequency left for the response to reach steady state before measuring
+const SWEEP_SETTLE_CYCLES: f32 = 3.0;
+/// Cycles at each frequency the amplitude and phase are averaged over
+const SWEEP_MEASURE_CYCLES: f32 = 3.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrequencyResponse {
+    /// In Hz
+    frequency: f32,
+    /// Amplitude of the angle over the amplitude of the reference
+    magnitude: f32,
+    /// Phase of the angle relative to the reference in radians, negative when it lags
+    phase: f32,
+}
+
+/// Steps the reference sine through `SWEEP_POINTS` frequencies and measures the steady-state
+/// amplitude and phase of the angle at each, the reference goes back to its own kind and
+/// frequency afterwards
+#[derive(Component, Default)]
+struct FrequencySweep {
+    /// Reference kind and frequency handed back once the sweep is done, `None` until it starts
+    previous: Option<(ReferenceKind, f32)>,
+    finished: bool,
+    /// Time spent at the current frequency
+    time: f32,
+    /// Sums of the angle times the sine and cosine of the reference phase over the measured cycles
+    sums: [f32; 2],
+    samples: usize,
+    results: Vec<FrequencyResponse>,
+}
+
+impl FrequencySweep {
+    fn frequency(index: usize) -> f32 {
+        let (low, high) = SWEEP_RANGE;
+        low * (high / low).powf(index as f32 / (SWEEP_POINTS - 1) as f32)
+    }
+
+    fn running(&self) -> bool {
+        !self.finished
+    }
+
+    /// Advances the sweep by one physics step, taking the angle the step ended at
+    fn update(&mut self, pendulum: &Pendulum, reference: &mut ReferenceSignal, dt: f32) {
+        if self.finished {
+            return;
+        }
+        if self.previous.is_none() {
+            self.previous = Some((reference.kind, reference.frequency));
+            self.start(reference);
+            return;
+        }
+
+        // After `advance` the reference time is the end of the step, same as the angle
+        let frequency = reference.frequency;
+        self.time += dt;
+        if self.time > SWEEP_SETTLE_CYCLES / frequency {
+            let phase = TAU * frequency * reference.time;
+            let y = angle_difference(pendulum.a, reference.center);
+            self.sums[0] += y * phase.sin();
+            self.sums[1] += y * phase.cos();
+            self.samples += 1;
+        }
+        if self.time < (SWEEP_SETTLE_CYCLES + SWEEP_MEASURE_CYCLES) / frequency {
+            return;
+        }
+
+        // y = M A sin(wt + phase) correlates to M A cos(phase) with the sine and M A sin(phase)
+        // with the cosine
+        let [s, c] = self.sums.map(|sum| 2.0 * sum / self.samples.max(1) as f32);
+        self.results.push(FrequencyResponse {
+            frequency,
+            magnitude: s.hypot(c) / reference.amplitude,
+            phase: c.atan2(s),
+        });
+
+        if self.results.len() < SWEEP_POINTS {
+            self.start(reference);
+        } else if let Some((kind, frequency)) = self.previous {
+            self.finished = true;
+            reference.kind = kind;
+            reference.frequency = frequency;
+        }
+    }
+
+    /// Stops a running sweep, handing the reference back its own kind and frequency
+    fn cancel(&mut self, reference: &mut ReferenceSignal) {
+        if let (false, Some((kind, frequency))) = (self.finished, self.previous) {
+            reference.kind = kind;
+            reference.frequency = frequency;
+        }
+        self.finished = true;
+    }
+
+    /// Moves the reference on to the next frequency to measure
+    fn start(&mut self, reference: &mut ReferenceSignal) {
+        reference.kind = ReferenceKind::Sine;
+        reference.frequency = Self::frequency(self.results.len());
+        reference.time = 0.0;
+        self.time = 0.0;
+        self.sums = [0.0; 2];
+        self.samples = 0;
+    }
+}
+
+/// Time the error has to stay inside the settling band for a step test to end early
+const STEP_TEST_HOLD: f32 = 2.0;
+
+/// Moves the active controller's set point by `size` at once and records the angle until the
+/// pendulum settles or `timeout` runs out. The set point stays where the step put it
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct StepTest {
+    size: f32,
+    /// Longest a test runs for, in simulated seconds
+    timeout: f32,
+    /// Set by the button, the test starts on the next physics step
+    #[serde(skip)]
+    requested: bool,
+    /// Set points before and after the step while the test runs
+    #[serde(skip)]
+    running: Option<(f32, f32)>,
+    /// Error from the stepped set point after each physics step
+    #[serde(skip)]
+    errors: Vec<f32>,
+    #[serde(skip)]
+    result: Option<StepTestResult>,
+}
+
+#[derive(Clone, Copy)]
+struct StepTestResult {
+    from: f32,
+    to: f32,
+    /// The error stayed in the settling band for `STEP_TEST_HOLD` before the timeout
+    settled: bool,
+    metrics: Option<StepMetrics>,
+}
+
+impl Default for StepTest {
+    fn default() -> Self {
+        Self {
+            size: 0.2,
+            timeout: 20.0,
+            requested: false,
+            running: None,
+            errors: Vec::new(),
+            result: None,
+        }
+    }
+}
+
+impl ResetState for StepTest {
+    /// Drops a test in progress, the set point stays where the step put it
+    fn reset(&mut self) {
+        self.requested = false;
+        self.running = None;
+        self.errors.clear();
+    }
+}
+
+impl StepTest {
+    /// Set points before and after the running test, or the last finished one
+    fn step(&self) -> Option<(f32, f32)> {
+        self.running
+            .or_else(|| self.result.map(|result| (result.from, result.to)))
+    }
+
+    /// Starts the test from `set_point`, returning the one to step to
+    fn start(&mut self, set_point: f32) -> f32 {
+        let target = wrap_angle(set_point + self.size);
+        self.requested = false;
+        self.running = Some((set_point, target));
+        self.errors.clear();
+        self.result = None;
+        target
+    }
+
+    /// Records the angle a physics step ended at, finishing the test once it settles or times out
+    fn update(&mut self, pendulum: &Pendulum, dt: f32, fraction: f32) {
+        let Some((from, to)) = self.running else {
+            return;
+        };
+        self.errors.push(angle_difference(pendulum.a, to));
+
+        let band = fraction * self.size.abs();
+        let hold = (STEP_TEST_HOLD / dt).ceil() as usize;
+        let settled = self.errors.len() > hold
+            && self.errors[self.errors.len() - hold..]
+                .iter()
+                .all(|e| e.abs() <= band);
+        if !settled && self.errors.len() as f32 * dt < self.timeout {
+            return;
+        }
+
+        // The first sample is one step in, put the start back in front for the metrics
+        let mut errors = vec![angle_difference(from, to)];
+        errors.extend_from_slice(&self.errors);
+        self.running = None;
+        self.result = Some(StepTestResult {
+            from,
+            to,
+            settled,
+            metrics: step_metrics(&errors, dt, fraction),
+        });
+    }
+}
+
+/// Kicks the angular velocity by `size` in one step and records how far the angle moves from
+/// where it started. Runs with whatever control is active, turn control off for the free response
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct ImpulseTest {
+    size: f32,
+    /// Simulated seconds the response is recorded for
+    duration: f32,
+    /// Set by the button, the kick is applied on the next physics step
+    #[serde(skip)]
+    requested: bool,
+    /// Angle the pendulum was at when kicked, while the test runs
+    #[serde(skip)]
+    running: Option<f32>,
+    /// Offset from the starting angle after each physics step
+    #[serde(skip)]
+    deviations: Vec<f32>,
+    #[serde(skip)]
+    dt: f32,
+    #[serde(skip)]
+    result: Option<ImpulseMetrics>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ImpulseMetrics {
+    peak: f32,
+    /// Exponential rate the envelope shrinks at, in 1/s, `None` if the response never decays
+    decay_rate: Option<f32>,
+    /// Damped oscillation frequency in Hz from the zero crossings, `None` without two of them
+    frequency: Option<f32>,
+}
+
+impl Default for ImpulseTest {
+    fn default() -> Self {
+        Self {
+            size: 0.5,
+            duration: 5.0,
+            requested: false,
+            running: None,
+            deviations: Vec::new(),
+            dt: DEFAULT_DT,
+            result: None,
+        }
+    }
+}
+
+impl ResetState for ImpulseTest {
+    /// Drops a test in progress, the last result stays
+    fn reset(&mut self) {
+        self.requested = false;
+        self.running = None;
+        self.deviations.clear();
+    }
+}
+
+impl ImpulseTest {
+    fn start(&mut self, pendulum: &mut Pendulum, dt: f32) {
+        pendulum.da += self.size;
+        self.requested = false;
+        self.running = Some(pendulum.a);
+        self.deviations.clear();
+        self.dt = dt;
+        self.result = None;
+    }
+
+    /// Records the angle a physics step ended at, analysing the response once `duration` is up
+    fn update(&mut self, pendulum: &Pendulum) {
+        let Some(start) = self.running else {
+            return;
+        };
+        self.deviations.push(angle_difference(pendulum.a, start));
+        if (self.deviations.len() as f32) * self.dt >= self.duration {
+            self.running = None;
+            self.result = impulse_metrics(&self.deviations, self.dt);
+        }
+    }
+}
+
+/// Peak, envelope decay and oscillation frequency of an impulse response
+///
+/// The decay rate is fitted to the logarithm of the turning points, a response that only turns
+/// once falls back on the drop from its peak to the last sample
+fn impulse_metrics(deviations: &[f32], dt: f32) -> Option<ImpulseMetrics> {
+    let peak = deviations
+        .iter()
+        .fold(0.0, |peak: f32, d| peak.max(d.abs()));
+    if peak == 0.0 {
+        return None;
+    }
+    // Ignore turning points lost in the numerical noise near the end
+    let floor = peak * 1e-3;
+
+    let extrema: Vec<(f32, f32)> = deviations
+        .windows(3)
+        .enumerate()
+        .filter(|(_, w)| (w[1] - w[0]) * (w[2] - w[1]) <= 0.0 && w[1] != w[0])
+        .filter(|(_, w)| w[1].abs() > floor)
+        .map(|(i, w)| ((i + 1) as f32 * dt, w[1].abs().ln()))
+        .collect();
+    let decay_rate = if extrema.len() >= 2 {
+        let n = extrema.len() as f32;
+        let (mean_t, mean_y) = extrema
+            .iter()
+            .fold((0.0, 0.0), |(t, y), (ti, yi)| (t + ti / n, y + yi / n));
+        let (cov, var) = extrema.iter().fold((0.0, 0.0), |(cov, var), (t, y)| {
+            (
+                cov + (t - mean_t) * (y - mean_y),
+                var + (t - mean_t).powi(2),
+            )
+        });
+        Some(-cov / var)
+    } else {
+        let last = *deviations.last()?;
+        extrema
+            .first()
+            .filter(|_| last.abs() > floor)
+            .map(|&(t, y)| (y - last.abs().ln()) / (deviations.len() as f32 * dt - t))
+    }
+    .filter(|rate| rate.is_finite() && *rate > 0.0);
+
+    let crossings: Vec<f32> = deviations
+        .windows(2)
+        .enumerate()
+        .filter(|(_, w)| w[0] * w[1] < 0.0 && w[0].abs().max(w[1].abs()) > floor)
+        .map(|(i, w)| (i as f32 + w[0] / (w[0] - w[1]) + 1.0) * dt)
+        .collect();
+    let frequency = match (crossings.first(), crossings.last()) {
+        (Some(first), Some(last)) if crossings.len() >= 2 => {
+            Some((crossings.len() - 1) as f32 / (2.0 * (last - first)))
+        }
+        _ => None,
+    };
+
+    Some(ImpulseMetrics {
+        peak,
+        decay_rate,
+        frequency,
+    })
+}
+
+/// Gap between the linear prediction and the nonlinear angle the linear model counts as holding for
+const LINEARIZATION_TOLERANCE: f32 = 0.1;
+
+/// Runs the linear model from `get_system` next to the nonlinear dynamics from the pendulum's
+/// current state, both unforced, to show how far from `angle` the linearization holds
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct LinearizationCheck {
+    /// Angle the model is linearized about
+    angle: f32,
+    /// Simulated seconds both trajectories are run for
+    horizon: f32,
+    /// Set by the button, the trajectories are computed on the next frame
+    #[serde(skip)]
+    requested: bool,
+    /// Step the trajectories were sampled at
+    #[serde(skip)]
+    dt: f32,
+    /// Angles after each step, starting from the initial state and unwrapped so neither jumps
+    #[serde(skip)]
+    predicted: Vec<f32>,
+    #[serde(skip)]
+    actual: Vec<f32>,
+}
+
+impl Default for LinearizationCheck {
+    fn default() -> Self {
+        Self {
+            angle: PI,
+            horizon: 3.0,
+            requested: false,
+            dt: DEFAULT_DT,
+            predicted: Vec::new(),
+            actual: Vec::new(),
+        }
+    }
+}
+
+impl LinearizationCheck {
+    fn run(&mut self, pendulum: &Pendulum, integrator: IntegratorKind, dt: f32) {
+        self.requested = false;
+        self.dt = dt;
+        self.predicted.clear();
+        self.actual.clear();
+
+        // The simulated parameters rather than the controller model, so only the linearization
+        // differs between the two
+        let mut nonlinear = Pendulum {
+            model: None,
+            ..pendulum.clone()
+        };
+        let (a, _) = nonlinear.get_system(self.angle, dt);
+        let mut x = Matrix2x1::new(angle_difference(pendulum.a, self.angle), pendulum.da);
+        let mut unwrapped = self.angle + x[0];
+
+        for _ in 0..=(self.horizon / dt).ceil() as usize {
+            self.predicted.push(self.angle + x[0]);
+            self.actual.push(unwrapped);
+
+            x = a * x;
+            let before = nonlinear.a;
+            nonlinear.integrate(integrator, dt, 0.0);
+            unwrapped += angle_difference(nonlinear.a, before);
+        }
+    }
+
+    fn divergence(&self) -> impl Iterator<Item = f32> + '_ {
+        self.predicted
+            .iter()
+            .zip(&self.actual)
+            .map(|(predicted, actual)| (predicted - actual).abs())
+    }
+
+    /// Time until the prediction first leaves `LINEARIZATION_TOLERANCE`, `None` if it never does
+    fn breakdown_time(&self) -> Option<f32> {
+        self.divergence()
+            .position(|gap| gap > LINEARIZATION_TOLERANCE)
+            .map(|step| step as f32 * self.dt)
+    }
+}
+
+/// Energy-pumping swing-up that hands control over to LQR near the top
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct SwingUp {
+    gain: f32,
+    handoff_window: f32,
+}
+
+impl Default for SwingUp {
+    fn default() -> Self {
+        Self {
+            gain: -0.05,
+            handoff_window: 0.3,
+        }
+    }
+}
+
+impl SwingUp {
+    fn is_active(&self, pendulum: &Pendulum) -> bool {
+        angle_difference(pendulum.a, PI).abs() > self.handoff_window
+    }
+
+    fn control(&self, pendulum: &Pendulum) -> f32 {
+        let error = energy(pendulum) - pendulum.upright_energy();
+        self.gain * error * (pendulum.da * pendulum.a.cos()).signum()
+    }
+}
+
+/// Where exported files get written
+#[derive(Resource)]
+struct ExportSettings {
+    directory: String,
+    /// Width and height in pixels of saved plot images
+    plot_size: (u32, u32),
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            directory: ".".to_string(),
+            plot_size: (1600, 800),
+        }
+    }
+}
+
+/// Fixed physics timestep used when no other value is configured
+const DEFAULT_DT: f32 = 0.05;
+const G: f32 = 9.8;
+/// Speed below which Coulomb friction gets scaled down, kept large enough that the ramp stays
+/// stable at the default step size
+const COULOMB_VELOCITY_THRESHOLD: f32 = 0.05;
+/// Upper bound on the real time the clock will try to catch up on in one frame
+const MAX_FRAME_TIME: f32 = 0.25;
+
+#[derive(StageLabel)]
+struct PhysicsStage;
+
+/// Accumulates real frame time and releases it in fixed `dt` sized physics steps
+#[derive(Resource)]
+struct SimulationClock {
+    dt: f32,
+    accumulator: f32,
+    /// Simulated seconds per real second, scales how many physics steps run rather than their size
+    time_scale: f32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            dt: DEFAULT_DT,
+            accumulator: 0.0,
+            time_scale: 1.0,
+        }
+    }
+}
+
+/// Sent when the step size changes, the discrete models built from the old one are stale
+struct DtChangedEvent;
+
+/// Largest step for which the integrator keeps small swings about the bottom bounded, for
+/// semi-implicit Euler ω dt < 2 and for RK4 ω dt < 2.8 with ω = sqrt(g / length)
+fn stable_dt(integrator: IntegratorKind, pendulum: &Pendulum) -> f32 {
+    let bound = match integrator {
+        IntegratorKind::Euler => 2.0,
+        IntegratorKind::Rk4 => 2.8,
+    };
+    bound / (pendulum.gravity / pendulum.length).abs().sqrt()
+}
+
+fn relinearize_pendulums(
+    mut events: EventReader<DtChangedEvent>,
+    mut lqrs: Query<&mut LQR>,
+    mut schedules: Query<&mut GainSchedule>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    // Both get rebuilt by their control systems with the new step
+    for mut lqr in lqrs.iter_mut() {
+        lqr.linearized_at = None;
+    }
+    for mut schedule in schedules.iter_mut() {
+        schedule.gains.clear();
+        schedule.error = None;
+    }
+}
+
+impl SimulationClock {
+    fn advance(&mut self, delta: f32) {
+        self.accumulator =
+            (self.accumulator + delta * self.time_scale).min(MAX_FRAME_TIME * self.time_scale);
+    }
+
+    fn consume_step(&mut self) -> bool {
+        if self.accumulator >= self.dt {
+            self.accumulator -= self.dt;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Wall clock seconds each real-time factor is averaged over
+const REAL_TIME_WINDOW: f32 = 1.0;
+/// Fraction of the time scale the factor may fall short by before it counts as falling behind,
+/// the steps still waiting in the accumulator make it jitter by about a step per window
+const REAL_TIME_MARGIN: f32 = 0.02;
+
+/// Simulated seconds against wall clock seconds, to tell whether the physics keeps up
+#[derive(Resource, Default)]
+struct RealTimeFactor {
+    wall: f32,
+    simulated: f32,
+    steps: u32,
+    frames: u32,
+    /// Simulated seconds per wall second and physics steps per frame over the last full window
+    last: Option<(f32, f32)>,
+}
+
+impl RealTimeFactor {
+    /// Starts a frame that took `delta`, closing the window first so it only holds frames whose
+    /// steps have all run
+    fn frame(&mut self, delta: f32) {
+        if self.wall >= REAL_TIME_WINDOW {
+            self.last = Some((
+                self.simulated / self.wall,
+                self.steps as f32 / self.frames as f32,
+            ));
+            self.wall = 0.0;
+            self.simulated = 0.0;
+            self.steps = 0;
+            self.frames = 0;
+        }
+        self.wall += delta;
+        self.frames += 1;
+    }
+
+    fn step(&mut self, dt: f32) {
+        self.simulated += dt;
+        self.steps += 1;
+    }
+
+    /// Forgets everything measured, pausing says nothing about keeping up
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// While paused the physics only advances by explicitly requested steps
+#[derive(Resource, Default)]
+struct SimState {
+    paused: bool,
+    pending_steps: u32,
+}
+
+/// Checks every pendulum after each physics step for state that has blown up, so a bad solve
+/// shows up where it happened instead of as garbage later. On by default in debug builds
+#[derive(Resource)]
+struct InvariantCheck {
+    enabled: bool,
+    /// Pause the simulation at a violation so the state can be inspected
+    pause: bool,
+    /// Largest angular velocity that still counts as sane
+    max_velocity: f32,
+    /// Pendulum and description of the last violation
+    last_violation: Option<(Entity, String)>,
+    /// Pendulums whose current violation was already logged, dropped once they are sane again
+    reported: HashSet<Entity>,
+}
+
+impl Default for InvariantCheck {
+    fn default() -> Self {
+        Self {
+            enabled: cfg!(debug_assertions),
+            pause: true,
+            max_velocity: 1000.0,
+            last_violation: None,
+            reported: HashSet::new(),
+        }
+    }
+}
+
+/// What is wrong with the pendulum's state, `None` if the angle, velocity and control are all
+/// finite and in bounds
+fn invariant_violation(pendulum: &Pendulum, max_velocity: f32) -> Option<String> {
+    let problem = if !pendulum.a.is_finite() {
+        format!("angle is {}", pendulum.a)
+    } else if !pendulum.da.is_finite() || pendulum.da.abs() > max_velocity {
+        format!("angular velocity is {}", pendulum.da)
+    } else if pendulum.control != 0.0
+        // Disabled control and the dead zone both leave a zero that may sit outside the range
+        && !(pendulum.control_min..=pendulum.control_max).contains(&pendulum.control)
+    {
+        format!(
+            "control is outside {}..{}",
+            pendulum.control_min, pendulum.control_max
+        )
+    } else {
+        return None;
+    };
+    Some(format!(
+        "{}, last control {} with {} applied",
+        problem, pendulum.control, pendulum.applied_control
+    ))
+}
+
+fn check_invariants(
+    mut check: ResMut<InvariantCheck>,
+    mut state: ResMut<SimState>,
+    query: Query<(Entity, &Pendulum)>,
+) {
+    if !check.enabled {
+        return;
+    }
+    for (entity, pendulum) in query.iter() {
+        let Some(violation) = invariant_violation(pendulum, check.max_velocity) else {
+            check.reported.remove(&entity);
+            continue;
+        };
+        // A pendulum that stays broken while running on is only reported once
+        if check.reported.insert(entity) {
+            error!(
+                "Pendulum {:?} ({}): {}",
+                entity,
+                pendulum.controller.name(),
+                violation
+            );
+        }
+        check.last_violation = Some((entity, violation));
+        if check.pause {
+            state.paused = true;
+            state.pending_steps = 0;
+        }
+    }
+}
+
+fn advance_clock(
+    time: Res<Time>,
+    state: Res<SimState>,
+    mut clock: ResMut<SimulationClock>,
+    mut real_time: ResMut<RealTimeFactor>,
+) {
+    if state.paused {
+        clock.accumulator = 0.0;
+        real_time.clear();
+    } else {
+        clock.advance(time.delta_seconds());
+        real_time.frame(time.delta_seconds());
+    }
+}
+
+fn fixed_step(
+    mut clock: ResMut<SimulationClock>,
+    mut state: ResMut<SimState>,
+    mut recorder: ResMut<Recorder>,
+    mut real_time: ResMut<RealTimeFactor>,
+) -> ShouldRun {
+    let step = if state.paused {
+        let pending = state.pending_steps > 0;
+        state.pending_steps = state.pending_steps.saturating_sub(1);
+        pending
+    } else {
+        clock.consume_step()
+    };
+
+    if !step {
+        return ShouldRun::No;
+    }
+    if !state.paused {
+        real_time.step(clock.dt);
+    }
+
+    // Replay takes the place of the physics, steps only move it on to the next frame
+    if recorder.replaying() {
+        recorder.advance();
+        return ShouldRun::NoAndCheckAgain;
+    }
+
+    ShouldRun::YesAndCheckAgain
+}
+
+fn sim_state_keyboard(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut state: ResMut<SimState>,
+    mut reset_events: EventWriter<ResetAllEvent>,
+) {
+    // Keys typed into a text field, such as a preset name, are not meant as hotkeys
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Space) {
+        state.paused = !state.paused;
+        state.pending_steps = 0;
+    }
+
+    // Not an arrow key, those push manually controlled pendulums
+    if state.paused && keys.just_pressed(KeyCode::Period) {
+        state.pending_steps += 1;
+    }
+
+    if keys.just_pressed(KeyCode::R) {
+        reset_events.send(ResetAllEvent);
+    }
+}
+
+struct ResetAllEvent;
+
+/// Marks the pendulum picked with Tab, drawn highlighted with its window in front
+#[derive(Component)]
+struct Selected;
+
+/// Pendulum after `current` in `entities`, wrapping around, or the first one if none is selected
+fn next_selection(entities: &[Entity], current: Option<Entity>) -> Option<Entity> {
+    let next = current
+        .and_then(|current| entities.iter().position(|&e| e == current))
+        .map_or(0, |i| (i + 1) % entities.len().max(1));
+    entities.get(next).copied()
+}
+
+fn cycle_selection(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    pendulums: Query<Entity, With<Pendulum>>,
+    selected: Query<Entity, With<Selected>>,
+) {
+    // Tab also moves focus between egui widgets while one is being edited
+    if !keys.just_pressed(KeyCode::Tab) || egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    let mut entities: Vec<Entity> = pendulums.iter().collect();
+    entities.sort();
+    let current = selected.iter().next();
+    let Some(next) = next_selection(&entities, current) else {
+        return;
+    };
+
+    if let Some(current) = current {
+        commands.entity(current).remove::<Selected>();
+    }
+    commands.entity(next).insert(Selected);
+    // Same layer the pendulum settings window lives on
+    egui_context
+        .ctx_mut()
+        .move_to_top(egui::LayerId::new(egui::Order::Middle, Id::new(next)));
+}
+
+/// How far Up and Down move the selected pendulum's set point
+#[derive(Resource)]
+struct SetpointNudge {
+    step: f32,
+}
+
+impl Default for SetpointNudge {
+    fn default() -> Self {
+        Self { step: 0.05 }
+    }
+}
+
+impl SetpointNudge {
+    /// `set_point` moved one step up for a positive `direction` and down for a negative one
+    fn apply(&self, set_point: f32, direction: f32) -> f32 {
+        wrap_angle(set_point + direction.signum() * self.step)
+    }
+}
+
+/// Nudges the active controller's set point of the selected pendulum, through the ramp when it
+/// has one since the ramp would pull the set point back otherwise
+#[allow(clippy::type_complexity)]
+fn nudge_setpoint(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    nudge: Res<SetpointNudge>,
+    mut query: Query<
+        (
+            &Pendulum,
+            Option<&mut SetpointRamp>,
+            Option<&ReferenceSignal>,
+            SetPoints,
+        ),
+        With<Selected>,
+    >,
+) {
+    let direction = match (
+        keys.just_pressed(KeyCode::Up),
+        keys.just_pressed(KeyCode::Down),
+    ) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => return,
+    };
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    for (pendulum, ramp, reference, mut set_points) in query.iter_mut() {
+        // The reference signal moves the set point itself every step
+        if reference.is_some() {
+            continue;
+        }
+
+        match (pendulum.controller, ramp) {
+            (ControllerKind::Pid | ControllerKind::Lqr, Some(mut ramp)) => {
+                ramp.target = nudge.apply(ramp.target, direction);
+            }
+            (controller, _) => {
+                if let Some(set_point) = set_points.get(controller) {
+                    set_points.set(controller, nudge.apply(set_point, direction));
+                }
+            }
+        }
+    }
+}
+
+/// Sent for a pendulum that was put back at a starting state, everything attached to it that
+/// builds up state while running clears it in `reset_on_event`
+struct PendulumResetEvent(Entity);
+
+/// State a component builds up while the pendulum runs, forgotten when the pendulum is reset
+trait ResetState {
+    fn reset(&mut self);
+}
+
+fn reset_on_event<T: Component + ResetState>(
+    mut events: EventReader<PendulumResetEvent>,
+    mut query: Query<&mut T>,
+) {
+    for PendulumResetEvent(entity) in events.iter() {
+        if let Ok(mut state) = query.get_mut(*entity) {
+            state.reset();
+        }
+    }
+}
+
+/// Systems that send `PendulumResetEvent`, the handlers run after them so a reset lands before
+/// the next physics step
+#[derive(SystemLabel)]
+struct SendsResets;
+
+fn reset_systems() -> SystemSet {
+    SystemSet::new()
+        .after(SendsResets)
+        .with_system(reset_on_event::<PID>)
+        .with_system(reset_on_event::<LQR>)
+        .with_system(reset_on_event::<KalmanFilter>)
+        .with_system(reset_on_event::<LuenbergerObserver>)
+        .with_system(reset_on_event::<SetpointRamp>)
+        .with_system(reset_on_event::<ReferenceSignal>)
+        .with_system(reset_on_event::<BangBang>)
+        .with_system(reset_on_event::<Mpc>)
+        .with_system(reset_on_event::<SlidingMode>)
+        .with_system(reset_on_event::<Park>)
+        .with_system(reset_on_event::<Cascade>)
+        .with_system(reset_on_event::<Disturbance>)
+        .with_system(reset_on_event::<PeriodicDisturbance>)
+        .with_system(reset_on_event::<StepTest>)
+        .with_system(reset_on_event::<ImpulseTest>)
+        .with_system(reset_on_event::<Stability>)
+        .with_system(cancel_experiments_on_reset)
+}
+
+/// Experiments that also hold on to the pendulum or its reference end early, handing them back
+fn cancel_experiments_on_reset(
+    mut events: EventReader<PendulumResetEvent>,
+    mut identifications: Query<(&mut Pendulum, &mut FrictionIdentification)>,
+    mut sweeps: Query<(&mut ReferenceSignal, &mut FrequencySweep)>,
+) {
+    for PendulumResetEvent(entity) in events.iter() {
+        if let Ok((mut pendulum, mut identification)) = identifications.get_mut(*entity) {
+            identification.cancel(&mut pendulum);
+        }
+        if let Ok((mut reference, mut sweep)) = sweeps.get_mut(*entity) {
+            sweep.cancel(&mut reference);
+        }
+    }
+}
+
+/// Puts the pendulum back at its starting state and forgets everything recorded about it, the
+/// components attached to it follow once the caller sends a `PendulumResetEvent`
+fn reset_pendulum(pendulum: &mut Pendulum) {
+    let template = Pendulum::default();
+    pendulum.a = template.a;
+    pendulum.da = template.da;
+    pendulum.control_history.clear();
+    pendulum.angle_history.clear();
+    pendulum.measured_angle_history.clear();
+    pendulum.energy_history.clear();
+    pendulum.disturbance_history.clear();
+    pendulum.trail.clear();
+    pendulum.effort = 0.0;
+    pendulum.control_tick = 0;
+    pendulum.pending_controls.clear();
+    pendulum.applied_control = 0.0;
+}
+
+impl ResetState for PID {
+    fn reset(&mut self) {
+        self.clear_integral();
+        self.filtered_derivative = 0.0;
+        self.previous_error = 0.0;
+        self.previous_derivative = 0.0;
+        self.previous_acceleration = 0.0;
+        self.error_history.clear();
+        self.accumulator_history.clear();
+    }
+}
+
+impl ResetState for LQR {
+    fn reset(&mut self) {
+        self.integral = 0.0;
+        self.error_history.clear();
+    }
+}
+
+/// Ranges the initial angle and velocity are drawn from when randomizing
+#[derive(Resource)]
+struct InitialRanges {
+    angle: (f32, f32),
+    velocity: (f32, f32),
+}
+
+impl Default for InitialRanges {
+    fn default() -> Self {
+        Self {
+            angle: (0.0, 2.0 * PI),
+            velocity: (-1.0, 1.0),
+        }
+    }
+}
+
+struct RandomizeAllEvent;
+
+/// Named starting state that a pendulum can be reset to
+#[derive(Clone)]
+struct InitialPreset {
+    name: String,
+    a: f32,
+    da: f32,
+}
+
+impl InitialPreset {
+    fn new(name: &str, a: f32, da: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            a,
+            da,
+        }
+    }
+}
+
+/// Built in presets followed by any saved during the session
+#[derive(Resource)]
+struct InitialPresets {
+    presets: Vec<InitialPreset>,
+    /// Name typed in for the next custom preset
+    new_name: String,
+}
+
+impl Default for InitialPresets {
+    fn default() -> Self {
+        Self {
+            presets: vec![
+                InitialPreset::new("Hanging down", 0.0, 0.0),
+                InitialPreset::new("Near top", PI + 0.1, 0.0),
+                InitialPreset::new("Horizontal", PI / 2.0, 0.0),
+                InitialPreset::new("Fast spin", 0.0, 10.0),
+            ],
+            new_name: String::new(),
+        }
+    }
+}
+
+impl InitialPresets {
+    /// Adds the preset, replacing any with the same name
+    fn save(&mut self, preset: InitialPreset) {
+        match self.presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => self.presets.push(preset),
+        }
+    }
+}
+
+/// Same as `reset_pendulum` but starts from a random state instead of the default one
+fn randomize_pendulum(pendulum: &mut Pendulum, rng: &mut AppRng, ranges: &InitialRanges) {
+    reset_pendulum(pendulum);
+    pendulum.a = wrap_angle(rng.uniform(ranges.angle));
+    pendulum.da = rng.uniform(ranges.velocity);
+}
+
+fn randomize_all_pendulums(
+    mut events: EventReader<RandomizeAllEvent>,
+    mut resets: EventWriter<PendulumResetEvent>,
+    mut rng: ResMut<AppRng>,
+    ranges: Res<InitialRanges>,
+    mut query: Query<(Entity, &mut Pendulum)>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for (entity, mut pendulum) in query.iter_mut() {
+        randomize_pendulum(&mut pendulum, &mut rng, &ranges);
+        resets.send(PendulumResetEvent(entity));
+    }
+}
+
+fn reset_all_pendulums(
+    mut events: EventReader<ResetAllEvent>,
+    mut resets: EventWriter<PendulumResetEvent>,
+    mut query: Query<(Entity, &mut Pendulum)>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+
+    for (entity, mut pendulum) in query.iter_mut() {
+        reset_pendulum(&mut pendulum);
+        resets.send(PendulumResetEvent(entity));
+    }
+}
+
+fn add_pendulum(
+    mut commands: Commands,
+    clock: Res<SimulationClock>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let mut camera_bundle = Camera2dBundle::default();
+    camera_bundle.projection.scale *= 0.1;
+    commands.spawn(camera_bundle);
+
+    let configs = [
+        PendulumConfig {
+            pendulum: Pendulum {
+                controller: ControllerKind::Pid,
+                ..Pendulum::from_offset(-7.0, 0.0)
+            },
+            noise: Some(NoiseConfig::default()),
+            ramp: Some(SetpointRamp::new(PI)),
+            pid: Some(PID {
+                set_point: PI,
+                // set_point: 4.3,
+                proportional_gain: -8.0,
+                integral_gain: -5.5,
+                derivative_gain: -4.0,
+                ..default()
+            }),
+            ..default()
+        },
+        {
+            let p = Pendulum {
+                controller: ControllerKind::Lqr,
+                ..Pendulum::from_offset(7.0, 0.0)
+            };
+            PendulumConfig {
+                lqr: Some(LQR::new(PI, p.get_system(PI, clock.dt))),
+                pendulum: p,
+                noise: Some(NoiseConfig::default()),
+                kalman: Some(KalmanFilter::default()),
+                ramp: Some(SetpointRamp::new(PI)),
+                ..default()
+            }
+        },
+        PendulumConfig {
+            pendulum: Pendulum {
+                controller: ControllerKind::BangBang,
+                ..Pendulum::from_offset(-49.0, 0.0)
+            },
+            bang_bang: Some(BangBang::default()),
+            ..default()
+        },
+        PendulumConfig {
+            pendulum: Pendulum {
+                controller: ControllerKind::PolePlacement,
+                ..Pendulum::from_offset(49.0, 0.0)
+            },
+            pole_placement: Some(PolePlacement::default()),
+            ..default()
+        },
+        PendulumConfig {
+            pendulum: Pendulum {
+                controller: ControllerKind::Mpc,
+                ..Pendulum::from_offset(-7.0, 25.0)
+            },
+            mpc: Some(Mpc::default()),
+            ..default()
+        },
+        PendulumConfig {
+            pendulum: Pendulum {
+                a: PI + 1.0,
+                controller: ControllerKind::GainSchedule,
+                ..Pendulum::from_offset(7.0, 25.0)
+            },
+            gain_schedule: Some(GainSchedule::default()),
+            ..default()
+        },
+        PendulumConfig {
+            pendulum: Pendulum {
+                controller: ControllerKind::SlidingMode,
+                ..Pendulum::from_offset(-28.0, 0.0)
+            },
+            sliding_mode: Some(SlidingMode::default()),
+            ..default()
+        },
+        {
+            let p = Pendulum {
+                controller: ControllerKind::Lqr,
+                ..Pendulum::from_offset(28.0, 25.0)
+            };
+            PendulumConfig {
+                lqr: Some(LQR::new(PI, p.get_system(PI, clock.dt))),
+                pendulum: p,
+                observer: Some(LuenbergerObserver::default()),
+                ..default()
+            }
+        },
+        {
+            let p = Pendulum {
+                a: 0.0,
+                controller: ControllerKind::SwingUp,
+                ..Pendulum::from_offset(28.0, 0.0)
+            };
+            PendulumConfig {
+                lqr: Some(LQR::new(PI, p.get_system(PI, clock.dt))),
+                pendulum: p,
+                swing_up: Some(SwingUp::default()),
+                ..default()
+            }
+        },
+    ];
+
+    for config in configs {
+        spawn_pendulum(&mut commands, &mut meshes, &mut materials, config);
+    }
+}
+
+fn spawn_pendulum(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    config: PendulumConfig,
+) {
+    let appearance = config
+        .appearance
+        .unwrap_or_else(|| Appearance::for_controller(config.pendulum.controller));
+    let mut entity = commands.spawn((
+        config.pendulum,
+        MaterialMesh2dBundle {
+            mesh: meshes.add(shape::Circle::new(1.).into()).into(),
+            material: materials.add(ColorMaterial::from(appearance.color())),
+            transform: Transform::default(),
+            ..default()
+        },
+        appearance,
+    ));
+
+    if let Some(pid) = config.pid {
+        entity.insert(pid);
+    }
+    if let Some(lqr) = config.lqr {
+        entity.insert(lqr);
+    }
+    if let Some(swing_up) = config.swing_up {
+        entity.insert(swing_up);
+    }
+    if let Some(noise) = config.noise {
+        entity.insert(noise);
+    }
+    if let Some(kalman) = config.kalman {
+        entity.insert(kalman);
+    }
+    if let Some(observer) = config.observer {
+        entity.insert(observer);
+    }
+    if let Some(ramp) = config.ramp {
+        entity.insert(ramp);
+    }
+    if let Some(reference) = config.reference {
+        entity.insert(reference);
+    }
+    if let Some(pole_placement) = config.pole_placement {
+        entity.insert(pole_placement);
+    }
+    if let Some(bang_bang) = config.bang_bang {
+        entity.insert(bang_bang);
+    }
+    if let Some(mpc) = config.mpc {
+        entity.insert(mpc);
+    }
+    if let Some(gain_schedule) = config.gain_schedule {
+        entity.insert(gain_schedule);
+    }
+    if let Some(sliding_mode) = config.sliding_mode {
+        entity.insert(sliding_mode);
+    }
+    if let Some(park) = config.park {
+        entity.insert(park);
+    }
+    if let Some(cascade) = config.cascade {
+        entity.insert(cascade);
+    }
+    entity.insert(config.disturbance.unwrap_or_default());
+    entity.insert(config.periodic_disturbance.unwrap_or_default());
+    entity.insert(config.step_test.unwrap_or_default());
+    entity.insert(config.impulse_test.unwrap_or_default());
+    entity.insert(config.linearization.unwrap_or_default());
+    entity.insert(Stability::default());
+}
+
+/// How close a pendulum is to its set point, from the envelope of the recent error
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StabilityStatus {
+    /// The error stayed inside the band over the last window
+    Balanced,
+    /// Outside the band but not getting worse
+    Recovering,
+    /// The largest error of the last window grew past the one before it
+    Unstable,
+}
+
+impl StabilityStatus {
+    fn name(self) -> &'static str {
+        match self {
+            StabilityStatus::Balanced => "Balanced",
+            StabilityStatus::Recovering => "Recovering",
+            StabilityStatus::Unstable => "Unstable",
+        }
+    }
+
+    fn color(self) -> egui::Color32 {
+        match self {
+            StabilityStatus::Balanced => egui::Color32::GREEN,
+            StabilityStatus::Recovering => egui::Color32::YELLOW,
+            StabilityStatus::Unstable => egui::Color32::RED,
+        }
+    }
+}
+
+/// Thresholds the stability status of every pendulum is judged by
+#[derive(Resource, Clone, Copy)]
+struct StabilityIndicator {
+    /// Error in radians a balanced pendulum stays within
+    band: f32,
+    /// Simulated seconds each envelope is taken over
+    window: f32,
+    /// Factor the envelope has to grow by from one window to the next to count as unstable
+    growth: f32,
+}
+
+impl Default for StabilityIndicator {
+    fn default() -> Self {
+        Self {
+            band: 0.05,
+            window: 0.5,
+            growth: 1.2,
+        }
+    }
+}
+
+/// Error magnitudes over the last two windows and the status they give, none while the active
+/// controller holds no set point to measure against
+#[derive(Component, Default)]
+struct Stability {
+    errors: VecDeque<f32>,
+    status: Option<StabilityStatus>,
+    /// Controller the errors were measured under, switching starts the windows over
+    controller: ControllerKind,
+}
+
+impl ResetState for Stability {
+    fn reset(&mut self) {
+        self.errors.clear();
+        self.status = None;
+    }
+}
+
+impl Stability {
+    fn update(&mut self, error: f32, indicator: &StabilityIndicator, dt: f32) {
+        let window = (indicator.window / dt).ceil().max(1.0) as usize;
+        self.errors.push_back(error.abs());
+        while self.errors.len() > 2 * window {
+            self.errors.pop_front();
+        }
+        self.status = Some(stability_status(
+            self.errors.make_contiguous(),
+            window,
+            indicator,
+        ));
+    }
+}
+
+/// Balanced while the last `window` errors stay in the band, unstable once their largest grew by
+/// `growth` over the largest of the `window` before them
+fn stability_status(
+    errors: &[f32],
+    window: usize,
+    indicator: &StabilityIndicator,
+) -> StabilityStatus {
+    let split = errors.len().saturating_sub(window);
+    let envelope = |errors: &[f32]| errors.iter().fold(0.0, |max: f32, e| max.max(*e));
+    let (previous, recent) = (envelope(&errors[..split]), envelope(&errors[split..]));
+
+    if recent <= indicator.band {
+        StabilityStatus::Balanced
+    } else if split >= window && recent > indicator.growth * previous {
+        StabilityStatus::Unstable
+    } else {
+        StabilityStatus::Recovering
+    }
+}
+
+/// Set points of every controller a pendulum can carry, read and written for whichever one is
+/// active so every system agrees on what the pendulum is being held at
+#[derive(WorldQuery)]
+#[world_query(mutable)]
+struct SetPoints {
+    pid: Option<&'static mut PID>,
+    lqr: Option<&'static mut LQR>,
+    placement: Option<&'static mut PolePlacement>,
+    bang_bang: Option<&'static mut BangBang>,
+    mpc: Option<&'static mut Mpc>,
+    schedule: Option<&'static mut GainSchedule>,
+    sliding: Option<&'static mut SlidingMode>,
+    cascade: Option<&'static mut Cascade>,
+}
+
+impl SetPointsItem<'_> {
+    /// Angle `controller` holds the pendulum at, parking always aims at the bottom while manual
+    /// control and the swing-up hold none
+    fn get(&self, controller: ControllerKind) -> Option<f32> {
+        match controller {
+            ControllerKind::Pid => self.pid.as_ref().map(|c| c.set_point),
+            ControllerKind::Lqr => self.lqr.as_ref().map(|c| c.set_point),
+            ControllerKind::PolePlacement => self.placement.as_ref().map(|c| c.set_point),
+            ControllerKind::BangBang => self.bang_bang.as_ref().map(|c| c.set_point),
+            ControllerKind::Mpc => self.mpc.as_ref().map(|c| c.set_point),
+            ControllerKind::GainSchedule => self.schedule.as_ref().map(|c| c.set_point),
+            ControllerKind::SlidingMode => self.sliding.as_ref().map(|c| c.set_point),
+            ControllerKind::Cascade => self.cascade.as_ref().map(|c| c.set_point),
+            ControllerKind::Park => Some(0.0),
+            ControllerKind::Manual | ControllerKind::SwingUp => None,
+        }
+    }
+
+    /// Moves the set point of `controller`, false when it has none that can be moved
+    fn set(&mut self, controller: ControllerKind, set_point: f32) -> bool {
+        let held = match controller {
+            ControllerKind::Pid => self.pid.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::Lqr => self.lqr.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::PolePlacement => self.placement.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::BangBang => self.bang_bang.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::Mpc => self.mpc.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::GainSchedule => self.schedule.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::SlidingMode => self.sliding.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::Cascade => self.cascade.as_mut().map(|c| &mut c.set_point),
+            ControllerKind::Manual | ControllerKind::SwingUp | ControllerKind::Park => None,
+        };
+        match held {
+            Some(held) => {
+                *held = set_point;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the set point of every controller, so switching between them keeps the target
+    fn set_all(&mut self, set_point: f32) {
+        for controller in ControllerKind::ALL {
+            self.set(controller, set_point);
+        }
+    }
+}
+
+fn update_stability(
+    indicator: Res<StabilityIndicator>,
+    clock: Res<SimulationClock>,
+    mut query: Query<(&Pendulum, &mut Stability, SetPoints)>,
+) {
+    for (pendulum, mut stability, set_points) in query.iter_mut() {
+        if stability.controller != pendulum.controller {
+            stability.reset();
+            stability.controller = pendulum.controller;
+        }
+        let Some(set_point) = set_points.get(pendulum.controller) else {
+            stability.reset();
+            continue;
+        };
+        let error = angle_difference(pendulum.a, set_point);
+        stability.update(error, &indicator, clock.dt);
+    }
+}
+
+/// Grid runtime-added pendulums get placed on and the tiling of the settings windows
+#[derive(Resource, Clone)]
+struct PendulumLayout {
+    /// Pendulums per row, centered on the pivot of the middle startup pendulums
+    columns: usize,
+    /// Distance between neighbouring pivots, rows stack upward from the startup row
+    spacing: Vec2,
+    /// Settings windows per row, spread across the screen in order
+    window_columns: usize,
+}
+
+impl Default for PendulumLayout {
+    fn default() -> Self {
+        Self {
+            columns: 8,
+            spacing: Vec2::new(14.0, 25.0),
+            window_columns: 2,
+        }
+    }
+}
+
+/// Horizontal span of the screen the settings windows are spread over, and the height of a row
+const WINDOW_SPAN: f32 = 1080.0;
+const WINDOW_ROW: f32 = 100.0;
+
+impl PendulumLayout {
+    fn tile(&self, slot: usize) -> Vec3 {
+        let columns = self.columns.max(1);
+        let column = (slot % columns) as f32 - (columns - 1) as f32 / 2.0;
+        let row = (slot / columns + 1) as f32;
+        Vec3::new(column * self.spacing.x, row * self.spacing.y, 0.0)
+    }
+
+    /// First grid slot above the startup pendulums that no pendulum occupies yet
+    fn free_tile(&self, taken: &[Vec3]) -> Vec3 {
+        (0..)
+            .map(|slot| self.tile(slot))
+            .find(|tile| taken.iter().all(|offset| offset.distance(*tile) > 1.0))
+            .unwrap()
+    }
+
+    /// Where the settings window of the `index`th pendulum opens
+    fn window_pos(&self, index: usize) -> (f32, f32) {
+        let columns = self.window_columns.max(1);
+        let column = (index % columns) as f32;
+        let step = if columns > 1 {
+            WINDOW_SPAN / (columns - 1) as f32
+        } else {
+            0.0
+        };
+        (
+            20.0 + column * step,
+            20.0 + WINDOW_ROW * (index / columns) as f32,
+        )
+    }
+}
+
+enum PendulumEvent {
+    AddPid,
+    AddLqr,
+    Delete(Entity),
+    /// Gives the pendulum a default configured component for a controller it doesn't have yet
+    AddController(Entity, ControllerKind),
+    /// Starts or stops driving the set points from a `ReferenceSignal`
+    TrackReference(Entity, bool),
+    /// Runs a `FrictionIdentification` on the pendulum, replacing any previous one
+    IdentifyFriction(Entity),
+    /// Runs a `FrequencySweep` on the pendulum's reference, replacing any previous one
+    SweepFrequency(Entity),
+}
+
+fn handle_pendulum_events(
+    mut commands: Commands,
+    mut events: EventReader<PendulumEvent>,
+    clock: Res<SimulationClock>,
+    layout: Res<PendulumLayout>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    query: Query<(&Pendulum, Option<&LQR>)>,
+) {
+    let mut taken: Vec<Vec3> = query.iter().map(|(pendulum, _)| pendulum.offset).collect();
+
+    for event in events.iter() {
+        let pendulum = Pendulum {
+            offset: layout.free_tile(&taken),
+            ..default()
+        };
+
+        let config = match event {
+            PendulumEvent::AddPid => PendulumConfig {
+                pid: Some(PID::balancing()),
+                pendulum: Pendulum {
+                    controller: ControllerKind::Pid,
+                    ..pendulum
+                },
+                ..default()
+            },
+            PendulumEvent::AddLqr => PendulumConfig {
+                lqr: Some(LQR::new(PI, pendulum.get_system(PI, clock.dt))),
+                pendulum: Pendulum {
+                    controller: ControllerKind::Lqr,
+                    ..pendulum
+                },
+                ..default()
+            },
+            PendulumEvent::Delete(entity) => {
+                if let Some(mut entity) = commands.get_entity(*entity) {
+                    entity.despawn();
+                }
+                continue;
+            }
+            PendulumEvent::TrackReference(entity, track) => {
+                if let Some(mut entity) = commands.get_entity(*entity) {
+                    if *track {
+                        entity.insert(ReferenceSignal::default());
+                    } else {
+                        entity.remove::<ReferenceSignal>();
+                    }
+                }
+                continue;
+            }
+            PendulumEvent::IdentifyFriction(entity) => {
+                if let Ok((pendulum, _)) = query.get(*entity) {
+                    commands
+                        .entity(*entity)
+                        .insert(FrictionIdentification::new(pendulum.controller));
+                }
+                continue;
+            }
+            PendulumEvent::SweepFrequency(entity) => {
+                if let Some(mut entity) = commands.get_entity(*entity) {
+                    entity.insert(FrequencySweep::default());
+                }
+                continue;
+            }
+            PendulumEvent::AddController(entity, kind) => {
+                let Ok((pendulum, existing_lqr)) = query.get(*entity) else {
+                    continue;
+                };
+                let lqr = || LQR::new(PI, pendulum.get_system(PI, clock.dt));
+
+                let mut entity = commands.entity(*entity);
+                match kind {
+                    ControllerKind::Manual => {}
+                    ControllerKind::Pid => {
+                        entity.insert(PID::balancing());
+                    }
+                    ControllerKind::Lqr => {
+                        entity.insert(lqr());
+                    }
+                    ControllerKind::SwingUp => {
+                        entity.insert(SwingUp::default());
+                        if existing_lqr.is_none() {
+                            entity.insert(lqr());
+                        }
+                    }
+                    ControllerKind::BangBang => {
+                        entity.insert(BangBang::default());
+                    }
+                    ControllerKind::PolePlacement => {
+                        entity.insert(PolePlacement::default());
+                    }
+                    ControllerKind::Mpc => {
+                        entity.insert(Mpc::default());
+                    }
+                    ControllerKind::GainSchedule => {
+                        entity.insert(GainSchedule::default());
+                    }
+                    ControllerKind::SlidingMode => {
+                        entity.insert(SlidingMode::default());
+                    }
+                    ControllerKind::Park => {
+                        entity.insert(Park::default());
+                    }
+                    ControllerKind::Cascade => {
+                        entity.insert(Cascade::default());
+                    }
+                }
+                continue;
+            }
+        };
+
+        taken.push(config.pendulum.offset);
+        spawn_pendulum(&mut commands, &mut meshes, &mut materials, config);
+    }
+}
+
+/// Total mechanical energy per unit mass, zero when hanging at rest
+fn energy(pendulum: &Pendulum) -> f32 {
+    0.5 * (pendulum.length * pendulum.da).powi(2)
+        + pendulum.gravity * pendulum.length * (1.0 - pendulum.a.cos())
+}
+
+/// Slowing of the swing from viscous and Coulomb friction at `da`
+fn friction_deceleration(friction: f32, coulomb_friction: f32, da: f32) -> f32 {
+    // Coulomb friction ramps in linearly below the threshold instead of flipping sign at zero
+    let coulomb = coulomb_friction * (da / COULOMB_VELOCITY_THRESHOLD).clamp(-1.0, 1.0);
+    friction * da + coulomb
+}
+
+/// Continuous dynamics of the pendulum, returns (da, dda) at the given state
+fn derivative(pendulum: &Pendulum, a: f32, da: f32, control: f32) -> (f32, f32) {
+    let friction = friction_deceleration(pendulum.friction, pendulum.coulomb_friction, da);
+
+    // Gravity's torque m g L sin(a) over the inertia m L² leaves the mass out of it
+    let dda = -pendulum.gravity * a.sin() / pendulum.length - friction
+        + control * pendulum.control_gain();
+    (da, dda)
+}
+
+fn measure_pendulum(
+    mut rng: ResMut<AppRng>,
+    mut query: Query<(&mut Pendulum, Option<&NoiseConfig>)>,
+) {
+    for (mut pendulum, noise) in query.iter_mut() {
+        let (mut a, mut da) = (pendulum.a, pendulum.da);
+
+        if let Some(noise) = noise {
+            a = wrap_angle(a + rng.normal(noise.angle_stddev));
+            da += rng.normal(noise.velocity_stddev);
+        }
+
+        pendulum.measured_a = a;
+        pendulum.measured_da = da;
+    }
+}
+
+fn ramp_setpoints(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut SetpointRamp, Option<&mut PID>, Option<&mut LQR>)>,
+) {
+    for (mut ramp, pid, lqr) in query.iter_mut() {
+        let set_point = ramp.advance(clock.dt);
+
+        if let Some(mut pid) = pid {
+            pid.set_point = set_point;
+        }
+        if let Some(mut lqr) = lqr {
+            lqr.set_point = set_point;
+        }
+    }
+}
+
+fn track_reference(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut ReferenceSignal, SetPoints)>,
+) {
+    for (mut reference, mut set_points) in query.iter_mut() {
+        let set_point = reference.advance(clock.dt);
+        reference.history.push(set_point);
+        set_points.set_all(set_point);
+    }
+}
+
+fn estimate_pendulum(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&Pendulum, &mut KalmanFilter)>,
+) {
+    for (pendulum, mut kalman) in query.iter_mut() {
+        kalman.predict(pendulum.get_system(PI, clock.dt), pendulum.applied_control);
+        kalman.update(pendulum.measured_a, pendulum.measured_da);
+
+        let (a, _) = kalman.estimate();
+        kalman.estimate_history.push(a);
+    }
+}
+
+fn observe_pendulum(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut LuenbergerObserver)>,
+) {
+    for (mut pendulum, mut observer) in query.iter_mut() {
+        let system = pendulum.get_system(PI, clock.dt);
+        observer.update(system, pendulum.applied_control, pendulum.measured_a);
+
+        let (_, da) = observer.estimate();
+        observer.estimate_history.push(da);
+        observer.velocity_history.push(pendulum.da);
+        if observer.enabled {
+            pendulum.measured_da = da;
+        }
+    }
+}
+
+fn identify_friction(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut FrictionIdentification)>,
+) {
+    for (mut pendulum, mut identification) in query.iter_mut() {
+        identification.update(&mut pendulum, clock.dt);
+    }
+}
+
+fn check_linearization(
+    clock: Res<SimulationClock>,
+    integrator: Res<IntegratorKind>,
+    mut query: Query<(&Pendulum, &mut LinearizationCheck)>,
+) {
+    for (pendulum, mut check) in query.iter_mut() {
+        if check.requested {
+            check.run(pendulum, *integrator, clock.dt);
+        }
+    }
+}
+
+fn run_impulse_test(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut ImpulseTest)>,
+) {
+    for (mut pendulum, mut test) in query.iter_mut() {
+        test.update(&pendulum);
+        if test.requested {
+            test.start(&mut pendulum, clock.dt);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn run_step_test(
+    clock: Res<SimulationClock>,
+    settling: Res<SettlingBand>,
+    mut query: Query<(
+        &Pendulum,
+        &mut StepTest,
+        Option<&mut SetpointRamp>,
+        SetPoints,
+    )>,
+) {
+    for (pendulum, mut test, ramp, mut set_points) in query.iter_mut() {
+        test.update(pendulum, clock.dt, settling.0);
+        if !test.requested {
+            continue;
+        }
+
+        // Parking always heads for the bottom, there is no set point to step
+        let set_point = set_points
+            .get(pendulum.controller)
+            .filter(|_| pendulum.controller != ControllerKind::Park);
+        let Some(set_point) = set_point else {
+            test.requested = false;
+            continue;
+        };
+        let target = test.start(set_point);
+
+        // Same controllers a reference signal drives, and the ramp jumps along with them
+        if let Some(mut ramp) = ramp {
+            ramp.current = target;
+            ramp.target = target;
+            ramp.departed = Some((set_point, target));
+        }
+        set_points.set_all(target);
+    }
+}
+
+fn sweep_frequency(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&Pendulum, &mut ReferenceSignal, &mut FrequencySweep)>,
+) {
+    for (pendulum, mut reference, mut sweep) in query.iter_mut() {
+        sweep.update(pendulum, &mut reference, clock.dt);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn move_pendulum(
+    integrator: Res<IntegratorKind>,
+    clock: Res<SimulationClock>,
+    grab: Res<Grab>,
+    mut query: Query<(
+        Entity,
+        &mut Pendulum,
+        Option<&mut Disturbance>,
+        Option<&mut PeriodicDisturbance>,
+        Option<&FrictionIdentification>,
+    )>,
+) {
+    for (entity, mut pendulum, disturbance, periodic, identification) in query.iter_mut() {
+        // Held by the mouse, which sets the angle directly
+        if grab.holds(entity) {
+            pendulum.tick_control();
+            continue;
+        }
+        // Held off while friction is identified, they would spoil the free swing it is fitted to
+        let identifying = identification.is_some_and(|identification| identification.running);
+        if let Some(mut disturbance) = disturbance {
+            if identifying {
+                disturbance.applied = 0.0;
+            } else {
+                pendulum.da += disturbance.advance(clock.dt) * clock.dt;
+            }
+        }
+        if let Some(mut periodic) = periodic {
+            if identifying {
+                periodic.applied = 0.0;
+            } else {
+                pendulum.da += periodic.advance(clock.dt) * clock.dt;
+            }
+        }
+        if !pendulum.control_enabled {
+            pendulum.release_control();
+        }
+        pendulum.step_delayed(*integrator, clock.dt);
+        pendulum.tick_control();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn draw_pendulum(
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut query: Query<(
+        &mut Transform,
+        &Pendulum,
+        &Appearance,
+        ChangeTrackers<Appearance>,
+        &Handle<ColorMaterial>,
+    )>,
+) {
+    for (mut transform, pendulum, appearance, tracker, material) in query.iter_mut() {
+        let (x, y) = pendulum.to_rectangular();
+        transform.translation = Vec3::new(x, y, 0.0) + pendulum.offset;
+        // The mesh is a unit circle
+        transform.scale = Vec3::splat(appearance.bob_radius);
+
+        if tracker.is_changed() {
+            if let Some(material) = materials.get_mut(material) {
+                material.color = appearance.color();
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn apply_history_capacity(
+    capacity: Res<HistoryCapacity>,
+    mut query: Query<(
+        &mut Pendulum,
+        Option<&mut PID>,
+        Option<&mut LQR>,
+        Option<&mut KalmanFilter>,
+        Option<&mut LuenbergerObserver>,
+        Option<&mut SlidingMode>,
+        Option<&mut ReferenceSignal>,
+        Option<&mut Cascade>,
+    )>,
+) {
+    // Checked every frame rather than on a change of the setting, so pendulums spawned or
+    // controllers added later pick it up too, and only stale components get marked changed
+    let stale = |history: &History| history.max_len != capacity.0;
+    for (mut pendulum, pid, lqr, kalman, observer, sliding, reference, cascade) in query.iter_mut()
+    {
+        if let Some(mut kalman) = kalman.filter(|kalman| stale(&kalman.estimate_history)) {
+            kalman.estimate_history.set_max_len(capacity.0);
+        }
+        if let Some(mut observer) = observer.filter(|observer| stale(&observer.estimate_history)) {
+            observer.estimate_history.set_max_len(capacity.0);
+            observer.velocity_history.set_max_len(capacity.0);
+        }
+        if let Some(mut sliding) = sliding.filter(|sliding| stale(&sliding.surface_history)) {
+            sliding.surface_history.set_max_len(capacity.0);
+        }
+        if let Some(mut cascade) = cascade.filter(|cascade| stale(&cascade.error_history)) {
+            cascade.error_history.set_max_len(capacity.0);
+            cascade.velocity_error_history.set_max_len(capacity.0);
+        }
+        if let Some(mut reference) = reference.filter(|reference| stale(&reference.history)) {
+            reference.history.set_max_len(capacity.0);
+        }
+
+        if stale(&pendulum.control_history) {
+            pendulum.control_history.set_max_len(capacity.0);
+            pendulum.angle_history.set_max_len(capacity.0);
+            pendulum.measured_angle_history.set_max_len(capacity.0);
+            pendulum.energy_history.set_max_len(capacity.0);
+            pendulum.disturbance_history.set_max_len(capacity.0);
+        }
+
+        if let Some(mut pid) = pid.filter(|pid| stale(&pid.error_history)) {
+            pid.error_history.set_max_len(capacity.0);
+            pid.accumulator_history.set_max_len(capacity.0);
+        }
+        if let Some(mut lqr) = lqr.filter(|lqr| stale(&lqr.error_history)) {
+            lqr.error_history.set_max_len(capacity.0);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn history(
+    clock: Res<SimulationClock>,
+    mut query: Query<(
+        &mut Pendulum,
+        Option<&mut PID>,
+        Option<&mut LQR>,
+        Option<&Disturbance>,
+        Option<&PeriodicDisturbance>,
+    )>,
+) {
+    for (mut pendulum, pid, lqr, disturbance, periodic) in query.iter_mut() {
+        let applied = disturbance.map_or(0.0, |d| d.applied) + periodic.map_or(0.0, |p| p.applied);
+        pendulum.disturbance_history.push(applied);
+        pendulum.accumulate_effort(clock.dt);
+        let control = pendulum.control;
+        pendulum.control_history.push(control);
+        let (a, measured_a) = (pendulum.a, pendulum.measured_a);
+        pendulum.angle_history.push(a);
+        pendulum.measured_angle_history.push(measured_a);
+        let energy = energy(&pendulum);
+        pendulum.energy_history.push(energy);
+        pendulum.record_trail();
+
+        if let Some(mut pid) = pid {
+            let (error, acc) = match pid.mode {
+                PidMode::Position => (angle_difference(pid.set_point, pendulum.a), pid.accumulator),
+                PidMode::Rate => (pid.da_set_point - pendulum.da, pid.rate_accumulator),
+            };
+            pid.error_history.push(error);
+            pid.accumulator_history.push(acc);
+        }
+
+        if let Some(mut lqr) = lqr {
+            let error = angle_difference(lqr.set_point, pendulum.a);
+            lqr.error_history.push(error);
+        }
+    }
+}
+
+/// Latest input from the keyboard and mouse, applied to pendulums in manual mode
+#[derive(Resource, Default)]
+struct ManualInput {
+    keyboard: f32,
+    mouse: f32,
+}
+
+fn control_pendulum_keyboard(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut manual: ResMut<ManualInput>,
+) {
+    // Arrows typed into a text field move the cursor, not the pendulum
+    if egui_context.ctx_mut().wants_keyboard_input() {
+        manual.keyboard = 0.0;
+        return;
+    }
+
+    let mut input = 0.0;
+    input += if keys.pressed(KeyCode::Left) {
+        -1.0
+    } else {
+        0.0
+    };
+    input += if keys.pressed(KeyCode::Right) {
+        1.0
+    } else {
+        0.0
+    };
+
+    manual.keyboard = input;
+}
+
+fn control_pendulum_mouse(
+    mut egui_context: ResMut<EguiContext>,
+    buttons: Res<Input<MouseButton>>,
+    mut motion_evr: EventReader<MouseMotion>,
+    mut manual: ResMut<ManualInput>,
+    grab: Res<Grab>,
+) {
+    let mut acc = 0.0;
+    let mut moved = false;
+    for ev in motion_evr.iter() {
+        acc += ev.delta.x;
+        moved = true;
+    }
+
+    // Dragging moves the pendulum itself while grabbing
+    if grab.enabled
+        || !buttons.pressed(MouseButton::Left)
+        || egui_context.ctx_mut().wants_pointer_input()
+    {
+        manual.mouse = 0.0;
+        return;
+    };
+
+    // Frames without motion events keep the last drag instead of dropping back to zero
+    if moved {
+        manual.mouse = acc / 5.0;
+    }
+}
+
+/// How far from the center of a bob a click still picks it up
+const GRAB_RADIUS: f32 = 2.0;
+
+/// Dragging pendulums by the bob instead of driving them with the mouse
+#[derive(Resource, Default)]
+struct Grab {
+    enabled: bool,
+    /// Pendulum being dragged and the controller it gets back on release, it is frozen and left
+    /// in manual mode until then
+    held: Option<(Entity, ControllerKind)>,
+}
+
+impl Grab {
+    fn holds(&self, entity: Entity) -> bool {
+        self.held.is_some_and(|(held, _)| held == entity)
+    }
+}
+
+/// Angle about `pivot` that points the rod towards `point`, measured like `Pendulum::a`
+fn angle_about(pivot: Vec2, point: Vec2) -> f32 {
+    let d = point - pivot;
+    wrap_angle(d.x.atan2(-d.y))
+}
+
+fn grab_pendulum(
+    mut egui_context: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut grab: ResMut<Grab>,
+    mut query: Query<(Entity, &mut Pendulum)>,
+) {
+    if keys.just_pressed(KeyCode::G) && !egui_context.ctx_mut().wants_keyboard_input() {
+        grab.enabled = !grab.enabled;
+    }
+
+    if let Some((entity, controller)) = grab.held {
+        if !grab.enabled || !buttons.pressed(MouseButton::Left) {
+            if let Ok((_, mut pendulum)) = query.get_mut(entity) {
+                pendulum.controller = controller;
+            }
+            grab.held = None;
+            return;
+        }
+    }
+    if !grab.enabled {
+        return;
+    }
+
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position())
+        .and_then(|position| {
+            let (camera, transform) = cameras.get_single().ok()?;
+            camera.viewport_to_world(transform, position)
+        })
+        .map(|ray| ray.origin.truncate());
+    let Some(cursor) = cursor else {
+        return;
+    };
+
+    if grab.held.is_none() {
+        if !buttons.just_pressed(MouseButton::Left) || egui_context.ctx_mut().wants_pointer_input()
+        {
+            return;
+        }
+        let nearest = query
+            .iter_mut()
+            .map(|(entity, pendulum)| {
+                let (x, y) = pendulum.to_rectangular();
+                let bob = pendulum.offset.truncate() + Vec2::new(x, y);
+                (entity, bob.distance(cursor))
+            })
+            .filter(|(_, distance)| *distance < GRAB_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        let Some((entity, _)) = nearest else {
+            return;
+        };
+        let (_, mut pendulum) = query.get_mut(entity).unwrap();
+        grab.held = Some((entity, pendulum.controller));
+        pendulum.controller = ControllerKind::Manual;
+    }
+
+    let Some((entity, _)) = grab.held else {
+        return;
+    };
+    if let Ok((_, mut pendulum)) = query.get_mut(entity) {
+        pendulum.a = angle_about(pendulum.offset.truncate(), cursor);
+        pendulum.da = 0.0;
+        pendulum.control = 0.0;
+    }
+}
+
+fn control_pendulum_manual(
+    clock: Res<SimulationClock>,
+    manual: Res<ManualInput>,
+    mut query: Query<&mut Pendulum>,
+) {
+    let input = if manual.keyboard != 0.0 {
+        manual.keyboard
+    } else {
+        manual.mouse
+    };
+
+    for mut pendulum in query.iter_mut() {
+        if pendulum.controller == ControllerKind::Manual && pendulum.control_due() {
+            let period = pendulum.control_period(clock.dt);
+            pendulum.set_control(input, period);
+        }
+    }
+}
+
+fn control_pendulum_pid(clock: Res<SimulationClock>, mut query: Query<(&mut Pendulum, &mut PID)>) {
+    for (mut pendulum, mut pid) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::Pid || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let control = pid.control(&pendulum, period);
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_swingup(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &SwingUp)>,
+) {
+    for (mut pendulum, swing_up) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::SwingUp
+            || !swing_up.is_active(&pendulum)
+            || !pendulum.control_due()
+        {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let control = swing_up.control(&pendulum);
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_lqr(
+    clock: Res<SimulationClock>,
+    mut query: Query<(
+        &mut Pendulum,
+        &mut LQR,
+        Option<&SwingUp>,
+        Option<&KalmanFilter>,
+    )>,
+) {
+    for (mut pendulum, mut lqr, swing_up, kalman) in query.iter_mut() {
+        let active = match pendulum.controller {
+            ControllerKind::Lqr => true,
+            ControllerKind::SwingUp => swing_up.is_some_and(|s| !s.is_active(&pendulum)),
+            _ => false,
+        };
+        if !active || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        if lqr.needs_relinearizing() {
+            lqr.update_model(&pendulum, clock.dt);
+        }
+
+        let (a, da) = match kalman {
+            Some(kalman) => kalman.estimate(),
+            None => (pendulum.measured_a, pendulum.measured_da),
+        };
+
+        // Without a gain the pendulum is left to fall rather than pushed by a stale one
+        let control = lqr.control(&pendulum, (a, da)).unwrap_or(0.0);
+        lqr.integrate(&pendulum, a, control, period);
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_bangbang(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut BangBang)>,
+) {
+    for (mut pendulum, mut bang_bang) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::BangBang || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let error = angle_difference(pendulum.measured_a, bang_bang.set_point);
+        let control = bang_bang.update(error);
+
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_sliding(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut SlidingMode)>,
+) {
+    for (mut pendulum, mut sliding) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::SlidingMode || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let s = sliding.surface((pendulum.measured_a, pendulum.measured_da));
+        sliding.surface_history.push(s);
+        let control = sliding.control(s);
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_park(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut Park)>,
+) {
+    for (mut pendulum, mut park) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::Park || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let control = park.control((pendulum.measured_a, pendulum.measured_da));
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_cascade(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut Cascade)>,
+) {
+    for (mut pendulum, mut cascade) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::Cascade || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let control = cascade.control(&pendulum, period);
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_scheduled(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut GainSchedule)>,
+) {
+    for (mut pendulum, mut schedule) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::GainSchedule
+            || schedule.angles.is_empty()
+            || !pendulum.control_due()
+        {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+        if schedule.gains.len() != schedule.angles.len()
+            && (schedule.error.is_some() || schedule.compute_gains(&pendulum, clock.dt).is_err())
+        {
+            pendulum.set_control(0.0, period);
+            continue;
+        }
+
+        let k = schedule.gain(pendulum.measured_a);
+        let x = Matrix2x1::new(
+            angle_difference(pendulum.measured_a, schedule.set_point),
+            pendulum.measured_da,
+        );
+        let u = -k * x;
+
+        let control = *u.index(0) + schedule.feedforward.control(&pendulum, schedule.set_point);
+        pendulum.set_control(control, period);
+    }
+}
+
+fn control_pendulum_poleplace(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut PolePlacement)>,
+) {
+    for (mut pendulum, mut placement) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::PolePlacement || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let k = match ackermann(
+            pendulum.get_system(placement.set_point, clock.dt),
+            placement.poles,
+        ) {
+            Ok(k) => k,
+            Err(err) => {
+                placement.error = Some(err);
+                pendulum.set_control(0.0, period);
+                continue;
+            }
+        };
+        placement.error = None;
+
+        let x = Matrix2x1::new(
+            angle_difference(pendulum.measured_a, placement.set_point),
+            pendulum.measured_da,
+        );
+
+        let u = -k * x;
+
+        pendulum.set_control(*u.index(0), period);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn debug_draw(
+    mut lines: ResMut<DebugLines>,
+    query: Query<(
+        &Pendulum,
+        &Appearance,
+        &Stability,
+        Option<&PID>,
+        Option<&LQR>,
+        Option<&Selected>,
+    )>,
+) {
+    for (pendulum, appearance, stability, pid, lqr, selected) in query.iter() {
+        let (x, y) = pendulum.to_rectangular();
+        let arm = if selected.is_some() {
+            Color::YELLOW
+        } else {
+            appearance.color()
+        };
+        let bob = Vec3::new(x, y, 0.0);
+        let normal = Vec3::new(-y, x, 0.0).normalize_or_zero();
+        for offset in arm_offsets(normal, appearance.thickness) {
+            lines.line_colored(
+                pendulum.offset + offset,
+                pendulum.offset + bob + offset,
+                0.0,
+                arm,
+            );
+        }
+
+        for (from, to) in dot_marker(pendulum.offset, PIVOT_MARKER_RADIUS) {
+            lines.line_colored(from, to, 0.0, Color::WHITE);
+        }
+
+        // Up and to the right of the bob, clear of the arm in most positions
+        if let Some(status) = stability.status {
+            let clearance = appearance.bob_radius + 2.0 * STATUS_MARKER_RADIUS;
+            let [r, g, b, _] = status.color().to_array();
+            let center = pendulum.offset + bob + Vec3::new(clearance, clearance, 0.0);
+            for (from, to) in dot_marker(center, STATUS_MARKER_RADIUS) {
+                lines.line_colored(from, to, 0.0, Color::rgb_u8(r, g, b));
+            }
+        }
+
+        // Fades in from the oldest position
+        let trail = &pendulum.trail;
+        for (i, (from, to)) in trail.iter().zip(trail.iter().skip(1)).enumerate() {
+            let alpha = (i + 1) as f32 / trail.len() as f32;
+            lines.line_colored(
+                pendulum.offset + from.extend(0.0),
+                pendulum.offset + to.extend(0.0),
+                0.0,
+                *appearance.color().set_a(alpha),
+            );
+        }
+
+        for stop in [pendulum.min_angle, pendulum.max_angle]
+            .into_iter()
+            .flatten()
+        {
+            let (x, y) = to_rectangular(pendulum.length, stop);
+            let stop = Vec3::new(x, y, 0.0);
+            lines.line_colored(
+                pendulum.offset + stop * 0.85,
+                pendulum.offset + stop * 1.15,
+                0.0,
+                Color::RED,
+            );
+        }
+
+        if let Some(pid) = pid {
+            let (x, y) = to_rectangular(pendulum.length, pid.set_point);
+            lines.line(pendulum.offset, Vec3::new(x, y, 0.0) + pendulum.offset, 0.0);
+        }
+
+        if let Some(lqr) = lqr {
+            let (x, y) = to_rectangular(pendulum.length, lqr.set_point);
+            lines.line(pendulum.offset, Vec3::new(x, y, 0.0) + pendulum.offset, 0.0);
+        }
+    }
+}
+
+/// Formats history columns as CSV with a leading time column, leaving cells empty where a column
+/// has no sample for that time
+fn history_csv(dt: f32, columns: &[(&str, &History)]) -> String {
+    let start = columns.iter().map(|(_, h)| h.start()).min().unwrap_or(0);
+    let end = columns.iter().map(|(_, h)| h.end()).max().unwrap_or(0);
+
+    let mut csv = String::from("t");
+    for (name, _) in columns {
+        csv.push(',');
+        csv.push_str(name);
+    }
+    csv.push('\n');
+
+    for i in start..end {
+        csv.push_str(&format!("{}", i as f32 * dt));
+        for (_, history) in columns {
+            csv.push(',');
+            if let Some(value) = history.get(i) {
+                csv.push_str(&format!("{}", value));
+            }
+        }
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn export_csv(directory: &str, index: usize, csv: &str) -> std::io::Result<PathBuf> {
+    let timestamp = unix_timestamp();
+    let path = Path::new(directory).join(format!("pendulum_{}_{}.csv", index, timestamp));
+    fs::write(&path, csv)?;
+    Ok(path)
+}
+
+/// Default fraction of the initial error the response has to stay within to count as settled
+const SETTLING_BAND: f32 = 0.02;
+
+/// Settling band used by the step metrics and drawn around the set point of the tracking plot,
+/// as a fraction of the initial error
+#[derive(Resource, Clone, Copy)]
+struct SettlingBand(f32);
+
+impl Default for SettlingBand {
+    fn default() -> Self {
+        Self(SETTLING_BAND)
+    }
+}
+
+/// Set point and half width of the settling band, `fraction` of the last `(from, to)` step the
+/// set point took so the band keeps its width as the history scrolls. `None` without a step
+fn settling_band(set_point: f32, step: Option<(f32, f32)>, fraction: f32) -> Option<(f32, f32)> {
+    let (from, to) = step?;
+    let size = angle_difference(to, from).abs();
+    (size > 0.0).then_some((set_point, fraction * size))
+}
+/// Values tried for each LQR cost by `LQR::auto_tune`
+const TUNING_GRID: [f32; 5] = [0.1, 0.3, 1.0, 3.0, 10.0];
+/// Simulated seconds of response scored for each candidate
+const TUNING_HORIZON: f32 = 10.0;
+/// Starting error of the tuning response
+const TUNING_OFFSET: f32 = 0.3;
+/// Weight of the integrated squared control against settling time
+const TUNING_EFFORT_WEIGHT: f32 = 0.1;
+
+/// Step response figures of merit
+#[derive(Clone, Copy)]
+struct StepMetrics {
+    /// Time from first covering 10% of the initial error to first covering 90% of it
+    rise_time: Option<f32>,
+    /// Furthest excursion past the set point, as a percentage of the initial error
+    overshoot: f32,
+    /// Time from the first sample until the error stays within the settling band
+    settling_time: Option<f32>,
+}
+
+fn step_metrics(errors: &[f32], dt: f32, band: f32) -> Option<StepMetrics> {
+    let initial = *errors.first()?;
+    if initial == 0.0 {
+        return None;
+    }
+
+    let past = errors
+        .iter()
+        .map(|e| -e * initial.signum())
+        .fold(0.0, f32::max);
+
+    let covered = |fraction: f32| errors.iter().position(|e| 1.0 - e / initial >= fraction);
+    let rise_time = match (covered(0.1), covered(0.9)) {
+        (Some(start), Some(end)) => Some((end - start) as f32 * dt),
+        _ => None,
+    };
+
+    let band = band * initial.abs();
+    let settling_time = match errors.iter().rposition(|e| e.abs() > band) {
+        Some(last) if last + 1 == errors.len() => None,
+        Some(last) => Some((last + 1) as f32 * dt),
+        None => Some(0.0),
+    };
+
+    Some(StepMetrics {
+        rise_time,
+        overshoot: 100.0 * past / initial.abs(),
+        settling_time,
+    })
+}
+
+fn ui_step_metrics(ui: &mut egui::Ui, errors: &[f32], dt: f32, band: f32) {
+    if let Some(metrics) = step_metrics(errors, dt, band) {
+        ui_metrics(ui, &metrics);
+    }
+}
+
+fn ui_metrics(ui: &mut egui::Ui, metrics: &StepMetrics) {
+    match metrics.rise_time {
+        Some(t) => ui.label(format!("Rise time: {:.2}s", t)),
+        None => ui.label("Rise time: not reached"),
+    };
+    ui.label(format!("Overshoot: {:.1}%", metrics.overshoot));
+    match metrics.settling_time {
+        Some(t) => ui.label(format!("Settling time: {:.2}s", t)),
+        None => ui.label("Settling time: not settled"),
+    };
+}
+
+/// Seconds at the end of the history averaged into the steady-state error
+const STEADY_STATE_WINDOW: f32 = 1.0;
+
+/// One line of the controller summary, all figures from the angle history since the last reset
+struct SummaryRow {
+    name: String,
+    gains: String,
+    metrics: Option<StepMetrics>,
+    effort: f32,
+    /// Mean absolute error over the last `STEADY_STATE_WINDOW`, so an oscillation does not
+    /// average out to nothing
+    steady_state_error: f32,
+}
+
+impl SummaryRow {
+    const HEADER: [&'static str; 6] = [
+        "Pendulum",
+        "Gains",
+        "Overshoot (%)",
+        "Settling time (s)",
+        "Control effort",
+        "Steady-state error",
+    ];
+
+    fn new(name: String, gains: String, errors: &[f32], effort: f32, dt: f32, band: f32) -> Self {
+        let window = (STEADY_STATE_WINDOW / dt).ceil().max(1.0) as usize;
+        let tail = &errors[errors.len().saturating_sub(window)..];
+        let steady_state_error = if tail.is_empty() {
+            0.0
+        } else {
+            tail.iter().map(|e| e.abs()).sum::<f32>() / tail.len() as f32
+        };
+
+        Self {
+            name,
+            gains,
+            metrics: step_metrics(errors, dt, band),
+            effort,
+            steady_state_error,
+        }
+    }
+
+    /// Formatted values in the order of `HEADER`
+    fn cells(&self) -> [String; 6] {
+        let (overshoot, settling_time) = match &self.metrics {
+            Some(metrics) => (
+                format!("{:.1}", metrics.overshoot),
+                match metrics.settling_time {
+                    Some(t) => format!("{:.2}", t),
+                    None => "not settled".to_string(),
+                },
+            ),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        [
+            self.name.clone(),
+            self.gains.clone(),
+            overshoot,
+            settling_time,
+            format!("{:.3}", self.effort),
+            format!("{:.4}", self.steady_state_error),
+        ]
+    }
+}
+
+fn summary_csv(rows: &[SummaryRow]) -> String {
+    // Gains are listed with commas, so fields get quoted where needed
+    let field = |cell: &str| {
+        if cell.contains([',', '"']) {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell.to_string()
+        }
+    };
+    let mut csv = SummaryRow::HEADER.join(",");
+    csv.push('\n');
+    for row in rows {
+        let cells: Vec<String> = row.cells().iter().map(|cell| field(cell)).collect();
+        csv.push_str(&cells.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn summary_markdown(rows: &[SummaryRow]) -> String {
+    let line = |cells: &[String]| format!("| {} |\n", cells.join(" | "));
+    let header: Vec<String> = SummaryRow::HEADER.iter().map(|h| h.to_string()).collect();
+    let mut markdown = line(&header);
+    markdown.push_str(&line(&vec!["---".to_string(); header.len()]));
+    for row in rows {
+        let cells: Vec<String> = row.cells().iter().map(|c| c.replace('|', "\\|")).collect();
+        markdown.push_str(&line(&cells));
+    }
+    markdown
+}
+
+fn export_summary(directory: &str, extension: &str, contents: &str) -> std::io::Result<PathBuf> {
+    let path = Path::new(directory).join(format!("summary_{}.{}", unix_timestamp(), extension));
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// Parameter sets kept for each pendulum's undo, older ones get dropped
+const UNDO_DEPTH: usize = 20;
+
+/// What moves a pendulum's set points by itself, undo leaves those set points to it
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize)]
+enum SetPointDriver {
+    #[default]
+    None,
+    /// Moves the PID and LQR set points, see `ramp_setpoints`
+    Ramp,
+    /// Moves every set point, see `track_reference`
+    Reference,
+}
+
+impl SetPointDriver {
+    /// The reference signal is applied after the ramp, so it wins when there are both
+    fn new(ramp: bool, reference: bool) -> Self {
+        match (ramp, reference) {
+            (_, true) => SetPointDriver::Reference,
+            (true, false) => SetPointDriver::Ramp,
+            (false, false) => SetPointDriver::None,
+        }
+    }
+
+    fn drives(self, controller: ControllerKind) -> bool {
+        match self {
+            SetPointDriver::None => false,
+            SetPointDriver::Ramp => matches!(controller, ControllerKind::Pid | ControllerKind::Lqr),
+            SetPointDriver::Reference => true,
+        }
+    }
+}
+
+/// Controller parameters of one pendulum, with the runtime state like accumulators and histories
+/// cleared since undo leaves that with the live components. Set points that something else is
+/// moving are left out as well, they are not edits and putting them back would fight the driver
+#[derive(Clone, Default, Serialize)]
+struct TuningSnapshot {
+    driver: SetPointDriver,
+    pid: Option<PID>,
+    lqr: Option<LQR>,
+    swing_up: Option<SwingUp>,
+    pole_placement: Option<PolePlacement>,
+    bang_bang: Option<BangBang>,
+    mpc: Option<Mpc>,
+    gain_schedule: Option<GainSchedule>,
+    sliding_mode: Option<SlidingMode>,
+}
+
+impl TuningSnapshot {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        pid: Option<&PID>,
+        lqr: Option<&LQR>,
+        swing_up: Option<&SwingUp>,
+        pole_placement: Option<&PolePlacement>,
+        bang_bang: Option<&BangBang>,
+        mpc: Option<&Mpc>,
+        gain_schedule: Option<&GainSchedule>,
+        sliding_mode: Option<&SlidingMode>,
+        driver: SetPointDriver,
+    ) -> Self {
+        let held = |controller, set_point| {
+            if driver.drives(controller) {
+                0.0
+            } else {
+                set_point
+            }
+        };
+        Self {
+            driver,
+            pid: pid.map(|pid| PID {
+                set_point: held(ControllerKind::Pid, pid.set_point),
+                accumulator: 0.0,
+                filtered_derivative: 0.0,
+                previous_error: 0.0,
+                previous_derivative: 0.0,
+                previous_acceleration: 0.0,
+                rate_accumulator: 0.0,
+                last_output: 0.0,
+                error_history: default(),
+                accumulator_history: default(),
+                ..pid.clone()
+            }),
+            lqr: lqr.map(|lqr| LQR {
+                set_point: held(ControllerKind::Lqr, lqr.set_point),
+                error_history: default(),
+                ..lqr.clone()
+            }),
+            swing_up: swing_up.cloned(),
+            pole_placement: pole_placement.map(|placement| PolePlacement {
+                set_point: held(ControllerKind::PolePlacement, placement.set_point),
+                ..placement.clone()
+            }),
+            bang_bang: bang_bang.map(|bang| BangBang {
+                set_point: held(ControllerKind::BangBang, bang.set_point),
+                last_sign: 0.0,
+                ..bang.clone()
+            }),
+            mpc: mpc.map(|mpc| Mpc {
+                set_point: held(ControllerKind::Mpc, mpc.set_point),
+                plan: Vec::new(),
+                predicted: Vec::new(),
+                ..mpc.clone()
+            }),
+            gain_schedule: gain_schedule.map(|schedule| GainSchedule {
+                set_point: held(ControllerKind::GainSchedule, schedule.set_point),
+                ..schedule.clone()
+            }),
+            sliding_mode: sliding_mode.map(|sliding| SlidingMode {
+                set_point: held(ControllerKind::SlidingMode, sliding.set_point),
+                surface_history: default(),
+                ..sliding.clone()
+            }),
+        }
+    }
+
+    /// Compares what a saved config would hold, which leaves out the skipped runtime state
+    fn same_parameters(&self, other: &Self) -> bool {
+        ron::to_string(self).ok() == ron::to_string(other).ok()
+    }
+
+    /// Puts the parameters back on the components that are still there, keeping their runtime
+    /// state. Models and gains get rebuilt by the control systems on their next step
+    #[allow(clippy::too_many_arguments)]
+    fn restore(
+        &self,
+        pid: Option<&mut PID>,
+        lqr: Option<&mut LQR>,
+        swing_up: Option<&mut SwingUp>,
+        pole_placement: Option<&mut PolePlacement>,
+        bang_bang: Option<&mut BangBang>,
+        mpc: Option<&mut Mpc>,
+        gain_schedule: Option<&mut GainSchedule>,
+        sliding_mode: Option<&mut SlidingMode>,
+    ) {
+        let kept = |controller, live: f32, saved: f32| {
+            if self.driver.drives(controller) {
+                live
+            } else {
+                saved
+            }
+        };
+        if let (Some(live), Some(pid)) = (pid, &self.pid) {
+            *live = PID {
+                set_point: kept(ControllerKind::Pid, live.set_point, pid.set_point),
+                accumulator: live.accumulator,
+                filtered_derivative: live.filtered_derivative,
+                previous_error: live.previous_error,
+                previous_derivative: live.previous_derivative,
+                previous_acceleration: live.previous_acceleration,
+                rate_accumulator: live.rate_accumulator,
+                previous_proportional_gain: live.previous_proportional_gain,
+                last_output: live.last_output,
+                error_history: std::mem::take(&mut live.error_history),
+                accumulator_history: std::mem::take(&mut live.accumulator_history),
+                ..pid.clone()
+            };
+        }
+        if let (Some(live), Some(lqr)) = (lqr, &self.lqr) {
+            *live = LQR {
+                set_point: kept(ControllerKind::Lqr, live.set_point, lqr.set_point),
+                error_history: std::mem::take(&mut live.error_history),
+                linearized_at: None,
+                ..lqr.clone()
+            };
+        }
+        if let (Some(live), Some(swing_up)) = (swing_up, &self.swing_up) {
+            *live = swing_up.clone();
+        }
+        if let (Some(live), Some(placement)) = (pole_placement, &self.pole_placement) {
+            *live = PolePlacement {
+                set_point: kept(
+                    ControllerKind::PolePlacement,
+                    live.set_point,
+                    placement.set_point,
+                ),
+                ..placement.clone()
+            };
+        }
+        if let (Some(live), Some(bang)) = (bang_bang, &self.bang_bang) {
+            *live = BangBang {
+                set_point: kept(ControllerKind::BangBang, live.set_point, bang.set_point),
+                last_sign: live.last_sign,
+                ..bang.clone()
+            };
+        }
+        if let (Some(live), Some(mpc)) = (mpc, &self.mpc) {
+            *live = Mpc {
+                set_point: kept(ControllerKind::Mpc, live.set_point, mpc.set_point),
+                plan: std::mem::take(&mut live.plan),
+                predicted: std::mem::take(&mut live.predicted),
+                ..mpc.clone()
+            };
+        }
+        if let (Some(live), Some(schedule)) = (gain_schedule, &self.gain_schedule) {
+            *live = GainSchedule {
+                set_point: kept(
+                    ControllerKind::GainSchedule,
+                    live.set_point,
+                    schedule.set_point,
+                ),
+                gains: Vec::new(),
+                error: None,
+                ..schedule.clone()
+            };
+        }
+        if let (Some(live), Some(sliding)) = (sliding_mode, &self.sliding_mode) {
+            *live = SlidingMode {
+                set_point: kept(
+                    ControllerKind::SlidingMode,
+                    live.set_point,
+                    sliding.set_point,
+                ),
+                surface_history: std::mem::take(&mut live.surface_history),
+                ..sliding.clone()
+            };
+        }
+    }
+}
+
+#[derive(Default)]
+struct UndoStack {
+    snapshots: VecDeque<TuningSnapshot>,
+    /// Parameters from when the pointer went down, pushed once it is back up if they changed
+    pending: Option<TuningSnapshot>,
+    /// Set by the undo button, handled on the next frame along with Ctrl+Z
+    requested: bool,
+}
+
+impl UndoStack {
+    fn push(&mut self, snapshot: TuningSnapshot) {
+        if self.snapshots.len() == UNDO_DEPTH {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+}
+
+#[derive(Default)]
+struct UndoStacks {
+    stacks: HashMap<Entity, UndoStack>,
+    /// Pendulum Ctrl+Z applies to
+    last_edited: Option<Entity>,
+}
+
+/// Plot points with x in simulated seconds, independent of the time scale
+fn to_points(history: &History, dt: f32) -> PlotPoints {
+    history
+        .iter()
+        .map(|(i, v)| [(i as f64) * (dt as f64), v as f64])
+        .collect()
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn ui_example(
+    mut egui_context: ResMut<EguiContext>,
+    clock: Res<SimulationClock>,
+    export: Res<ExportSettings>,
+    mut pendulum_events: EventWriter<PendulumEvent>,
+    mut resets: EventWriter<PendulumResetEvent>,
+    mut plot_tabs: Local<HashMap<Entity, PlotTab>>,
+    mut plot_settings: Local<HashMap<(Entity, PlotTab), PlotSettings>>,
+    mut undo: Local<UndoStacks>,
+    mut rng: ResMut<AppRng>,
+    ranges: Res<InitialRanges>,
+    mut presets: ResMut<InitialPresets>,
+    layout: Res<PendulumLayout>,
+    settling: Res<SettlingBand>,
+    integrator: Res<IntegratorKind>,
+    mut query: Query<(
+        Entity,
+        (&mut Pendulum, &mut Appearance),
+        Option<&mut PID>,
+        Option<&mut LQR>,
+        Option<&mut SwingUp>,
+        Option<&mut NoiseConfig>,
+        (Option<&mut KalmanFilter>, Option<&mut LuenbergerObserver>),
+        (Option<&mut SetpointRamp>, Option<&mut ReferenceSignal>),
+        Option<&mut PolePlacement>,
+        Option<&mut BangBang>,
+        Option<&mut Mpc>,
+        Option<&mut GainSchedule>,
+        (
+            Option<&mut SlidingMode>,
+            Option<&mut Park>,
+            Option<&mut Cascade>,
+        ),
+        (Option<&mut Disturbance>, Option<&mut PeriodicDisturbance>),
+        (
+            Option<&FrictionIdentification>,
+            Option<&FrequencySweep>,
+            (Option<&mut StepTest>, Option<&mut ImpulseTest>),
+            Option<&mut LinearizationCheck>,
+            &Stability,
+        ),
+    )>,
+) {
+    // A snapshot is taken when the pointer goes down and compared a frame after it comes back up,
+    // so clicks that only change things on release are caught as well
+    let ctx = egui_context.ctx_mut();
+    let undo_key = !ctx.wants_keyboard_input()
+        && ctx.input().modifiers.command
+        && ctx.input().key_pressed(egui::Key::Z);
+    let (pressed, settled) = {
+        let pointer = &ctx.input().pointer;
+        (
+            pointer.any_pressed(),
+            !pointer.any_down() && !pointer.any_released(),
+        )
+    };
+
+    for (
+        i,
+        (
+            entity,
+            (mut pendulum, mut appearance),
+            mut pid,
+            mut lqr,
+            mut swing_up,
+            noise,
+            (kalman, mut observer),
+            (ramp, mut reference),
+            mut placement,
+            mut bang_bang,
+            mut mpc,
+            mut schedule,
+            (mut sliding, park, mut cascade),
+            (disturbance, periodic),
+            (identification, sweep, (mut step_test, impulse), linearization, stability),
+        ),
+    ) in query.iter_mut().enumerate()
+    {
+        let key_undo = undo_key && undo.last_edited == Some(entity);
+        let stack = undo.stacks.entry(entity).or_default();
+        let driver = SetPointDriver::new(ramp.is_some(), reference.is_some());
+        let current = || {
+            TuningSnapshot::new(
+                pid.as_deref(),
+                lqr.as_deref(),
+                swing_up.as_deref(),
+                placement.as_deref(),
+                bang_bang.as_deref(),
+                mpc.as_deref(),
+                schedule.as_deref(),
+                sliding.as_deref(),
+                driver,
+            )
+        };
+        let mut edited = false;
+        if settled {
+            if let Some(before) = stack.pending.take() {
+                if !before.same_parameters(&current()) {
+                    stack.push(before);
+                    edited = true;
+                }
+            }
+        }
+        if pressed {
+            stack.pending = Some(current());
+        }
+        if std::mem::take(&mut stack.requested) || key_undo {
+            if let Some(snapshot) = stack.snapshots.pop_back() {
+                stack.pending = None;
+                snapshot.restore(
+                    pid.as_deref_mut(),
+                    lqr.as_deref_mut(),
+                    swing_up.as_deref_mut(),
+                    placement.as_deref_mut(),
+                    bang_bang.as_deref_mut(),
+                    mpc.as_deref_mut(),
+                    schedule.as_deref_mut(),
+                    sliding.as_deref_mut(),
+                );
+            }
+        }
+        let can_undo = !stack.snapshots.is_empty();
+        if edited {
+            undo.last_edited = Some(entity);
+        }
+        let mut undo_clicked = false;
+
+        let title = status_title(
+            egui_context.ctx_mut(),
+            stability.status,
+            "Pendulum settings",
+        );
+        egui::Window::new(title)
+            .id(Id::new(entity))
+            .resizable(true)
+            .default_pos(layout.window_pos(i))
+            .show(egui_context.ctx_mut(), |ui| {
+                let mut controller = pendulum.controller;
+                egui::ComboBox::from_label("Controller")
+                    .selected_text(controller.name())
+                    .show_ui(ui, |ui| {
+                        for kind in ControllerKind::ALL {
+                            ui.selectable_value(&mut controller, kind, kind.name());
+                        }
+                    });
+                if controller != pendulum.controller {
+                    let present = match controller {
+                        ControllerKind::Manual => true,
+                        ControllerKind::Pid => pid.is_some(),
+                        ControllerKind::Lqr => lqr.is_some(),
+                        ControllerKind::SwingUp => swing_up.is_some(),
+                        ControllerKind::BangBang => bang_bang.is_some(),
+                        ControllerKind::PolePlacement => placement.is_some(),
+                        ControllerKind::Mpc => mpc.is_some(),
+                        ControllerKind::GainSchedule => schedule.is_some(),
+                        ControllerKind::SlidingMode => sliding.is_some(),
+                        ControllerKind::Park => park.is_some(),
+                        ControllerKind::Cascade => cascade.is_some(),
+                    };
+                    if !present {
+                        pendulum_events.send(PendulumEvent::AddController(entity, controller));
+                    }
+                    pendulum.controller = controller;
+                    pendulum.control = 0.0;
+                }
+                if ui
+                    .checkbox(&mut pendulum.control_enabled, "Control enabled")
+                    .changed()
+                    && pendulum.control_enabled
+                {
+                    // What was integrated or queued before it fell no longer fits where it is now
+                    if let Some(pid) = pid.as_deref_mut() {
+                        pid.clear_integral();
+                    }
+                    if let Some(lqr) = lqr.as_deref_mut() {
+                        lqr.integral = 0.0;
+                    }
+                    if let Some(cascade) = cascade.as_deref_mut() {
+                        cascade.accumulator = 0.0;
+                    }
+                    pendulum.release_control();
+                }
+
+                // Edited on a copy so the material only gets touched when something changed
+                let mut look = appearance.clone();
+                ui.horizontal(|ui| {
+                    ui.color_edit_button_rgb(&mut look.color);
+                    if ui.button("Controller color").clicked() {
+                        look.color = pendulum.controller.color();
+                    }
+                    ui.add(
+                        egui::DragValue::new(&mut look.bob_radius)
+                            .clamp_range(0.2..=3.0)
+                            .speed(0.05)
+                            .prefix("bob "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut look.thickness)
+                            .clamp_range(0.0..=1.0)
+                            .speed(0.01)
+                            .prefix("arm "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut pendulum.trail_length)
+                            .clamp_range(0..=1000)
+                            .prefix("trail "),
+                    );
+                });
+                if look != *appearance {
+                    *appearance = look;
+                }
+
+                ui.horizontal(|ui| {
+                    if let Some(status) = stability.status {
+                        ui.colored_label(status.color(), status.name());
+                    }
+                    ui.label(format!("Control effort: {:.3}", pendulum.effort));
+                    undo_clicked = ui
+                        .add_enabled(can_undo, egui::Button::new("Undo (Ctrl+Z)"))
+                        .clicked();
+                });
+
+                ui.label("Pendulum");
+                let model_before = pendulum.model();
+                ui.add(egui::Slider::new(&mut pendulum.length, 0.0..=20.0).text("length"));
+                // Drawing, grabbing and the trail all follow the pivot as it moves
+                ui.horizontal(|ui| {
+                    ui.label("Pivot");
+                    ui.add(egui::Slider::new(&mut pendulum.offset.x, -100.0..=100.0).text("x"));
+                    ui.add(egui::Slider::new(&mut pendulum.offset.y, -60.0..=60.0).text("y"));
+                });
+                ui.horizontal(|ui| {
+                    let mut mode = pendulum.control_mode;
+                    ui.label("Control");
+                    ui.radio_value(&mut mode, ControlMode::Normalized, "Normalized");
+                    ui.radio_value(&mut mode, ControlMode::Torque, "Torque");
+                    if mode != pendulum.control_mode {
+                        pendulum.set_control_mode(mode);
+                    }
+                });
+                // Gravity scales with the mass too, so only torque control feels it
+                ui.add_enabled(
+                    pendulum.control_mode == ControlMode::Torque,
+                    egui::Slider::new(&mut pendulum.mass, 0.1..=10.0)
+                        .logarithmic(true)
+                        .text("Mass"),
+                );
+                ui.add_enabled(
+                    pendulum.control_mode == ControlMode::Normalized,
+                    egui::Slider::new(&mut pendulum.control_power, 0.0..=20.0)
+                        .text("Control power"),
+                );
+                ui.add(egui::Slider::new(&mut pendulum.gravity, 0.0..=25.0).text("Gravity"));
+                ui.add(
+                    egui::Slider::new(&mut pendulum.coulomb_friction, 0.0..=1.0)
+                        .text("Coulomb friction"),
+                );
+                let Pendulum {
+                    min_angle,
+                    max_angle,
+                    ..
+                } = &mut *pendulum;
+                for (name, stop, default) in [
+                    ("Min stop", min_angle, PI / 2.0),
+                    ("Max stop", max_angle, 3.0 * PI / 2.0),
+                ] {
+                    ui.horizontal(|ui| {
+                        let mut enabled = stop.is_some();
+                        if ui.checkbox(&mut enabled, name).changed() {
+                            *stop = enabled.then_some(default);
+                        }
+                        if let Some(angle) = stop {
+                            ui.add(egui::Slider::new(angle, 0.0..=2.0 * PI));
+                        }
+                    });
+                }
+                if pendulum.min_angle.is_some() || pendulum.max_angle.is_some() {
+                    ui.add(
+                        egui::Slider::new(&mut pendulum.restitution, 0.0..=1.0).text("Restitution"),
+                    );
+                }
+
+                let mut separate_model = pendulum.model.is_some();
+                if ui
+                    .checkbox(&mut separate_model, "Separate controller model")
+                    .changed()
+                {
+                    pendulum.model = separate_model.then(|| pendulum.params());
+                }
+                if let Some(model) = &mut pendulum.model {
+                    ui.add(egui::Slider::new(&mut model.length, 0.0..=20.0).text("Model length"));
+                    ui.add(
+                        egui::Slider::new(&mut model.friction, 0.0..=2.0).text("Model friction"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut model.coulomb_friction, 0.0..=1.0)
+                            .text("Model Coulomb friction"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut model.control_power, 0.0..=20.0)
+                            .text("Model control power"),
+                    );
+                    ui.add(egui::Slider::new(&mut model.gravity, 0.0..=25.0).text("Model gravity"));
+                }
+
+                // The cached gain only needs solving again when the model it was designed on moved
+                if pendulum.model() != model_before {
+                    if let Some(lqr) = &mut lqr {
+                        lqr.update_model(&pendulum, clock.dt);
+                    }
+                    if let Some(schedule) = &mut schedule {
+                        schedule.gains.clear();
+                        schedule.error = None;
+                    }
+                }
+
+                // Slider ranges follow the units so torque limits stay reachable
+                let unit = match pendulum.control_mode {
+                    ControlMode::Normalized => 1.0,
+                    ControlMode::Torque => (pendulum.control_power * pendulum.inertia()).max(1.0),
+                };
+                ui.add(
+                    egui::Slider::new(&mut pendulum.control_min, -2.0 * unit..=0.0)
+                        .text("Control min"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut pendulum.control_max, 0.0..=2.0 * unit)
+                        .text("Control max"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut pendulum.dead_zone, 0.0..=0.5 * unit).text("Dead zone"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut pendulum.control_decimation, 1..=20)
+                        .text("Control decimation"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut pendulum.physics_substeps, 1..=50)
+                        .text("Physics substeps"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut pendulum.control_delay_steps, 0..=20)
+                        .text("Control delay (steps)"),
+                );
+                ui.horizontal(|ui| {
+                    let mut limited = pendulum.max_control_rate.is_finite();
+                    if ui.checkbox(&mut limited, "Rate limit").changed() {
+                        pendulum.max_control_rate = if limited { 10.0 } else { f32::INFINITY };
+                    }
+                    if limited {
+                        ui.add(
+                            egui::Slider::new(&mut pendulum.max_control_rate, 0.1..=100.0)
+                                .logarithmic(true)
+                                .text("Max control rate"),
+                        );
+                    }
+                });
+
+                ui.add(egui::Slider::new(&mut pendulum.a, 0.0..=2.0 * PI).text("Angle"));
+                ui.add(egui::Slider::new(&mut pendulum.da, -10.0..=10.0).text("Speed"));
+
+                ui.label(format!(
+                    "Control: {} ({})",
+                    pendulum.control,
+                    pendulum.control_mode.units()
+                ));
+
+                ui.horizontal(|ui| {
+                    if ui.button("Reset").clicked() {
+                        reset_pendulum(&mut pendulum);
+                        resets.send(PendulumResetEvent(entity));
+                    }
+                    if ui.button("Randomize").clicked() {
+                        randomize_pendulum(&mut pendulum, &mut rng, &ranges);
+                        resets.send(PendulumResetEvent(entity));
+                    }
+                });
+                let mut chosen = None;
+                egui::ComboBox::from_label("Preset")
+                    .selected_text("Choose")
+                    .show_ui(ui, |ui| {
+                        for preset in &presets.presets {
+                            if ui.selectable_label(false, &preset.name).clicked() {
+                                chosen = Some(preset.clone());
+                            }
+                        }
+                    });
+                if let Some(preset) = chosen {
+                    reset_pendulum(&mut pendulum);
+                    resets.send(PendulumResetEvent(entity));
+                    pendulum.a = preset.a;
+                    pendulum.da = preset.da;
+                }
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut presets.new_name);
+                    let name = presets.new_name.trim().to_string();
+                    if ui
+                        .add_enabled(!name.is_empty(), egui::Button::new("Save preset"))
+                        .clicked()
+                    {
+                        presets.save(InitialPreset::new(&name, pendulum.a, pendulum.da));
+                        presets.new_name.clear();
+                    }
+                });
+
+                if ui.button("Export CSV").clicked() {
+                    let empty = History::default();
+                    let (error, accumulator) = match (&pid, &lqr) {
+                        (Some(pid), _) => (&pid.error_history, &pid.accumulator_history),
+                        (None, Some(lqr)) => (&lqr.error_history, &empty),
+                        (None, None) => (&empty, &empty),
+                    };
+                    let csv = history_csv(
+                        clock.dt,
+                        &[
+                            ("control", &pendulum.control_history),
+                            ("energy", &pendulum.energy_history),
+                            ("disturbance", &pendulum.disturbance_history),
+                            ("error", error),
+                            ("accumulator", accumulator),
+                        ],
+                    );
+                    match export_csv(&export.directory, i, &csv) {
+                        Ok(path) => info!("Exported history to {}", path.display()),
+                        Err(err) => error!("Failed to export history: {}", err),
+                    }
+                }
+
+                let save_png = ui.button("Save plot PNG").clicked();
+
+                if reference.is_none() && ui.button("Track reference").clicked() {
+                    pendulum_events.send(PendulumEvent::TrackReference(entity, true));
+                }
+
+                let identifying = identification.is_some_and(|i| i.running);
+                if ui
+                    .add_enabled(!identifying, egui::Button::new("Identify friction"))
+                    .clicked()
+                {
+                    pendulum_events.send(PendulumEvent::IdentifyFriction(entity));
+                }
+                match identification {
+                    Some(i) if i.running => {
+                        ui.label(format!("Identifying friction, {} swings", i.peaks.len()));
+                    }
+                    Some(FrictionIdentification {
+                        estimate: Some(friction),
+                        ..
+                    }) => {
+                        ui.label(format!("Estimated friction: {:.4}", friction));
+                    }
+                    Some(FrictionIdentification {
+                        error: Some(err), ..
+                    }) => {
+                        ui.colored_label(egui::Color32::RED, *err);
+                    }
+                    _ => {}
+                }
+
+                if let Some(step_test) = &mut step_test {
+                    ui_step_test(ui, step_test, pendulum.controller, reference.is_some());
+                }
+                if let Some(mut test) = impulse {
+                    ui_impulse_test(ui, entity, &mut test);
+                }
+                if let Some(mut check) = linearization {
+                    ui_linearization_check(ui, entity, &mut check);
+                }
+
+                if ui.button("Delete").clicked() {
+                    pendulum_events.send(PendulumEvent::Delete(entity));
+                }
+
+                let slider_range = 10.0;
+
+                let mut lines = Vec::new();
+                let mut band = None;
+                // The ramp also moves with a step test, which is all there is without one
+                let last_step = ramp
+                    .as_ref()
+                    .and_then(|ramp| ramp.departed)
+                    .or_else(|| step_test.as_ref().and_then(|test| test.step()));
+                let angle_points: PlotPoints = to_points(&pendulum.angle_history, clock.dt);
+                lines.push((PlotTab::Tracking, "Angle", angle_points));
+                let control_points: PlotPoints = to_points(&pendulum.control_history, clock.dt);
+                lines.push((PlotTab::Control, "Control", control_points));
+                let energy_points: PlotPoints = to_points(&pendulum.energy_history, clock.dt);
+                lines.push((PlotTab::Control, "Energy", energy_points));
+
+                if let Some(mut disturbance) = disturbance {
+                    ui.separator();
+                    ui.label("Disturbance");
+                    ui.add(
+                        egui::Slider::new(&mut disturbance.constant, -2.0..=2.0)
+                            .text("Constant torque"),
+                    );
+                    ui.horizontal(|ui| {
+                        if ui.button("Kick").clicked() {
+                            disturbance.kick();
+                        }
+                        ui.add(
+                            egui::Slider::new(&mut disturbance.torque, -50.0..=50.0)
+                                .text("Kick torque"),
+                        );
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut disturbance.duration, 0.01..=1.0)
+                            .text("Kick duration"),
+                    );
+                    if let Some(mut periodic) = periodic {
+                        ui.add(
+                            egui::Slider::new(&mut periodic.amplitude, 0.0..=2.0)
+                                .text("Periodic amplitude"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut periodic.frequency, 0.01..=5.0)
+                                .logarithmic(true)
+                                .text("Periodic frequency (Hz)"),
+                        );
+                    }
+
+                    let disturbance_points: PlotPoints =
+                        to_points(&pendulum.disturbance_history, clock.dt);
+                    lines.push((PlotTab::Control, "Disturbance", disturbance_points));
+                }
+
+                if let Some(mut noise) = noise {
+                    ui.separator();
+                    ui.label("Measurement noise");
+                    ui.add(
+                        egui::Slider::new(&mut noise.angle_stddev, 0.0..=0.5).text("Angle stddev"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut noise.velocity_stddev, 0.0..=2.0)
+                            .text("Velocity stddev"),
+                    );
+
+                    let measured_points: PlotPoints =
+                        to_points(&pendulum.measured_angle_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "Measured angle", measured_points));
+                }
+
+                if let Some(mut pid) = pid {
+                    ui.separator();
+                    ui.label("PID");
+                    ui.horizontal(|ui| {
+                        ui.label("Mode");
+                        ui.radio_value(&mut pid.mode, PidMode::Position, "Position");
+                        ui.radio_value(&mut pid.mode, PidMode::Rate, "Rate");
+                    });
+                    if pid.mode == PidMode::Rate {
+                        ui.label(format!(
+                            "Velocity error: {}",
+                            pendulum.da - pid.da_set_point
+                        ));
+                        ui.add(
+                            egui::Slider::new(&mut pid.da_set_point, -5.0..=5.0)
+                                .text("Velocity set point"),
+                        );
+                        ui.add(
+                            egui::Slider::new(
+                                &mut pid.rate_proportional_gain,
+                                -slider_range..=slider_range,
+                            )
+                            .text("Rate proportional gain"),
+                        );
+                        ui.add(
+                            egui::Slider::new(
+                                &mut pid.rate_integral_gain,
+                                -slider_range..=slider_range,
+                            )
+                            .text("Rate integral gain"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut pid.tracking_gain, 0.0..=20.0)
+                                .text("Anti-windup tracking gain"),
+                        );
+                    } else {
+                        ui.label(format!(
+                            "Error: {}",
+                            angle_difference(pendulum.a, pid.set_point)
+                        ));
+                        if ramp.is_none() {
+                            // TODO: There's probably a better way to do this
+                            let old_set_point = pid.set_point;
+                            ui.add(
+                                egui::Slider::new(&mut pid.set_point, 0.0..=2.0 * PI)
+                                    .text("Set point"),
+                            );
+                            if pid.set_point != old_set_point {
+                                pid.accumulator = 0.0;
+                                pid.accumulator_enabled = false;
+                            }
+                        }
+                        let pid = &mut *pid;
+                        ui.horizontal(|ui| {
+                            let mut previewing = pid.preview.is_some();
+                            if ui.checkbox(&mut previewing, "Preview gains").changed() {
+                                pid.preview = previewing.then(|| GainPreview::new(pid.gains()));
+                            }
+                            if let Some(preview) = &pid.preview {
+                                let gains = preview.gains;
+                                if ui
+                                    .add_enabled(gains != pid.gains(), egui::Button::new("Apply"))
+                                    .clicked()
+                                {
+                                    pid.set_gains(gains);
+                                }
+                            }
+                        });
+                        // While previewing the sliders move the candidate gains, the live ones
+                        // only change on "Apply"
+                        let [p, i, d, a] = match &mut pid.preview {
+                            Some(GainPreview {
+                                gains: [p, i, d, a],
+                                ..
+                            }) => [p, i, d, a],
+                            None => [
+                                &mut pid.proportional_gain,
+                                &mut pid.integral_gain,
+                                &mut pid.derivative_gain,
+                                &mut pid.acceleration_gain,
+                            ],
+                        };
+                        for (enabled, gain, name) in [
+                            (&mut pid.p_enabled, p, "Proportional gain"),
+                            (&mut pid.i_enabled, i, "Integral gain"),
+                            (&mut pid.d_enabled, d, "Derivative gain"),
+                            (&mut pid.a_enabled, a, "Acceleration gain"),
+                        ] {
+                            ui.horizontal(|ui| {
+                                ui.checkbox(enabled, "");
+                                ui.add_enabled(
+                                    *enabled,
+                                    egui::Slider::new(gain, -slider_range..=slider_range)
+                                        .text(name),
+                                );
+                            });
+                        }
+                        match ui_baseline(ui, pid.baseline.is_some()) {
+                            (true, _) => pid.set_baseline(),
+                            (_, true) => pid.restore_baseline(),
+                            _ => {}
+                        }
+                        // Checked again whenever the gains move, the button covers changes to
+                        // the pendulum
+                        ui.horizontal(|ui| {
+                            let gains = pid.gains();
+                            let recheck = ui.button("Check gain signs").clicked();
+                            if recheck || pid.sign_check.map(|(checked, _)| checked) != Some(gains)
+                            {
+                                let ratio = pid.sign_check(&pendulum, *integrator, clock.dt);
+                                pid.sign_check = Some((gains, ratio));
+                            }
+                            let Some((_, ratio)) = pid.sign_check else {
+                                return;
+                            };
+                            if ratio > 1.0 {
+                                ui.colored_label(
+                                    egui::Color32::YELLOW,
+                                    format!(
+                                        "Grows the error {:.1}x, try flipping the gain signs",
+                                        ratio
+                                    ),
+                                );
+                            } else {
+                                ui.label(format!(
+                                    "Leaves {:.0}% of the uncontrolled error",
+                                    ratio * 100.0
+                                ));
+                            }
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut pid.derivative_filter_tau, 0.0..=1.0)
+                                .text("Derivative filter tau"),
+                        );
+                        egui::ComboBox::from_label("Anti-windup")
+                            .selected_text(pid.anti_windup.name())
+                            .show_ui(ui, |ui| {
+                                for strategy in AntiWindup::ALL {
+                                    ui.selectable_value(
+                                        &mut pid.anti_windup,
+                                        strategy,
+                                        strategy.name(),
+                                    );
+                                }
+                            });
+                        match pid.anti_windup {
+                            AntiWindup::None => {}
+                            AntiWindup::Clamp => {
+                                ui.add(
+                                    egui::Slider::new(&mut pid.windup_threshold, 0.0..=1.0)
+                                        .text("Integrate within"),
+                                );
+                            }
+                            AntiWindup::BackCalculation => {
+                                ui.add(
+                                    egui::Slider::new(&mut pid.tracking_gain, 0.0..=20.0)
+                                        .text("Anti-windup tracking gain"),
+                                );
+                            }
+                        }
+                        ui.horizontal(|ui| {
+                            ui.label("Form");
+                            ui.radio_value(&mut pid.form, PidForm::Positional, "Positional");
+                            ui.radio_value(&mut pid.form, PidForm::Incremental, "Incremental");
+                        });
+                        ui_feedforward(ui, &mut pid.feedforward);
+                    }
+
+                    let error_points: PlotPoints = to_points(&pid.error_history, clock.dt);
+                    let accumulator_points: PlotPoints =
+                        to_points(&pid.accumulator_history, clock.dt);
+
+                    lines.push((PlotTab::Tracking, "Error", error_points));
+                    lines.push((PlotTab::Integral, "Accumulator", accumulator_points));
+                    if let Some(mut preview) = pid.preview.take() {
+                        if preview.simulated != Some(preview.gains) {
+                            preview.angles =
+                                pid.predict(preview.gains, &pendulum, *integrator, clock.dt);
+                            preview.start = pendulum.angle_history.end().saturating_sub(1);
+                            preview.simulated = Some(preview.gains);
+                        }
+                        let preview_points: PlotPoints = preview
+                            .angles
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &a)| {
+                                [((preview.start + i) as f32 * clock.dt) as f64, a as f64]
+                            })
+                            .collect();
+                        lines.push((PlotTab::Tracking, PREVIEW_LINE, preview_points));
+                        pid.preview = Some(preview);
+                    }
+                    if pid.mode == PidMode::Position {
+                        let set_points =
+                            set_point_points(&pendulum.angle_history, &pid.error_history, clock.dt);
+                        lines.push((PlotTab::Tracking, "Set point", set_points));
+                    }
+
+                    let errors: Vec<f32> = pid.error_history.iter().map(|(_, e)| e).collect();
+                    if pid.mode == PidMode::Position {
+                        band = settling_band(pid.set_point, last_step, settling.0);
+                    }
+                    ui_step_metrics(ui, &errors, clock.dt, settling.0);
+                }
+
+                if let Some(mut lqr) = lqr {
+                    ui.separator();
+                    ui.label("LQR");
+
+                    ui.label(format!(
+                        "Error: {}",
+                        angle_difference(pendulum.a, lqr.set_point)
+                    ));
+                    if ramp.is_none() {
+                        ui.add(
+                            egui::Slider::new(&mut lqr.set_point, 0.0..=2.0 * PI).text("Set point"),
+                        );
+                    }
+                    ui_feedforward(ui, &mut lqr.feedforward);
+                    if ui
+                        .checkbox(&mut lqr.continuous, "Continuous time model")
+                        .changed()
+                    {
+                        lqr.update_model(&pendulum, clock.dt);
+                    }
+                    ui_model_report(ui, (lqr.a, lqr.b));
+                    ui.horizontal(|ui| {
+                        let mut shown = lqr.matrix_export.is_some();
+                        if ui.checkbox(&mut shown, "Show matrices").changed() {
+                            lqr.matrix_export = shown.then(MatrixFormat::default);
+                        }
+                        if let Some(format) = &mut lqr.matrix_export {
+                            for option in [MatrixFormat::Matlab, MatrixFormat::NumPy] {
+                                ui.radio_value(format, option, option.name());
+                            }
+                        }
+                    });
+                    if let Some(format) = lqr.matrix_export {
+                        // Solved first so the gain is listed along with the model
+                        let _ = lqr.gain();
+                        let text = lqr.matrices(&pendulum.model(), clock.dt, format);
+                        ui.horizontal(|ui| {
+                            if ui.button("Copy").clicked() {
+                                ui.output().copied_text = text.clone();
+                            }
+                            if ui.button("Save").clicked() {
+                                let name = format!(
+                                    "lqr_matrices_{}.{}",
+                                    unix_timestamp(),
+                                    format.extension()
+                                );
+                                let path = Path::new(&export.directory).join(name);
+                                match fs::write(&path, &text) {
+                                    Ok(()) => info!("Saved matrices to {}", path.display()),
+                                    Err(err) => error!("Failed to save matrices: {}", err),
+                                }
+                            }
+                        });
+                        ui.add(
+                            egui::TextEdit::multiline(&mut text.as_str())
+                                .font(egui::TextStyle::Monospace)
+                                .desired_width(f32::INFINITY),
+                        );
+                    }
+
+                    let (mut pos_cost, mut cross_cost, mut vel_cost, mut power_cost) =
+                        (lqr.q[(0, 0)], lqr.q[(0, 1)], lqr.q[(1, 1)], lqr.r[0]);
+                    let costs = [
+                        ui.add(
+                            egui::Slider::new(&mut pos_cost, 0.0..=100.0)
+                                .logarithmic(true)
+                                .text("Position cost"),
+                        ),
+                        ui.add(egui::Slider::new(&mut cross_cost, -10.0..=10.0).text("Cross cost")),
+                        ui.add(
+                            egui::Slider::new(&mut vel_cost, 0.0..=100.0)
+                                .logarithmic(true)
+                                .text("Velocity cost"),
+                        ),
+                        ui.add(
+                            egui::Slider::new(&mut power_cost, 0.01..=100.0)
+                                .logarithmic(true)
+                                .text("Power cost"),
+                        ),
+                    ];
+                    if costs.iter().any(|response| response.changed()) {
+                        lqr.set_q(pos_cost, cross_cost, vel_cost);
+                        lqr.r = R::new(power_cost);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui
+                            .checkbox(&mut lqr.integral_action, "Integral action")
+                            .changed()
+                        {
+                            lqr.integral = 0.0;
+                            lqr.dirty = true;
+                        }
+                        let mut integral_cost = lqr.integral_q[(2, 2)];
+                        let slider = ui.add_enabled(
+                            lqr.integral_action,
+                            egui::Slider::new(&mut integral_cost, 0.0..=100.0)
+                                .logarithmic(true)
+                                .text("Integral cost"),
+                        );
+                        if slider.changed() {
+                            lqr.set_integral_cost(integral_cost);
+                        }
+                    });
+                    if !lqr.q_semidefinite() {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            "Q is not positive semidefinite, the cross cost is too large",
+                        );
+                    }
+                    if let Some(err) = lqr.error {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
+                    if !lqr.continuous {
+                        ui.horizontal(|ui| {
+                            ui.label("Discretization");
+                            let method = lqr.discretization;
+                            ui.radio_value(
+                                &mut lqr.discretization,
+                                DiscretizationMethod::Euler,
+                                "Euler",
+                            );
+                            ui.radio_value(
+                                &mut lqr.discretization,
+                                DiscretizationMethod::ZeroOrderHold,
+                                "Zero-order hold",
+                            );
+                            if lqr.discretization != method {
+                                lqr.update_model(&pendulum, clock.dt);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Solver");
+                            let solver = lqr.solver;
+                            ui.radio_value(&mut lqr.solver, RiccatiSolver::Doubling, "Doubling");
+                            ui.radio_value(&mut lqr.solver, RiccatiSolver::Recursion, "Recursion");
+                            if lqr.solver != solver {
+                                lqr.max_iterations = lqr.solver.default_iterations();
+                                lqr.dirty = true;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            let tolerance = ui.add(
+                                egui::Slider::new(&mut lqr.tolerance, 1e-9..=1e-2)
+                                    .logarithmic(true)
+                                    .text("Tolerance"),
+                            );
+                            let iterations = ui.add(
+                                egui::DragValue::new(&mut lqr.max_iterations)
+                                    .clamp_range(1..=100000)
+                                    .prefix("max iterations "),
+                            );
+                            if tolerance.changed() || iterations.changed() {
+                                lqr.dirty = true;
+                            }
+                        });
+                    }
+                    if let Some(stats) = lqr.riccati {
+                        let text = format!(
+                            "{} after {} iterations, residual {:.1e}",
+                            if stats.converged {
+                                "Converged"
+                            } else {
+                                "Not converged"
+                            },
+                            stats.iterations,
+                            stats.residual
+                        );
+                        if stats.converged && stats.residual < RICCATI_RESIDUAL_LIMIT {
+                            ui.label(text);
+                        } else {
+                            ui.colored_label(egui::Color32::RED, text);
+                        }
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Auto-tune").clicked() {
+                            lqr.auto_tune(&pendulum, clock.dt);
+                        }
+                        if let Some([pos_cost, vel_cost, power_cost]) = lqr.tuned_costs {
+                            ui.label(format!(
+                                "Position {}, velocity {}, power {}",
+                                pos_cost, vel_cost, power_cost
+                            ));
+                        }
+                    });
+                    match ui_baseline(ui, lqr.baseline.is_some()) {
+                        (true, _) => lqr.set_baseline(),
+                        (_, true) => lqr.restore_baseline(),
+                        _ => {}
+                    }
+
+                    let error_points: PlotPoints = to_points(&lqr.error_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "LQR error", error_points));
+                    let set_points =
+                        set_point_points(&pendulum.angle_history, &lqr.error_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "LQR set point", set_points));
+
+                    let errors: Vec<f32> = lqr.error_history.iter().map(|(_, e)| e).collect();
+                    band = settling_band(lqr.set_point, last_step, settling.0);
+                    ui_step_metrics(ui, &errors, clock.dt, settling.0);
+                }
+
+                if let Some(mut placement) = placement {
+                    ui.separator();
+                    ui.label("Pole placement");
+                    ui.horizontal(|ui| {
+                        ui.label("Poles");
+                        for pole in placement.poles.iter_mut() {
+                            ui.add(
+                                egui::DragValue::new(pole)
+                                    .speed(0.005)
+                                    .clamp_range(-1.0..=1.0),
+                            );
+                        }
+                    });
+                    ui.add(
+                        egui::Slider::new(&mut placement.set_point, 0.0..=2.0 * PI)
+                            .text("Set point"),
+                    );
+                    if let Some(err) = placement.error {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
+                }
+
+                if let Some(mut bang_bang) = bang_bang {
+                    ui.separator();
+                    ui.label("Bang-bang");
+                    ui.label(format!(
+                        "Error: {}",
+                        angle_difference(pendulum.a, bang_bang.set_point)
+                    ));
+                    ui.add(
+                        egui::Slider::new(&mut bang_bang.set_point, 0.0..=2.0 * PI)
+                            .text("Set point"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut bang_bang.hysteresis, 0.0..=1.0).text("Hysteresis"),
+                    );
+                }
+
+                if let Some(mut sliding) = sliding {
+                    ui.separator();
+                    ui.label("Sliding mode");
+                    ui.label(format!(
+                        "Error: {}",
+                        angle_difference(pendulum.a, sliding.set_point)
+                    ));
+                    ui.add(
+                        egui::Slider::new(&mut sliding.set_point, 0.0..=2.0 * PI).text("Set point"),
+                    );
+                    ui.add(egui::Slider::new(&mut sliding.lambda, 0.0..=10.0).text("Lambda"));
+                    ui.add(egui::Slider::new(&mut sliding.eta, 0.0..=1.0).text("Eta"));
+                    ui.add(
+                        egui::Slider::new(&mut sliding.boundary_layer, 0.0..=1.0)
+                            .text("Boundary layer"),
+                    );
+
+                    let surface_points: PlotPoints = to_points(&sliding.surface_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "Sliding surface", surface_points));
+                }
+
+                if let Some(mut park) = park {
+                    ui.separator();
+                    ui.label(if park.parked {
+                        "Park: parked, control off"
+                    } else {
+                        "Park: settling"
+                    });
+                    ui.add(egui::Slider::new(&mut park.damping, 0.0..=5.0).text("Damping"));
+                    ui.add(egui::Slider::new(&mut park.stiffness, 0.0..=1.0).text("Stiffness"));
+                    ui.add(
+                        egui::Slider::new(&mut park.angle_threshold, 0.001..=0.5)
+                            .logarithmic(true)
+                            .text("Angle threshold"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut park.velocity_threshold, 0.001..=0.5)
+                            .logarithmic(true)
+                            .text("Velocity threshold"),
+                    );
+                }
+
+                if let Some(mut cascade) = cascade {
+                    ui.separator();
+                    ui.label("Cascade");
+                    ui.label(format!(
+                        "Error: {}, velocity command: {}",
+                        angle_difference(pendulum.a, cascade.set_point),
+                        cascade.velocity_command
+                    ));
+                    ui.add(
+                        egui::Slider::new(&mut cascade.set_point, 0.0..=2.0 * PI).text("Set point"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut cascade.outer_gain, 0.0..=10.0).text("Outer gain"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut cascade.max_velocity, 0.1..=10.0)
+                            .text("Max velocity command"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut cascade.inner_proportional_gain, 0.0..=20.0)
+                            .text("Inner proportional gain"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut cascade.inner_integral_gain, 0.0..=20.0)
+                            .text("Inner integral gain"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut cascade.tracking_gain, 0.0..=20.0)
+                            .text("Anti-windup tracking gain"),
+                    );
+                    ui_feedforward(ui, &mut cascade.feedforward);
+
+                    let error_points: PlotPoints = to_points(&cascade.error_history, clock.dt);
+                    let velocity_points: PlotPoints =
+                        to_points(&cascade.velocity_error_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "Position error", error_points));
+                    lines.push((PlotTab::Tracking, "Velocity error", velocity_points));
+                }
+
+                if let Some(mut schedule) = schedule {
+                    ui.separator();
+                    ui.label("Gain schedule");
+                    ui.label(format!(
+                        "Error: {}",
+                        angle_difference(pendulum.a, schedule.set_point)
+                    ));
+                    ui.add(
+                        egui::Slider::new(&mut schedule.set_point, 0.0..=2.0 * PI)
+                            .text("Set point"),
+                    );
+                    ui.label(format!(
+                        "Breakpoints: {}",
+                        schedule
+                            .angles
+                            .iter()
+                            .map(|a| format!("{:.2}", a))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                    if let Some(err) = schedule.error {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
+                    ui_feedforward(ui, &mut schedule.feedforward);
+                }
+
+                if let Some(mut mpc) = mpc {
+                    ui.separator();
+                    ui.label("MPC");
+                    ui.label(format!(
+                        "Error: {}",
+                        angle_difference(pendulum.a, mpc.set_point)
+                    ));
+                    ui.add(egui::Slider::new(&mut mpc.set_point, 0.0..=2.0 * PI).text("Set point"));
+                    ui.add(egui::Slider::new(&mut mpc.horizon, 1..=100).text("Horizon"));
+                    ui.add(egui::Slider::new(&mut mpc.iterations, 1..=500).text("Iterations"));
+                    ui.add(
+                        egui::Slider::new(&mut mpc.r[0], 0.01..=10.0)
+                            .logarithmic(true)
+                            .text("Control cost"),
+                    );
+                }
+
+                if let Some(mut ramp) = ramp {
+                    ui.separator();
+                    ui.label(format!("Set point: {:.3}", ramp.current));
+                    ui.add(egui::Slider::new(&mut ramp.target, 0.0..=2.0 * PI).text("Target"));
+                    ui.add(
+                        egui::Slider::new(&mut ramp.max_rate, 0.01..=10.0)
+                            .logarithmic(true)
+                            .text("Max rate"),
+                    );
+                }
+
+                if let Some(reference) = reference.as_deref_mut() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Reference");
+                        if ui.button("Stop tracking").clicked() {
+                            pendulum_events.send(PendulumEvent::TrackReference(entity, false));
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.radio_value(&mut reference.kind, ReferenceKind::Constant, "Constant");
+                        ui.radio_value(&mut reference.kind, ReferenceKind::Sine, "Sine");
+                        ui.radio_value(&mut reference.kind, ReferenceKind::Square, "Square");
+                    });
+                    ui.add(egui::Slider::new(&mut reference.center, 0.0..=2.0 * PI).text("Center"));
+                    ui.add(egui::Slider::new(&mut reference.amplitude, 0.0..=PI).text("Amplitude"));
+                    ui.add(
+                        egui::Slider::new(&mut reference.frequency, 0.01..=5.0)
+                            .logarithmic(true)
+                            .text("Frequency (Hz)"),
+                    );
+                    ui_frequency_sweep(ui, entity, reference, sweep, &mut pendulum_events);
+
+                    lines.push((
+                        PlotTab::Tracking,
+                        "Reference",
+                        to_points(&reference.history, clock.dt),
+                    ));
+                }
+
+                if let Some(mut kalman) = kalman {
+                    ui.separator();
+                    ui.label("Kalman filter");
+                    let (a, da) = kalman.estimate();
+                    ui.label(format!(
+                        "Estimate error: {:.4}, {:.4}",
+                        angle_difference(a, pendulum.a),
+                        da - pendulum.da
+                    ));
+
+                    let mut process = kalman.q[(0, 0)];
+                    if ui
+                        .add(
+                            egui::Slider::new(&mut process, 1e-6..=1.0)
+                                .logarithmic(true)
+                                .text("Process noise"),
+                        )
+                        .changed()
+                    {
+                        kalman.q = Matrix2::from_diagonal_element(process);
+                    }
+                    ui.add(
+                        egui::Slider::new(&mut kalman.r[(0, 0)], 1e-6..=1.0)
+                            .logarithmic(true)
+                            .text("Angle measurement variance"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut kalman.r[(1, 1)], 1e-6..=10.0)
+                            .logarithmic(true)
+                            .text("Velocity measurement variance"),
+                    );
+
+                    let estimate_points: PlotPoints = to_points(&kalman.estimate_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "Estimated angle", estimate_points));
+                }
+
+                if let Some(observer) = observer.as_deref_mut() {
+                    ui.separator();
+                    ui.label("Luenberger observer");
+                    ui.checkbox(&mut observer.enabled, "Replace measured velocity");
+                    ui.horizontal(|ui| {
+                        ui.label("Poles");
+                        for pole in observer.poles.iter_mut() {
+                            ui.add(
+                                egui::DragValue::new(pole)
+                                    .speed(0.005)
+                                    .clamp_range(-1.0..=1.0),
+                            );
+                        }
+                    });
+                    if let Some(err) = observer.error {
+                        ui.colored_label(egui::Color32::RED, err);
+                    }
+
+                    let estimate_points: PlotPoints =
+                        to_points(&observer.estimate_history, clock.dt);
+                    let velocity_points: PlotPoints =
+                        to_points(&observer.velocity_history, clock.dt);
+                    lines.push((PlotTab::Tracking, "Velocity", velocity_points));
+                    lines.push((PlotTab::Tracking, "Estimated velocity", estimate_points));
+                }
+
+                if let Some(mut swing_up) = swing_up {
+                    ui.separator();
+                    ui.label("Swing-up");
+                    ui.label(format!(
+                        "Energy: {:.1} / {:.1} ({})",
+                        energy(&pendulum),
+                        pendulum.upright_energy(),
+                        if swing_up.is_active(&pendulum) {
+                            "pumping"
+                        } else {
+                            "LQR"
+                        }
+                    ));
+                    ui.add(egui::Slider::new(&mut swing_up.gain, -0.2..=0.0).text("Energy gain"));
+                    ui.add(
+                        egui::Slider::new(&mut swing_up.handoff_window, 0.0..=PI)
+                            .text("Hand-off window"),
+                    );
+                }
+
+                let tab = plot_tabs.entry(entity).or_default();
+                ui.separator();
+                ui.horizontal(|ui| {
+                    for kind in PlotTab::ALL {
+                        ui.selectable_value(tab, kind, kind.name());
+                    }
+                });
+                let lines: Vec<(&str, PlotPoints)> = lines
+                    .into_iter()
+                    .filter(|(kind, ..)| kind == tab)
+                    .map(|(_, name, points)| (name, points))
+                    .collect();
+
+                let settings = plot_settings.entry((entity, *tab)).or_default();
+                if save_png {
+                    let series: Vec<(&str, Vec<[f64; 2]>)> = lines
+                        .iter()
+                        .filter(|(name, _)| !settings.hidden.contains(*name))
+                        .map(|(name, points)| {
+                            (*name, points.points().iter().map(|p| [p.x, p.y]).collect())
+                        })
+                        .collect();
+                    let name = format!(
+                        "pendulum_{}_{}_{}",
+                        i,
+                        pendulum.controller.name().to_lowercase().replace(' ', "_"),
+                        unix_timestamp()
+                    );
+                    match plot_image::save_plot(&export.directory, &name, &series, export.plot_size)
+                    {
+                        Ok(path) => info!("Saved plot to {}", path.display()),
+                        Err(err) => error!("Failed to save plot: {}", err),
+                    }
+                }
+
+                // Separate ids keep a zoomed plot on one tab from moving the others
+                let band = band.filter(|_| *tab == PlotTab::Tracking);
+                ui.push_id(*tab, |ui| ui_plot(ui, settings, lines, band));
+            });
+
+        if undo_clicked {
+            undo.stacks.entry(entity).or_default().requested = true;
+        }
+    }
+}
+
+/// Linear range of the symmetric log axis, errors this small still get a visible share of it
+const SYMLOG_THRESHOLD: f64 = 1e-3;
+
+fn symlog(y: f64) -> f64 {
+    y.signum() * (1.0 + y.abs() / SYMLOG_THRESHOLD).log10()
+}
+
+fn symlog_inverse(y: f64) -> f64 {
+    y.signum() * SYMLOG_THRESHOLD * (10f64.powf(y.abs()) - 1.0)
+}
+
+/// Which history plot of a pendulum's window is showing, each scales to its own lines
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+enum PlotTab {
+    /// Angles, set points, errors and estimates
+    #[default]
+    Tracking,
+    /// Control effort along with the disturbances and energy it works against
+    Control,
+    /// The PID accumulator
+    Integral,
+}
+
+impl PlotTab {
+    const ALL: [PlotTab; 3] = [PlotTab::Tracking, PlotTab::Control, PlotTab::Integral];
+
+    fn name(self) -> &'static str {
+        match self {
+            PlotTab::Tracking => "Tracking",
+            PlotTab::Control => "Control",
+            PlotTab::Integral => "Integral",
+        }
+    }
+}
+
+/// Set point over time recovered from each angle sample and the `set_point - angle` error
+/// recorded with it
+fn set_point_points(angles: &History, errors: &History, dt: f32) -> PlotPoints {
+    errors
+        .iter()
+        .filter_map(|(i, error)| {
+            let angle = angles.get(i)?;
+            Some([i as f64 * dt as f64, wrap_angle(angle + error) as f64])
+        })
+        .collect()
+}
+
+/// Per window view options for the history plot
+struct PlotSettings {
+    hidden: HashSet<String>,
+    autoscale: bool,
+    symlog: bool,
+    /// Only show the last `window` seconds so the plot scrolls instead of compressing
+    follow: bool,
+    window: f32,
+    /// Reduce long lines with `decimate`, off to see every sample
+    decimate: bool,
+}
+
+impl Default for PlotSettings {
+    fn default() -> Self {
+        Self {
+            hidden: HashSet::new(),
+            autoscale: true,
+            symlog: false,
+            follow: false,
+            window: 10.0,
+            decimate: true,
+        }
+    }
+}
+
+/// Visible points per line above which the plot draws a decimated envelope instead
+const PLOT_POINT_LIMIT: usize = 1000;
+
+/// Keeps the lowest and highest point of each of `limit / 2` equal runs of points, in order, so
+/// the envelope and any spikes survive. Lines no longer than `limit` come back unchanged
+fn decimate(points: Vec<[f64; 2]>, limit: usize) -> Vec<[f64; 2]> {
+    if points.len() <= limit {
+        return points;
+    }
+    let size = points.len().div_ceil((limit / 2).max(1));
+
+    points
+        .chunks(size)
+        .flat_map(|chunk| {
+            let by_y = |a: &(usize, &[f64; 2]), b: &(usize, &[f64; 2])| a.1[1].total_cmp(&b.1[1]);
+            let low = chunk.iter().enumerate().min_by(by_y).unwrap();
+            let high = chunk.iter().enumerate().max_by(by_y).unwrap();
+            if low.0 <= high.0 {
+                [*low.1, *high.1]
+            } else {
+                [*high.1, *low.1]
+            }
+        })
+        .collect()
+}
+
+/// Legend entry of the shaded settling band, hidden like any line
+const SETTLING_BAND_NAME: &str = "Settling band";
+/// Predicted angle of the gain preview, drawn dashed to set it apart from what happened
+const PREVIEW_LINE: &str = "Preview";
+
+/// `band` is a set point and half width drawn as a shaded strip over the whole plot
+fn ui_plot<S: AsRef<str>>(
+    ui: &mut egui::Ui,
+    settings: &mut PlotSettings,
+    lines: Vec<(S, PlotPoints)>,
+    band: Option<(f32, f32)>,
+) {
+    ui.horizontal_wrapped(|ui| {
+        let names = lines.iter().map(|(name, _)| name.as_ref());
+        for name in names.chain(band.map(|_| SETTLING_BAND_NAME)) {
+            let mut visible = !settings.hidden.contains(name);
+            if ui.checkbox(&mut visible, name).changed() {
+                if visible {
+                    settings.hidden.remove(name);
+                } else {
+                    settings.hidden.insert(name.to_string());
+                }
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut settings.autoscale, "Autoscale");
+        ui.checkbox(&mut settings.symlog, "Symmetric log");
+        ui.checkbox(&mut settings.follow, "Last");
+        ui.add_enabled(
+            settings.follow,
+            egui::DragValue::new(&mut settings.window)
+                .speed(0.1)
+                .clamp_range(1.0..=600.0)
+                .suffix(" s"),
+        );
+        ui.checkbox(&mut settings.decimate, "Decimate");
+    });
+
+    let end = lines
+        .iter()
+        .filter_map(|(_, points)| points.points().last())
+        .map(|point| point.x)
+        .fold(0.0, f64::max);
+    let start = if settings.follow {
+        end - settings.window as f64
+    } else {
+        f64::NEG_INFINITY
+    };
+
+    let first = lines
+        .iter()
+        .filter_map(|(_, points)| points.points().first())
+        .map(|point| point.x)
+        .fold(end, f64::min)
+        .max(start);
+    let scale = |y: f64| if settings.symlog { symlog(y) } else { y };
+    let band = band
+        .filter(|_| !settings.hidden.contains(SETTLING_BAND_NAME))
+        .map(|(center, half_width)| {
+            let (low, high) = (
+                scale((center - half_width) as f64),
+                scale((center + half_width) as f64),
+            );
+            let strip = vec![[first, low], [end, low], [end, high], [first, high]];
+            (low, high, strip)
+        });
+
+    let lines: Vec<Line> = lines
+        .into_iter()
+        .filter(|(name, _)| !settings.hidden.contains(name.as_ref()))
+        .map(|(name, points)| {
+            let points: Vec<[f64; 2]> = points
+                .points()
+                .iter()
+                .filter(|point| point.x >= start)
+                .map(|point| [point.x, scale(point.y)])
+                .collect();
+            let points = if settings.decimate {
+                decimate(points, PLOT_POINT_LIMIT)
+            } else {
+                points
+            };
+            let style = if name.as_ref() == PREVIEW_LINE {
+                LineStyle::dashed_loose()
+            } else {
+                LineStyle::Solid
+            };
+            Line::new(PlotPoints::new(points))
+                .name(name.as_ref())
+                .style(style)
+        })
+        .collect();
+
+    // Plot memory stops fitting the bounds once the user drags or zooms, so the autoscaled plot
+    // gets its own id and ignores input
+    let mut plot = Plot::new(("My Plot", settings.autoscale))
+        .legend(Legend::default())
+        .view_aspect(2.0);
+    if settings.autoscale {
+        plot = plot
+            .allow_drag(false)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .allow_boxed_zoom(false);
+    }
+    if settings.symlog {
+        plot = plot.y_axis_formatter(|y, _| format!("{:.3}", symlog_inverse(y)));
+    }
+
+    plot.show(ui, |plot_ui| {
+        if let Some((low, high, strip)) = band {
+            let color = egui::Color32::GRAY;
+            plot_ui.polygon(
+                Polygon::new(PlotPoints::new(strip))
+                    .color(color)
+                    .fill_alpha(0.15)
+                    .name(SETTLING_BAND_NAME),
+            );
+            for y in [low, high] {
+                plot_ui.hline(HLine::new(y).color(color).name(SETTLING_BAND_NAME));
+            }
+        }
+        for line in lines {
+            plot_ui.line(line);
+        }
+    });
+}
+
+#[derive(Default, PartialEq, Eq)]
+enum ComparisonSignal {
+    /// From the active controller's set point, or upright for controllers without one
+    #[default]
+    Error,
+    Angle,
+}
+
+/// Overlays every pendulum's history on one plot
+fn ui_comparison(
+    mut egui_context: ResMut<EguiContext>,
+    clock: Res<SimulationClock>,
+    mut signal: Local<ComparisonSignal>,
+    mut settings: Local<PlotSettings>,
+    mut query: Query<(Entity, &Pendulum, SetPoints)>,
+) {
+    egui::Window::new("Comparison")
+        .default_pos((560.0, 280.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut *signal, ComparisonSignal::Error, "Error");
+                ui.radio_value(&mut *signal, ComparisonSignal::Angle, "Angle");
+            });
+
+            let mut pendulums: Vec<_> = query.iter_mut().collect();
+            pendulums.sort_by_key(|(entity, ..)| *entity);
+
+            let lines = pendulums
+                .into_iter()
+                .map(|(entity, pendulum, set_points)| {
+                    let name = format!("{} #{}", pendulum.controller.name(), entity.index());
+                    let set_point = set_points.get(pendulum.controller).unwrap_or(PI);
+                    let points = match *signal {
+                        ComparisonSignal::Error => pendulum
+                            .angle_history
+                            .iter()
+                            .map(|(i, a)| {
+                                let error = angle_difference(a, set_point);
+                                [i as f64 * clock.dt as f64, error as f64]
+                            })
+                            .collect(),
+                        ComparisonSignal::Angle => to_points(&pendulum.angle_history, clock.dt),
+                    };
+                    (name, points)
+                })
+                .collect();
+
+            ui_plot(ui, &mut settings, lines, None);
+        });
+}
+
+/// Table of every pendulum's controller and step response figures, collected on demand so the
+/// numbers hold still while being copied
+#[allow(clippy::type_complexity)]
+fn ui_summary(
+    mut egui_context: ResMut<EguiContext>,
+    clock: Res<SimulationClock>,
+    settling: Res<SettlingBand>,
+    export: Res<ExportSettings>,
+    mut rows: Local<Vec<SummaryRow>>,
+    mut query: Query<(
+        Entity,
+        &Pendulum,
+        Option<&SwingUp>,
+        Option<&Park>,
+        SetPoints,
+    )>,
+) {
+    egui::Window::new("Controller summary")
+        .default_pos((560.0, 640.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Collect metrics").clicked() {
+                    let mut pendulums: Vec<_> = query.iter_mut().collect();
+                    pendulums.sort_by_key(|(entity, ..)| *entity);
+
+                    *rows = pendulums
+                        .into_iter()
+                        .map(|(entity, pendulum, swing_up, park, set_points)| {
+                            let gains = match pendulum.controller {
+                                ControllerKind::Manual => None,
+                                ControllerKind::Pid => set_points.pid.as_deref().map(|p| {
+                                    format!(
+                                        "Kp {}, Ki {}, Kd {}, Ka {}",
+                                        p.proportional_gain,
+                                        p.integral_gain,
+                                        p.derivative_gain,
+                                        p.acceleration_gain
+                                    )
+                                }),
+                                ControllerKind::Lqr => set_points
+                                    .lqr
+                                    .as_deref()
+                                    .map(|l| format!("K [{:.3}, {:.3}]", l.k[0], l.k[1])),
+                                ControllerKind::SwingUp => {
+                                    swing_up.map(|s| format!("gain {}", s.gain))
+                                }
+                                ControllerKind::BangBang => set_points
+                                    .bang_bang
+                                    .as_deref()
+                                    .map(|b| format!("hysteresis {}", b.hysteresis)),
+                                ControllerKind::PolePlacement => set_points
+                                    .placement
+                                    .as_deref()
+                                    .map(|p| format!("poles {}, {}", p.poles[0], p.poles[1])),
+                                ControllerKind::Mpc => set_points
+                                    .mpc
+                                    .as_deref()
+                                    .map(|m| format!("horizon {}", m.horizon)),
+                                ControllerKind::GainSchedule => set_points
+                                    .schedule
+                                    .as_deref()
+                                    .map(|g| format!("{} breakpoints", g.angles.len())),
+                                ControllerKind::SlidingMode => set_points
+                                    .sliding
+                                    .as_deref()
+                                    .map(|s| format!("lambda {}, eta {}", s.lambda, s.eta)),
+                                ControllerKind::Park => {
+                                    park.map(|p| format!("damping {}", p.damping))
+                                }
+                                ControllerKind::Cascade => set_points.cascade.as_deref().map(|c| {
+                                    format!(
+                                        "outer {}, inner Kp {}, Ki {}",
+                                        c.outer_gain,
+                                        c.inner_proportional_gain,
+                                        c.inner_integral_gain
+                                    )
+                                }),
+                            };
+                            let set_point = set_points.get(pendulum.controller).unwrap_or(PI);
+                            let errors: Vec<f32> = pendulum
+                                .angle_history
+                                .iter()
+                                .map(|(_, a)| angle_difference(a, set_point))
+                                .collect();
+                            SummaryRow::new(
+                                format!("{} #{}", pendulum.controller.name(), entity.index()),
+                                gains.unwrap_or_else(|| "-".to_string()),
+                                &errors,
+                                pendulum.effort,
+                                clock.dt,
+                                settling.0,
+                            )
+                        })
+                        .collect();
+                }
+                if rows.is_empty() {
+                    return;
+                }
+                for (label, extension, contents) in [
+                    ("Export CSV", "csv", summary_csv(&rows)),
+                    ("Export Markdown", "md", summary_markdown(&rows)),
+                ] {
+                    if ui.button(label).clicked() {
+                        match export_summary(&export.directory, extension, &contents) {
+                            Ok(path) => info!("Exported summary to {}", path.display()),
+                            Err(err) => error!("Failed to export summary: {}", err),
+                        }
+                    }
+                }
+            });
+
+            egui::Grid::new("summary").striped(true).show(ui, |ui| {
+                for header in SummaryRow::HEADER {
+                    ui.strong(header);
+                }
+                ui.end_row();
+                for row in rows.iter() {
+                    for cell in row.cells() {
+                        ui.label(cell);
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+}
+
+fn ui_step_test(
+    ui: &mut egui::Ui,
+    test: &mut StepTest,
+    controller: ControllerKind,
+    tracking: bool,
+) {
+    let has_set_point = !matches!(
+        controller,
+        ControllerKind::Manual | ControllerKind::SwingUp | ControllerKind::Park
+    );
+    ui.horizontal(|ui| {
+        let enabled = test.running.is_none() && has_set_point && !tracking && test.size != 0.0;
+        if ui
+            .add_enabled(enabled, egui::Button::new("Step test"))
+            .clicked()
+        {
+            test.requested = true;
+        }
+        ui.add(
+            egui::DragValue::new(&mut test.size)
+                .clamp_range(-1.0..=1.0)
+                .speed(0.01)
+                .prefix("step "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut test.timeout)
+                .clamp_range(1.0..=120.0)
+                .prefix("timeout ")
+                .suffix(" s"),
+        );
+    });
+    if tracking {
+        ui.label("The reference signal moves the set point, remove it to run a step test");
+    }
+
+    if let Some((from, to)) = test.running {
+        ui.label(format!("Stepping from {:.3} to {:.3}", from, to));
+    } else if let Some(result) = &test.result {
+        ui.label(format!(
+            "Step from {:.3} to {:.3}{}",
+            result.from,
+            result.to,
+            if result.settled { "" } else { ", timed out" }
+        ));
+        if let Some(metrics) = &result.metrics {
+            ui_metrics(ui, metrics);
+        }
+    }
+}
+
+/// `title` after a dot in the color of `status`
+fn status_title(
+    ctx: &egui::Context,
+    status: Option<StabilityStatus>,
+    title: &str,
+) -> egui::text::LayoutJob {
+    let style = ctx.style();
+    let font = style.text_styles[&egui::TextStyle::Heading].clone();
+    let mut job = egui::text::LayoutJob::default();
+    let dot = status.map(|status| ("● ", status.color()));
+    for (text, color) in dot.into_iter().chain([(title, style.visuals.text_color())]) {
+        job.append(
+            text,
+            0.0,
+            egui::TextFormat {
+                font_id: font.clone(),
+                color,
+                ..Default::default()
+            },
+        );
+    }
+    job
+}
+
+fn ui_impulse_test(ui: &mut egui::Ui, entity: Entity, test: &mut ImpulseTest) {
+    ui.horizontal(|ui| {
+        let enabled = test.running.is_none() && test.size != 0.0;
+        if ui
+            .add_enabled(enabled, egui::Button::new("Impulse test"))
+            .clicked()
+        {
+            test.requested = true;
+        }
+        ui.add(
+            egui::DragValue::new(&mut test.size)
+                .clamp_range(-5.0..=5.0)
+                .speed(0.01)
+                .prefix("Δda "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut test.duration)
+                .clamp_range(0.5..=60.0)
+                .speed(0.1)
+                .prefix("record ")
+                .suffix(" s"),
+        );
+    });
+
+    if test.running.is_some() {
+        ui.label(format!(
+            "Recording, {:.1} / {:.1} s",
+            test.deviations.len() as f32 * test.dt,
+            test.duration
+        ));
+        return;
+    }
+    let Some(result) = &test.result else {
+        return;
+    };
+    ui.label(format!("Peak deviation: {:.4} rad", result.peak));
+    ui.label(match result.decay_rate {
+        Some(rate) => format!(
+            "Decay rate: {:.3} 1/s, time constant {:.2} s",
+            rate,
+            1.0 / rate
+        ),
+        None => "Decay rate: does not decay".to_string(),
+    });
+    ui.label(match result.frequency {
+        Some(frequency) => format!("Oscillation frequency: {:.3} Hz", frequency),
+        None => "Oscillation frequency: does not oscillate".to_string(),
+    });
+
+    let response: PlotPoints = test
+        .deviations
+        .iter()
+        .enumerate()
+        .map(|(i, &d)| [((i + 1) as f32 * test.dt) as f64, d as f64])
+        .collect();
+    Plot::new(("Impulse response", entity))
+        .height(120.0)
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(response).name("Deviation"));
+        });
+}
+
+fn ui_linearization_check(ui: &mut egui::Ui, entity: Entity, check: &mut LinearizationCheck) {
+    ui.horizontal(|ui| {
+        if ui.button("Compare linearization").clicked() {
+            check.requested = true;
+        }
+        ui.add(
+            egui::DragValue::new(&mut check.angle)
+                .clamp_range(0.0..=2.0 * PI)
+                .speed(0.01)
+                .prefix("about "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut check.horizon)
+                .clamp_range(0.1..=20.0)
+                .speed(0.1)
+                .prefix("horizon ")
+                .suffix(" s"),
+        );
+    });
+    if check.predicted.is_empty() {
+        return;
+    }
+
+    let largest = check.divergence().fold(0.0, f32::max);
+    match check.breakdown_time() {
+        Some(t) => ui.label(format!(
+            "Off by more than {} rad after {:.2} s, {:.3} rad at most",
+            LINEARIZATION_TOLERANCE, t, largest
+        )),
+        None => ui.label(format!(
+            "Within {} rad over the horizon, {:.3} rad at most",
+            LINEARIZATION_TOLERANCE, largest
+        )),
+    };
+
+    let line = |angles: &[f32]| -> PlotPoints {
+        angles
+            .iter()
+            .enumerate()
+            .map(|(i, &angle)| [(i as f32 * check.dt) as f64, angle as f64])
+            .collect()
+    };
+    Plot::new(("Linearization", entity))
+        .height(120.0)
+        .legend(Legend::default())
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(line(&check.predicted)).name("Linear"));
+            plot_ui.line(Line::new(line(&check.actual)).name("Nonlinear"));
+        });
+}
+
+fn ui_frequency_sweep(
+    ui: &mut egui::Ui,
+    entity: Entity,
+    reference: &ReferenceSignal,
+    sweep: Option<&FrequencySweep>,
+    pendulum_events: &mut EventWriter<PendulumEvent>,
+) {
+    let running = sweep.is_some_and(FrequencySweep::running);
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(
+                !running && reference.amplitude > 0.0,
+                egui::Button::new("Frequency sweep"),
+            )
+            .clicked()
+        {
+            pendulum_events.send(PendulumEvent::SweepFrequency(entity));
+        }
+        if let Some(sweep) = sweep.filter(|sweep| sweep.running()) {
+            ui.label(format!(
+                "Measuring {:.2} Hz, {} / {}",
+                reference.frequency,
+                sweep.results.len() + 1,
+                SWEEP_POINTS
+            ));
+        }
+    });
+
+    let Some(sweep) = sweep.filter(|sweep| !sweep.results.is_empty()) else {
+        return;
+    };
+    let magnitude: PlotPoints = sweep
+        .results
+        .iter()
+        .map(|r| {
+            [
+                r.frequency.log10() as f64,
+                20.0 * r.magnitude.log10() as f64,
+            ]
+        })
+        .collect();
+    Plot::new(("Frequency response", entity))
+        .height(120.0)
+        .x_axis_formatter(|x, _| format!("{:.2} Hz", 10f64.powf(x)))
+        .y_axis_formatter(|y, _| format!("{:.0} dB", y))
+        .show(ui, |plot_ui| {
+            plot_ui.line(Line::new(magnitude).name("Magnitude"))
+        });
+    egui::Grid::new(("Frequency response table", entity)).show(ui, |ui| {
+        ui.strong("Hz");
+        ui.strong("Magnitude");
+        ui.strong("Phase (deg)");
+        ui.end_row();
+        for r in &sweep.results {
+            ui.label(format!("{:.2}", r.frequency));
+            ui.label(format!("{:.3}", r.magnitude));
+            ui.label(format!("{:.1}", r.phase.to_degrees()));
+            ui.end_row();
+        }
+    });
+}
+
+fn ui_model_report(ui: &mut egui::Ui, (a, b): (A, B)) {
+    for (name, rank) in [
+        ("Controllable", controllability_rank((a, b))),
+        ("Observable from angle", observability_rank(&a)),
+    ] {
+        let (verdict, color) = if rank == 2 {
+            ("yes", egui::Color32::GREEN)
+        } else {
+            ("no", egui::Color32::RED)
+        };
+        ui.colored_label(color, format!("{}: {} (rank {})", name, verdict, rank));
+    }
+}
+
+/// Buttons for putting gains aside and getting them back, returns whether each got clicked
+fn ui_baseline(ui: &mut egui::Ui, saved: bool) -> (bool, bool) {
+    ui.horizontal(|ui| {
+        (
+            ui.button("Set baseline").clicked(),
+            ui.add_enabled(saved, egui::Button::new("Restore baseline"))
+                .clicked(),
+        )
+    })
+    .inner
+}
+
+fn ui_feedforward(ui: &mut egui::Ui, feedforward: &mut Feedforward) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut feedforward.enabled, "Feedforward");
+        ui.add_enabled(
+            feedforward.enabled,
+            egui::Slider::new(&mut feedforward.gain, 0.0..=2.0).text("gain"),
+        );
+        ui.checkbox(&mut feedforward.friction, "Cancel friction");
+    });
+}
+
+/// Corner readout of the real-time factor, colored once the physics falls behind the time scale
+fn ui_real_time(
+    mut egui_context: ResMut<EguiContext>,
+    clock: Res<SimulationClock>,
+    real_time: Res<RealTimeFactor>,
+) {
+    let Some((factor, steps_per_frame)) = real_time.last else {
+        return;
+    };
+
+    egui::Area::new("Real-time factor")
+        .anchor(egui::Align2::RIGHT_BOTTOM, (-10.0, -10.0))
+        .interactable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            let text = format!(
+                "{:.2}x real time, {:.1} steps per frame",
+                factor, steps_per_frame
+            );
+            if factor < clock.time_scale * (1.0 - REAL_TIME_MARGIN) {
+                ui.colored_label(egui::Color32::YELLOW, text);
+            } else {
+                ui.label(text);
+            }
+        });
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn ui_simulation(
+    mut egui_context: ResMut<EguiContext>,
+    mut integrator: ResMut<IntegratorKind>,
+    mut state: ResMut<SimState>,
+    mut clock: ResMut<SimulationClock>,
+    mut export: ResMut<ExportSettings>,
+    mut capacity: ResMut<HistoryCapacity>,
+    mut config_events: EventWriter<ConfigEvent>,
+    mut pendulum_events: EventWriter<PendulumEvent>,
+    mut reset_events: EventWriter<ResetAllEvent>,
+    mut randomize_events: EventWriter<RandomizeAllEvent>,
+    mut ranges: ResMut<InitialRanges>,
+    (recorder, mut recorder_events): (Res<Recorder>, EventWriter<RecorderEvent>),
+    mut rng: ResMut<AppRng>,
+    mut dt_events: EventWriter<DtChangedEvent>,
+    (mut grab, mut nudge, mut layout, mut settling, mut invariants, mut stability): (
+        ResMut<Grab>,
+        ResMut<SetpointNudge>,
+        ResMut<PendulumLayout>,
+        ResMut<SettlingBand>,
+        ResMut<InvariantCheck>,
+        ResMut<StabilityIndicator>,
+    ),
+    pendulums: Query<&Pendulum>,
+) {
+    egui::Window::new("Simulation")
+        .resizable(false)
+        .default_pos((560.0, 20.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut state.paused, "Paused (Space)").changed() {
+                    state.pending_steps = 0;
+                }
+                if ui
+                    .add_enabled(state.paused, egui::Button::new("Step (.)"))
+                    .clicked()
+                {
+                    state.pending_steps += 1;
+                }
+                if ui.button("Reset all (R)").clicked() {
+                    reset_events.send(ResetAllEvent);
+                }
+                if ui.button("Randomize all").clicked() {
+                    randomize_events.send(RandomizeAllEvent);
+                }
+            });
+            ui.checkbox(&mut grab.enabled, "Drag pendulums by the bob (G)");
+            ui.add(
+                egui::DragValue::new(&mut nudge.step)
+                    .clamp_range(0.001..=PI)
+                    .speed(0.005)
+                    .prefix("Set point nudge (Up/Down) "),
+            );
+            ui.add(
+                egui::Slider::new(&mut settling.0, 0.005..=0.2)
+                    .text("Settling band (fraction of initial error)"),
+            );
+            ui.horizontal(|ui| {
+                ui.label("Stability dots");
+                ui.add(
+                    egui::DragValue::new(&mut stability.band)
+                        .clamp_range(0.001..=1.0)
+                        .speed(0.001)
+                        .prefix("band ")
+                        .suffix(" rad"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut stability.window)
+                        .clamp_range(0.05..=10.0)
+                        .speed(0.01)
+                        .prefix("window ")
+                        .suffix(" s"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut stability.growth)
+                        .clamp_range(1.0..=5.0)
+                        .speed(0.01)
+                        .prefix("unstable past x"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut invariants.enabled, "Check state each step");
+                ui.add_enabled(
+                    invariants.enabled,
+                    egui::Checkbox::new(&mut invariants.pause, "Pause on violation"),
+                );
+                ui.add_enabled(
+                    invariants.enabled,
+                    egui::DragValue::new(&mut invariants.max_velocity)
+                        .clamp_range(1.0..=1e6)
+                        .prefix("max |da| "),
+                );
+            });
+            if let Some((entity, violation)) = invariants.last_violation.clone() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(
+                        egui::Color32::RED,
+                        format!("Pendulum #{}: {}", entity.index(), violation),
+                    );
+                    if ui.button("Dismiss").clicked() {
+                        invariants.last_violation = None;
+                    }
+                });
+            }
+            ui.horizontal(|ui| {
+                ui.label("New pendulums");
+                ui.add(
+                    egui::DragValue::new(&mut layout.columns)
+                        .clamp_range(1..=32)
+                        .suffix(" columns"),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut layout.spacing.x)
+                        .clamp_range(1.0..=100.0)
+                        .prefix("x "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut layout.spacing.y)
+                        .clamp_range(1.0..=100.0)
+                        .prefix("y "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut layout.window_columns)
+                        .clamp_range(1..=8)
+                        .suffix(" window columns"),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("Initial angle");
+                ui.add(egui::DragValue::new(&mut ranges.angle.0).speed(0.05));
+                ui.add(egui::DragValue::new(&mut ranges.angle.1).speed(0.05));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Initial speed");
+                ui.add(egui::DragValue::new(&mut ranges.velocity.0).speed(0.05));
+                ui.add(egui::DragValue::new(&mut ranges.velocity.1).speed(0.05));
+            });
+            ui.add(
+                egui::Slider::new(&mut clock.time_scale, 0.1..=4.0)
+                    .logarithmic(true)
+                    .text("Time scale"),
+            );
+            ui.horizontal(|ui| {
+                ui.label("Integrator");
+                ui.radio_value(&mut *integrator, IntegratorKind::Euler, "Euler");
+                ui.radio_value(&mut *integrator, IntegratorKind::Rk4, "RK4");
+            });
+            if ui
+                .add(
+                    egui::Slider::new(&mut clock.dt, 0.001..=1.0)
+                        .logarithmic(true)
+                        .text("Step size (s)"),
+                )
+                .changed()
+            {
+                dt_events.send(DtChangedEvent);
+            }
+            let limit = pendulums
+                .iter()
+                .map(|pendulum| stable_dt(*integrator, pendulum))
+                .fold(f32::INFINITY, f32::min);
+            if clock.dt > limit {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!(
+                        "Step size above {:.3}s, the integrator is likely unstable",
+                        limit
+                    ),
+                );
+            }
+            ui.horizontal(|ui| {
+                ui.label("Export directory");
+                ui.text_edit_singleline(&mut export.directory);
+            });
+            ui.horizontal(|ui| {
+                ui.label("Plot image size");
+                ui.add(egui::DragValue::new(&mut export.plot_size.0).clamp_range(100..=8000));
+                ui.add(egui::DragValue::new(&mut export.plot_size.1).clamp_range(100..=8000));
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Seed");
+                ui.add(egui::DragValue::new(&mut rng.seed));
+                if ui.button("Reseed").clicked() {
+                    rng.reseed();
+                }
+            });
+
+            let mut max_len = capacity.0;
+            if ui
+                .add(
+                    egui::Slider::new(&mut max_len, 100..=100_000)
+                        .logarithmic(true)
+                        .text("History length"),
+                )
+                .changed()
+            {
+                capacity.0 = max_len;
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Save config").clicked() {
+                    config_events.send(ConfigEvent::Save);
+                }
+                if ui.button("Load config").clicked() {
+                    config_events.send(ConfigEvent::Load);
+                }
+                // Loading leaves them where they are, so this is not obvious otherwise
+                ui.weak("Cart-poles and double pendulums are not saved");
+            });
+            ui.horizontal(|ui| match recorder.mode {
+                RecorderMode::Idle => {
+                    if ui.button("Start recording").clicked() {
+                        recorder_events.send(RecorderEvent::StartRecording);
+                    }
+                    if ui.button("Play last recording").clicked() {
+                        recorder_events.send(RecorderEvent::Replay);
+                    }
+                }
+                RecorderMode::Recording => {
+                    if ui.button("Stop recording").clicked() {
+                        recorder_events.send(RecorderEvent::StopRecording);
+                    }
+                    ui.label(format!("{} frames", recorder.recording.frames.len()));
+                }
+                RecorderMode::Replaying => {
+                    if ui.button("Stop replay").clicked() {
+                        recorder_events.send(RecorderEvent::StopReplay);
+                    }
+                    ui.label(format!(
+                        "frame {} / {}",
+                        recorder.cursor + 1,
+                        recorder.recording.frames.len()
+                    ));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Add PID pendulum").clicked() {
+                    pendulum_events.send(PendulumEvent::AddPid);
+                }
+                if ui.button("Add LQR pendulum").clicked() {
+                    pendulum_events.send(PendulumEvent::AddLqr);
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rk4_conserves_energy() {
+        let mut pendulum = Pendulum::default();
+        let initial = energy(&pendulum);
+
+        for _ in 0..1000 {
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        let drift = (energy(&pendulum) - initial).abs() / initial.abs();
+        assert!(drift < 0.01, "energy drifted by {}", drift);
+    }
+
+    #[test]
+    fn substeps_reduce_energy_drift_without_changing_the_control_rate() {
+        // Short and under high gravity, where one Euler step per physics step gains energy fast
+        let stiff = |substeps: u32| Pendulum {
+            length: 0.5,
+            gravity: 30.0,
+            control_delay_steps: 2,
+            physics_substeps: substeps,
+            ..default()
+        };
+        let drift = |substeps: u32| {
+            let mut pendulum = stiff(substeps);
+            let initial = energy(&pendulum);
+            for _ in 0..200 {
+                pendulum.step_delayed(IntegratorKind::Euler, DEFAULT_DT);
+            }
+            (energy(&pendulum) - initial).abs() / initial.abs()
+        };
+        assert!(drift(10) < drift(1) / 5.0, "{} vs {}", drift(10), drift(1));
+
+        // The delayed control arrives after the same number of physics steps either way
+        let applied_after = |substeps: u32| {
+            let mut pendulum = stiff(substeps);
+            pendulum.control = 0.5;
+            (1..10)
+                .find(|_| {
+                    pendulum.step_delayed(IntegratorKind::Euler, DEFAULT_DT);
+                    pendulum.applied_control == 0.5
+                })
+                .unwrap()
+        };
+        assert_eq!(applied_after(10), applied_after(1));
+        assert_eq!(applied_after(1), 3);
+    }
+
+    #[test]
+    fn energy_zero_hanging_at_rest() {
+        let pendulum = Pendulum {
+            a: 0.0,
+            da: 0.0,
+            ..default()
+        };
+        assert_eq!(energy(&pendulum), 0.0);
+
+        let upright = Pendulum { a: PI, ..pendulum };
+        assert!((energy(&upright) - upright.upright_energy()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn free_tile_skips_occupied_slots() {
+        let layout = PendulumLayout::default();
+        let first = layout.free_tile(&[]);
+        let second = layout.free_tile(&[first]);
+        assert_ne!(first, second);
+        assert_eq!(layout.free_tile(&[second]), first);
+    }
+
+    #[test]
+    fn layout_wraps_rows_and_windows() {
+        let layout = PendulumLayout {
+            columns: 3,
+            spacing: Vec2::new(10.0, 20.0),
+            window_columns: 3,
+        };
+        assert_eq!(layout.tile(0), Vec3::new(-10.0, 20.0, 0.0));
+        assert_eq!(layout.tile(2), Vec3::new(10.0, 20.0, 0.0));
+        assert_eq!(layout.tile(3), Vec3::new(-10.0, 40.0, 0.0));
+
+        assert_eq!(layout.window_pos(1), (20.0 + WINDOW_SPAN / 2.0, 20.0));
+        assert_eq!(layout.window_pos(3), (20.0, 20.0 + WINDOW_ROW));
+        // The defaults keep the startup layout
+        let default = PendulumLayout::default();
+        assert_eq!(default.tile(0).x, -49.0);
+        assert_eq!(default.window_pos(1), (1100.0, 20.0));
+    }
+
+    #[test]
+    fn time_scale_changes_step_count_not_size() {
+        let steps = |time_scale: f32| {
+            let mut clock = SimulationClock {
+                time_scale,
+                ..default()
+            };
+            let mut steps = 0;
+            for _ in 0..100 {
+                clock.advance(1.0 / 60.0);
+                while clock.consume_step() {
+                    steps += 1;
+                }
+            }
+            assert_eq!(clock.dt, DEFAULT_DT);
+            steps
+        };
+
+        assert_eq!(steps(1.0), 33);
+        assert_eq!(steps(0.25), 8);
+        assert_eq!(steps(4.0), 133);
+    }
+
+    fn pid_snapshot(pid: &PID) -> TuningSnapshot {
+        TuningSnapshot::new(
+            Some(pid),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            SetPointDriver::None,
+        )
+    }
+
+    #[test]
+    fn undo_stack_drops_the_oldest_snapshots() {
+        let mut stack = UndoStack::default();
+        for gain in 0..UNDO_DEPTH + 5 {
+            stack.push(pid_snapshot(&PID {
+                proportional_gain: gain as f32,
+                ..default()
+            }));
+        }
+
+        assert_eq!(stack.snapshots.len(), UNDO_DEPTH);
+        assert_eq!(
+            stack.snapshots[0].pid.as_ref().unwrap().proportional_gain,
+            5.0
+        );
+    }
+
+    #[test]
+    fn undo_restores_parameters_but_keeps_runtime_state() {
+        let before = PID {
+            proportional_gain: -3.0,
+            accumulator: -1.0,
+            ..default()
+        };
+        let mut live = PID {
+            proportional_gain: -8.0,
+            accumulator: 2.0,
+            ..default()
+        };
+        live.error_history.push(0.5);
+
+        let snapshot = pid_snapshot(&before);
+        assert!(snapshot.same_parameters(&pid_snapshot(&PID {
+            accumulator: 4.0,
+            ..before.clone()
+        })));
+        assert!(!snapshot.same_parameters(&pid_snapshot(&live)));
+
+        snapshot.restore(Some(&mut live), None, None, None, None, None, None, None);
+        assert_eq!(live.proportional_gain, -3.0);
+        assert_eq!(live.accumulator, 2.0);
+        assert_eq!(live.error_history.end(), 1);
+    }
+
+    #[test]
+    fn undo_leaves_driven_set_points_alone() {
+        let snapshot = |pid: &PID, bang: &BangBang| {
+            TuningSnapshot::new(
+                Some(pid),
+                None,
+                None,
+                None,
+                Some(bang),
+                None,
+                None,
+                None,
+                SetPointDriver::Ramp,
+            )
+        };
+        let pid = PID::balancing();
+        let bang = BangBang::default();
+        let before = snapshot(&pid, &bang);
+        let mut ramped = PID {
+            set_point: PI + 0.3,
+            ..pid.clone()
+        };
+        assert!(before.same_parameters(&snapshot(&ramped, &bang)));
+
+        let mut moved = BangBang {
+            set_point: 1.0,
+            ..bang
+        };
+        assert!(!before.same_parameters(&snapshot(&ramped, &moved)));
+
+        before.restore(
+            Some(&mut ramped),
+            None,
+            None,
+            None,
+            Some(&mut moved),
+            None,
+            None,
+            None,
+        );
+        assert_eq!(ramped.set_point, PI + 0.3);
+        assert_eq!(moved.set_point, BangBang::default().set_point);
+    }
+
+    #[test]
+    fn summary_row_averages_the_tail_for_steady_state_error() {
+        let mut errors = vec![1.0, -0.2];
+        errors.extend([0.01, -0.01].repeat(10));
+        let row = SummaryRow::new(
+            "PID #0".to_string(),
+            "-".to_string(),
+            &errors,
+            2.5,
+            0.1,
+            SETTLING_BAND,
+        );
+
+        assert!((row.steady_state_error - 0.01).abs() < 1e-6);
+        let cells = row.cells();
+        assert_eq!(cells[2], "20.0");
+        assert_eq!(cells[3], "0.20");
+        assert_eq!(cells[4], "2.500");
+    }
+
+    #[test]
+    fn summary_exports_quote_and_escape_cells() {
+        let rows = [SummaryRow::new(
+            "PID #0".to_string(),
+            "Kp -8, Ki | 1".to_string(),
+            &[],
+            0.0,
+            0.1,
+            SETTLING_BAND,
+        )];
+
+        let csv = summary_csv(&rows);
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.contains("\"Kp -8, Ki | 1\""));
+
+        let markdown = summary_markdown(&rows);
+        assert_eq!(
+            markdown.lines().nth(1),
+            Some("| --- | --- | --- | --- | --- | --- |")
+        );
+        assert!(markdown.contains("| PID #0 | Kp -8, Ki \\| 1 | - | - |"));
+    }
+
+    #[test]
+    fn step_metrics_of_damped_response() {
+        let errors = [1.0, 0.5, -0.2, 0.05, -0.01, 0.005, 0.0];
+        let metrics = step_metrics(&errors, 0.5, SETTLING_BAND).unwrap();
+
+        assert!((metrics.overshoot - 20.0).abs() < 1e-4);
+        assert_eq!(metrics.settling_time, Some(2.0));
+        assert_eq!(metrics.rise_time, Some(0.5));
+
+        let metrics = step_metrics(&[1.0, 0.5, 0.3], 0.5, SETTLING_BAND).unwrap();
+        assert_eq!(metrics.overshoot, 0.0);
+        assert_eq!(metrics.settling_time, None);
+        assert_eq!(metrics.rise_time, None);
+
+        assert!(step_metrics(&[], 0.5, SETTLING_BAND).is_none());
+    }
+
+    #[test]
+    fn wider_settling_band_settles_sooner() {
+        let errors = [1.0, 0.5, -0.2, 0.05, -0.01, 0.005, 0.0];
+        let settling = |band| step_metrics(&errors, 0.5, band).unwrap().settling_time;
+        assert_eq!(settling(0.02), Some(2.0));
+        assert_eq!(settling(0.1), Some(1.5));
+        assert_eq!(settling(0.3), Some(1.0));
+
+        let band = settling_band(PI, Some((0.1, -0.1)), 0.1).unwrap();
+        assert_eq!(band.0, PI);
+        assert!((band.1 - 0.02).abs() < 1e-6);
+        assert_eq!(settling_band(PI, Some((PI, PI)), 0.1), None);
+        assert_eq!(settling_band(PI, None, 0.1), None);
+    }
+
+    #[test]
+    fn linearization_holds_near_the_top_only() {
+        let largest = |a: f32| {
+            let mut check = LinearizationCheck::default();
+            let pendulum = Pendulum {
+                a,
+                ..Default::default()
+            };
+            check.run(&pendulum, IntegratorKind::Rk4, DEFAULT_DT);
+            assert_eq!(check.predicted.len(), check.actual.len());
+            assert_eq!(check.predicted[0], check.actual[0]);
+            (
+                check.divergence().fold(0.0, f32::max),
+                check.breakdown_time(),
+            )
+        };
+
+        let (near_top, breakdown) = largest(PI + 0.02);
+        assert!(near_top < 0.05, "{}", near_top);
+        assert_eq!(breakdown, None);
+        let (near_bottom, breakdown) = largest(0.1);
+        assert!(near_bottom > 1.0, "{}", near_bottom);
+        assert!(breakdown.is_some());
+    }
+
+    #[test]
+    fn step_test_reports_a_settled_step() {
+        let mut pendulum = Pendulum {
+            a: PI,
+            da: 0.0,
+            ..default()
+        };
+        let mut pid = PID::balancing();
+        let mut test = StepTest::default();
+        pid.set_point = test.start(pid.set_point);
+        assert!((pid.set_point - (PI + 0.2)).abs() < 1e-6);
+
+        while test.running.is_some() {
+            simulate_pid(&mut pid, &mut pendulum, IntegratorKind::Rk4, DEFAULT_DT, 1);
+            test.update(&pendulum, DEFAULT_DT, SETTLING_BAND);
+        }
+
+        let result = test.result.unwrap();
+        assert!(result.settled);
+        assert!(test.errors.len() as f32 * DEFAULT_DT < test.timeout);
+        let metrics = result.metrics.unwrap();
+        assert!(metrics.rise_time.is_some());
+        assert!(metrics.settling_time.is_some());
+    }
+
+    #[test]
+    fn impulse_response_matches_a_damped_oscillator() {
+        // x'' + 2 ζ ω x' + ω² x = 0 kicked to x' = 1, stepped exactly
+        let (zeta, omega, dt) = (0.1_f32, 2.0 * PI, 0.001);
+        let sigma = zeta * omega;
+        let damped = omega * (1.0 - zeta * zeta).sqrt();
+        let deviations: Vec<f32> = (1..=5000)
+            .map(|i| {
+                let t = i as f32 * dt;
+                (-sigma * t).exp() * (damped * t).sin() / damped
+            })
+            .collect();
+
+        let metrics = impulse_metrics(&deviations, dt).unwrap();
+        let decay = metrics.decay_rate.unwrap();
+        let frequency = metrics.frequency.unwrap();
+        assert!((decay - sigma).abs() < 0.02 * sigma, "{}", decay);
+        assert!(
+            (frequency - damped / (2.0 * PI)).abs() < 0.01,
+            "{}",
+            frequency
+        );
+    }
+
+    #[test]
+    fn impulse_test_kicks_the_pendulum_once() {
+        let mut pendulum = Pendulum {
+            a: PI,
+            da: 0.0,
+            ..default()
+        };
+        let mut pid = PID::balancing();
+        let mut test = ImpulseTest::default();
+        test.start(&mut pendulum, DEFAULT_DT);
+        assert_eq!(pendulum.da, test.size);
+
+        while test.running.is_some() {
+            simulate_pid(&mut pid, &mut pendulum, IntegratorKind::Rk4, DEFAULT_DT, 1);
+            test.update(&pendulum);
+        }
+
+        let result = test.result.unwrap();
+        assert!(result.peak > 0.0);
+        assert!(result.decay_rate.is_some());
+        assert!(test.deviations.last().unwrap().abs() < 0.1 * result.peak);
+    }
+
+    #[test]
+    fn preview_predicts_without_touching_the_live_state() {
+        let pendulum = Pendulum {
+            a: PI + 0.1,
+            da: 0.0,
+            ..default()
+        };
+        let pid = PID::balancing();
+
+        let predict = |gains| pid.predict(gains, &pendulum, IntegratorKind::Rk4, DEFAULT_DT);
+        let stable = predict(pid.gains());
+        let flipped = predict(pid.gains().map(|gain| -gain));
+        assert_eq!(
+            stable.len(),
+            (PREVIEW_DURATION / DEFAULT_DT).ceil() as usize + 1
+        );
+        assert_eq!(stable[0], pendulum.a);
+        assert!(angle_difference(*stable.last().unwrap(), PI).abs() < 0.05);
+        assert!(angle_difference(*flipped.last().unwrap(), PI).abs() > 0.1);
+        assert!(pid.error_history.iter().next().is_none());
+    }
+
+    #[test]
+    fn invariant_check_reports_each_broken_pendulum_once() {
+        let mut app = App::new();
+        app.insert_resource(InvariantCheck {
+            enabled: true,
+            ..default()
+        })
+        .init_resource::<SimState>()
+        .add_system(check_invariants);
+        let broken = || Pendulum {
+            da: f32::NAN,
+            ..default()
+        };
+        let first = app.world.spawn(broken()).id();
+        let second = app.world.spawn(broken()).id();
+
+        app.update();
+        app.update();
+        let check = app.world.resource::<InvariantCheck>();
+        assert_eq!(check.reported, HashSet::from([first, second]));
+        assert!(app.world.resource::<SimState>().paused);
+
+        app.world.get_mut::<Pendulum>(first).unwrap().da = 0.0;
+        app.update();
+        let check = app.world.resource::<InvariantCheck>();
+        assert_eq!(check.reported, HashSet::from([second]));
+    }
+
+    #[test]
+    fn invariant_check_catches_blown_up_state() {
+        let healthy = Pendulum::default();
+        assert_eq!(invariant_violation(&healthy, 1000.0), None);
+
+        let nan_control = Pendulum {
+            control: f32::NAN,
+            ..default()
+        };
+        let violation = invariant_violation(&nan_control, 1000.0).unwrap();
+        assert!(violation.starts_with("control"), "{}", violation);
+
+        let spinning = Pendulum {
+            da: 2000.0,
+            ..default()
+        };
+        assert!(invariant_violation(&spinning, 1000.0)
+            .unwrap()
+            .starts_with("angular velocity"));
+        let dead_zone = Pendulum {
+            control_min: 0.2,
+            control_max: 1.0,
+            control: 0.0,
+            ..default()
+        };
+        assert_eq!(invariant_violation(&dead_zone, 1000.0), None);
+        assert!(invariant_violation(
+            &Pendulum {
+                control: 0.1,
+                ..dead_zone
+            },
+            1000.0
+        )
+        .is_some());
+        assert!(invariant_violation(
+            &Pendulum {
+                a: f32::INFINITY,
+                ..default()
+            },
+            1000.0
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn matrices_export_in_both_syntaxes() {
+        let matrix = DMatrix::from_row_slice(2, 2, &[1.0, 0.5, -2.0, 0.25]);
+        assert_eq!(
+            format_matrix("A", &matrix, MatrixFormat::Matlab),
+            "A = [1e0 5e-1;\n     -2e0 2.5e-1];"
+        );
+        assert_eq!(
+            format_matrix("A", &matrix, MatrixFormat::NumPy),
+            "A = np.array([[1e0, 5e-1],\n              [-2e0, 2.5e-1]])"
+        );
+
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        lqr.gain().unwrap();
+        let text = lqr.matrices(&pendulum.model(), DEFAULT_DT, MatrixFormat::NumPy);
+        assert!(text.starts_with("# Pendulum linearized at"));
+        for name in ["A", "B", "Q", "R", "K"] {
+            assert!(
+                text.contains(&format!("\n{} = np.array(", name)),
+                "{}",
+                text
+            );
+        }
+
+        lqr.integral_action = true;
+        lqr.update_model(&pendulum, DEFAULT_DT);
+        lqr.gain().unwrap();
+        let text = lqr.matrices(&pendulum.model(), DEFAULT_DT, MatrixFormat::Matlab);
+        assert!(text.contains("angle error integral]"), "{}", text);
+        let (a, _) = lqr.augmented.unwrap();
+        for (name, matrix) in [
+            ("A", DMatrix::from_column_slice(3, 3, a.as_slice())),
+            (
+                "K",
+                DMatrix::from_column_slice(1, 3, lqr.integral_k.as_slice()),
+            ),
+        ] {
+            let expected = format_matrix(name, &matrix, MatrixFormat::Matlab);
+            assert!(text.contains(&expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn stability_follows_the_error_envelope() {
+        let indicator = StabilityIndicator::default();
+        let dt = 0.01;
+        let status_of = |envelope: fn(f32) -> f32| {
+            let mut stability = Stability::default();
+            for i in 0..300 {
+                let t = i as f32 * dt;
+                stability.update(envelope(t) * (10.0 * t).cos(), &indicator, dt);
+            }
+            stability.status
+        };
+
+        assert_eq!(
+            status_of(|t| 0.5 * (-0.5 * t).exp()),
+            Some(StabilityStatus::Recovering)
+        );
+        assert_eq!(
+            status_of(|t| 0.5 * (-3.0 * t).exp()),
+            Some(StabilityStatus::Balanced)
+        );
+        assert_eq!(
+            status_of(|t| 0.01 * t.exp()),
+            Some(StabilityStatus::Unstable)
+        );
+    }
+
+    #[test]
+    fn disturbances_wait_for_friction_identification() {
+        let mut app = App::new();
+        app.init_resource::<IntegratorKind>()
+            .init_resource::<SimulationClock>()
+            .init_resource::<Grab>()
+            .add_system(move_pendulum);
+        let entity = app
+            .world
+            .spawn((
+                Pendulum {
+                    a: 0.0,
+                    da: 0.0,
+                    ..default()
+                },
+                Disturbance {
+                    constant: 5.0,
+                    ..default()
+                },
+                PeriodicDisturbance {
+                    amplitude: 5.0,
+                    ..default()
+                },
+                FrictionIdentification::new(ControllerKind::Manual),
+            ))
+            .id();
+
+        app.update();
+        assert_eq!(app.world.get::<Pendulum>(entity).unwrap().da, 0.0);
+        assert_eq!(
+            app.world.get::<PeriodicDisturbance>(entity).unwrap().time,
+            0.0
+        );
+
+        app.world
+            .get_mut::<FrictionIdentification>(entity)
+            .unwrap()
+            .running = false;
+        app.update();
+        assert!(app.world.get::<Pendulum>(entity).unwrap().da > 0.0);
+    }
+
+    #[test]
+    fn stability_starts_over_with_the_controller() {
+        let mut app = App::new();
+        app.init_resource::<StabilityIndicator>()
+            .init_resource::<SimulationClock>()
+            .add_system(update_stability);
+        let entity = app
+            .world
+            .spawn((
+                Pendulum {
+                    controller: ControllerKind::Pid,
+                    ..default()
+                },
+                PID::balancing(),
+                Stability::default(),
+            ))
+            .id();
+
+        app.update();
+        app.update();
+        let stability = app.world.get::<Stability>(entity).unwrap();
+        assert_eq!(stability.errors.len(), 2);
+        assert!(stability.status.is_some());
+
+        app.world.get_mut::<Pendulum>(entity).unwrap().controller = ControllerKind::Park;
+        app.update();
+        assert_eq!(app.world.get::<Stability>(entity).unwrap().errors.len(), 1);
+
+        app.world.get_mut::<Pendulum>(entity).unwrap().controller = ControllerKind::SwingUp;
+        app.update();
+        let stability = app.world.get::<Stability>(entity).unwrap();
+        assert!(stability.errors.is_empty());
+        assert_eq!(stability.status, None);
+    }
+
+    #[test]
+    fn set_points_follow_the_active_controller() {
+        let mut world = World::new();
+        let entity = world
+            .spawn((
+                PID::default(),
+                BangBang {
+                    set_point: PI + 0.3,
+                    ..default()
+                },
+            ))
+            .id();
+        let mut query = world.query::<SetPoints>();
+        let mut set_points = query.get_mut(&mut world, entity).unwrap();
+
+        assert_eq!(set_points.get(ControllerKind::BangBang), Some(PI + 0.3));
+        assert_eq!(set_points.get(ControllerKind::Park), Some(0.0));
+        assert_eq!(set_points.get(ControllerKind::SwingUp), None);
+        assert_eq!(set_points.get(ControllerKind::Lqr), None);
+
+        assert!(set_points.set(ControllerKind::BangBang, 3.0));
+        assert!(!set_points.set(ControllerKind::Park, 3.0));
+        assert!(!set_points.set(ControllerKind::Lqr, 3.0));
+        assert_eq!(set_points.get(ControllerKind::BangBang), Some(3.0));
+        assert_eq!(set_points.get(ControllerKind::Pid), Some(0.0));
+
+        set_points.set_all(2.0);
+        assert_eq!(set_points.get(ControllerKind::Pid), Some(2.0));
+        assert_eq!(set_points.get(ControllerKind::BangBang), Some(2.0));
+    }
+
+    #[test]
+    fn lqr_balances_in_low_gravity() {
+        let mut pendulum = Pendulum {
+            gravity: 1.62,
+            ..default()
+        };
+        let mut lqr = LQR::new(PI, Pendulum::default().get_system(PI, DEFAULT_DT));
+        let earth_gain = lqr.gain().unwrap();
+
+        lqr.set_system(pendulum.get_system(PI, DEFAULT_DT));
+        assert_ne!(lqr.gain().unwrap(), earth_gain);
+
+        for _ in 0..1000 {
+            let control = lqr.control(&pendulum, (pendulum.a, pendulum.da)).unwrap();
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+    }
+
+    #[test]
+    fn lqr_tolerates_length_mismatch() {
+        let mut pendulum = Pendulum::default();
+        pendulum.model = Some(pendulum.params());
+        pendulum.length *= 1.2;
+
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        assert_eq!(
+            pendulum.get_system(PI, DEFAULT_DT),
+            Pendulum::default().get_system(PI, DEFAULT_DT)
+        );
+
+        for _ in 0..1000 {
+            let control = lqr.control(&pendulum, (pendulum.a, pendulum.da)).unwrap();
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+    }
+
+    #[test]
+    fn lqr_balances_on_observed_velocity() {
+        let mut pendulum = Pendulum {
+            a: PI + 0.3,
+            ..default()
+        };
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        let mut observer = LuenbergerObserver::default();
+
+        for _ in 0..400 {
+            // Only the angle reaches the controller
+            observer.update(
+                pendulum.get_system(PI, DEFAULT_DT),
+                pendulum.control,
+                pendulum.a,
+            );
+            let (_, da) = observer.estimate();
+            let control = lqr.control(&pendulum, (pendulum.a, da)).unwrap();
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        assert!(observer.error.is_none());
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+        assert!((observer.estimate().1 - pendulum.da).abs() < 0.01);
+    }
+
+    #[test]
+    fn setpoint_ramp_crosses_the_seam() {
+        let mut ramp = SetpointRamp {
+            target: 0.1,
+            ..SetpointRamp::new(6.2)
+        };
+        let set_points: Vec<f32> = (0..30).map(|_| ramp.advance(0.01)).collect();
+        assert!(set_points
+            .iter()
+            .all(|a| angle_difference(*a, 0.0).abs() <= 0.1 + 1e-5));
+        assert!(set_points.iter().all(|a| (0.0..TAU).contains(a)));
+        assert!(set_points[0] > 6.2);
+        assert_eq!(*set_points.last().unwrap(), 0.1);
+        assert_eq!(ramp.departed, Some((6.2, 0.1)));
+
+        // The band stays sized by the move after the ramp arrives, until the target moves again
+        ramp.advance(0.01);
+        assert_eq!(ramp.departed, Some((6.2, 0.1)));
+        ramp.target = 0.5;
+        ramp.advance(0.01);
+        assert_eq!(ramp.departed, Some((0.1, 0.5)));
+    }
+
+    #[test]
+    fn square_reference_switches_each_half_period() {
+        let mut reference = ReferenceSignal {
+            kind: ReferenceKind::Square,
+            amplitude: 0.5,
+            frequency: 1.0,
+            ..default()
+        };
+        let dt = 0.1;
+
+        let set_points: Vec<f32> = (0..10).map(|_| reference.advance(dt)).collect();
+        // The samples on the edges are left out, the sign of sin there is rounding noise
+        assert!(set_points[1..5]
+            .iter()
+            .all(|a| (a - (PI + 0.5)).abs() < 1e-5));
+        assert!(set_points[6..]
+            .iter()
+            .all(|a| (a - (PI - 0.5)).abs() < 1e-5));
+
+        reference.reset();
+        assert_eq!(reference.advance(dt), set_points[0]);
+    }
+
+    #[test]
+    fn continuous_gain_of_double_integrator() {
+        let a = Matrix2::new(0.0, 1.0, 0.0, 0.0);
+        let b = Matrix2x1::new(0.0, 1.0);
+        let k = continuous_gain(&a, &b, &Q::identity(), &R::identity()).unwrap();
+
+        assert!((k[0] - 1.0).abs() < 1e-3);
+        assert!((k[1] - 3f32.sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn continuous_lqr_balances() {
+        let mut pendulum = Pendulum::default();
+        let mut lqr = LQR {
+            continuous: true,
+            ..LQR::new(PI, pendulum.get_continuous_system(PI))
+        };
+
+        for _ in 0..1000 {
+            let control = lqr.control(&pendulum, (pendulum.a, pendulum.da)).unwrap();
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+    }
+
+    #[test]
+    fn lqr_relinearizes_only_past_the_tolerance() {
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        assert!(lqr.needs_relinearizing());
+
+        lqr.update_model(&pendulum, DEFAULT_DT);
+        lqr.set_point = PI + 0.5 * RELINEARIZE_TOLERANCE;
+        assert!(!lqr.needs_relinearizing());
+        lqr.set_point = PI + 2.0 * RELINEARIZE_TOLERANCE;
+        assert!(lqr.needs_relinearizing());
+
+        // Across the seam is as close as it looks
+        lqr.set_point = -PI + 0.5 * RELINEARIZE_TOLERANCE;
+        assert!(!lqr.needs_relinearizing());
+    }
+
+    #[test]
+    fn lqr_balances_off_vertical_set_point() {
+        let mut pendulum = Pendulum {
+            a: 2.8,
+            da: 0.0,
+            ..default()
+        };
+        let mut lqr = LQR::new(2.5, pendulum.get_system(PI, DEFAULT_DT));
+        lqr.update_model(&pendulum, DEFAULT_DT);
+        assert_eq!(lqr.a, pendulum.get_system(2.5, DEFAULT_DT).0);
+
+        for _ in 0..1000 {
+            let control = lqr.control(&pendulum, (pendulum.a, pendulum.da)).unwrap();
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+        assert!(angle_difference(pendulum.a, 2.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn auto_tune_beats_default_costs() {
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        let default_cost = lqr.tuning_cost(&pendulum, DEFAULT_DT);
+
+        let [pos_cost, vel_cost, power_cost] = lqr.auto_tune(&pendulum, DEFAULT_DT);
+
+        assert_eq!(lqr.q, Q::<2>::new(pos_cost, 0.0, 0.0, vel_cost));
+        assert_eq!(lqr.r, R::new(power_cost));
+        assert!(lqr.tuning_cost(&pendulum, DEFAULT_DT) <= default_cost);
+    }
+
+    #[test]
+    fn cascade_balances_with_clamped_velocity_command() {
+        let mut pendulum = Pendulum::default();
+        let mut cascade = Cascade::default();
+
+        for _ in 0..600 {
+            pendulum.measured_a = pendulum.a;
+            pendulum.measured_da = pendulum.da;
+            let control = cascade.control(&pendulum, DEFAULT_DT);
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+        assert!(pendulum.da.abs() < 0.01);
+
+        let (error, velocity_error) = cascade.errors((PI + 1.5, 0.5));
+        assert!((error - 1.5).abs() < 1e-5);
+        assert_eq!(cascade.velocity_command, -cascade.max_velocity);
+        assert!((velocity_error - (0.5 + cascade.max_velocity)).abs() < 1e-5);
+
+        cascade.accumulator = 0.5;
+        cascade.error_history.push(1.5);
+        cascade.velocity_error_history.push(2.5);
+        cascade.reset();
+        assert_eq!(cascade.accumulator, 0.0);
+        assert_eq!(cascade.error_history.end(), 0);
+        assert_eq!(cascade.velocity_error_history.end(), 0);
+    }
+
+    #[test]
+    fn park_settles_at_the_bottom_and_switches_off() {
+        let mut pendulum = Pendulum {
+            a: PI,
+            da: 0.0,
+            ..default()
+        };
+        let mut park = Park::default();
+
+        for _ in 0..(60.0 / DEFAULT_DT) as usize {
+            let control = park.control((pendulum.a, pendulum.da));
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        assert!(park.parked);
+        assert_eq!(pendulum.control, 0.0);
+        assert!(angle_difference(pendulum.a, 0.0).abs() < park.angle_threshold);
+
+        // Knocked out of the band it takes over again
+        assert_ne!(park.control((0.5, 0.0)), 0.0);
+        assert!(!park.parked);
+    }
+
+    #[test]
+    fn sliding_mode_reaches_surface_and_balances() {
+        let mut pendulum = Pendulum {
+            a: PI + 0.3,
+            da: 0.0,
+            ..default()
+        };
+        let sliding = SlidingMode::default();
+
+        let mut surface = 0.0;
+        for _ in 0..400 {
+            surface = sliding.surface((pendulum.a, pendulum.da));
+            pendulum.set_control(sliding.control(surface), DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        assert!(surface.abs() < sliding.boundary_layer);
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+    }
+
+    /// Pendulum with every parameter `get_system` reads set away from its default
+    fn skewed_pendulum() -> Pendulum {
+        Pendulum {
+            length: 7.0,
+            friction: 0.3,
+            gravity: 9.0,
+            control_power: 4.0,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn get_system_matches_numerical_linearization() {
+        let pendulum = skewed_pendulum();
+        let dt = 0.02;
+        let eps = 1e-3;
+
+        for angle in [PI, 0.0] {
+            let dda = |a: f32, da: f32, u: f32| derivative(&pendulum, a, da, u).1;
+            let d_a = (dda(angle + eps, 0.0, 0.0) - dda(angle - eps, 0.0, 0.0)) / (2.0 * eps);
+            let d_da = (dda(angle, eps, 0.0) - dda(angle, -eps, 0.0)) / (2.0 * eps);
+            let d_u = (dda(angle, 0.0, eps) - dda(angle, 0.0, -eps)) / (2.0 * eps);
+
+            // One step at constant acceleration: a += da dt + dda dt²/2, da += dda dt
+            let expected_a = Matrix2::new(
+                1.0 + d_a * dt * dt / 2.0,
+                dt + d_da * dt * dt / 2.0,
+                d_a * dt,
+                1.0 + d_da * dt,
+            );
+            let expected_b = Matrix2x1::new(d_u * dt * dt / 2.0, d_u * dt);
+
+            let (a, b) = pendulum.get_system(angle, dt);
+            assert!((a - expected_a).abs().max() < 1e-4, "A at {}: {}", angle, a);
+            assert!((b - expected_b).abs().max() < 1e-4, "B at {}: {}", angle, b);
+        }
+    }
+
+    #[test]
+    fn euler_and_zero_order_hold_agree_as_dt_shrinks() {
+        let pendulum = skewed_pendulum();
+
+        // Both match exp(A dt) to first order, so the gap per unit of dt goes to zero with dt
+        let gap = |dt: f32| {
+            let (a_euler, b_euler) =
+                pendulum.get_discrete_system(PI, dt, DiscretizationMethod::Euler);
+            let (a_zoh, b_zoh) =
+                pendulum.get_discrete_system(PI, dt, DiscretizationMethod::ZeroOrderHold);
+            (a_euler - a_zoh)
+                .abs()
+                .max()
+                .max((b_euler - b_zoh).abs().max())
+                / dt
+        };
+        let gaps = [0.1, 0.01, 0.001].map(gap);
+        assert!(gaps[1] < gaps[0] / 5.0, "{:?}", gaps);
+        assert!(gaps[2] < gaps[1] / 5.0, "{:?}", gaps);
+        assert!(gaps[2] < 1e-2, "{:?}", gaps);
+
+        // Without friction the held input over one step is exactly b dt²/2 for the angle
+        let (_, b) = zero_order_hold(
+            (Matrix2::new(0.0, 1.0, 0.0, 0.0), Matrix2x1::new(0.0, 2.0)),
+            0.1,
+        );
+        assert!((b - Matrix2x1::new(0.01, 0.2)).abs().max() < 1e-6);
+    }
+
+    #[test]
+    fn get_system_control_column() {
+        let pendulum = skewed_pendulum();
+
+        let (_, b) = pendulum.get_system(PI, DEFAULT_DT);
+
+        let power = pendulum.control_power;
+        assert_eq!(
+            b,
+            Matrix2x1::new(power / 2.0 * DEFAULT_DT.powi(2), power * DEFAULT_DT)
+        );
+    }
+
+    #[test]
+    fn symlog_round_trips_and_keeps_sign() {
+        for y in [-20.0, -0.01, 0.0, 1e-4, 3.0] {
+            assert!((symlog_inverse(symlog(y)) - y).abs() < 1e-9 * (1.0 + y.abs()));
+            assert_eq!(symlog(y).signum(), y.signum());
+        }
+        assert!(symlog(0.01) > 1.0);
+    }
+
+    #[test]
+    fn set_point_line_comes_back_from_the_error() {
+        let (mut angles, mut errors) = (History::default(), History::default());
+        for angle in [6.0, 0.1, PI] {
+            angles.push(angle);
+            errors.push(angle_difference(0.2, angle));
+        }
+
+        let points = set_point_points(&angles, &errors, 0.5);
+        let points = points.points();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[2].x, 1.0);
+        assert!(points.iter().all(|point| (point.y - 0.2).abs() < 1e-5));
+    }
+
+    #[test]
+    fn friction_feedforward_cancels_the_model_friction() {
+        let mut pendulum = Pendulum {
+            gravity: 0.0,
+            friction: 0.5,
+            coulomb_friction: 0.2,
+            measured_da: 1.5,
+            ..default()
+        };
+        let feedforward = Feedforward {
+            enabled: false,
+            friction: true,
+            ..default()
+        };
+
+        let control = feedforward.control(&pendulum, PI);
+        let (_, dda) = derivative(&pendulum, PI, pendulum.measured_da, control);
+        assert!(dda.abs() < 1e-5, "{}", dda);
+
+        // Only what the controller believes about the friction gets cancelled
+        pendulum.model = Some(PendulumParams {
+            friction: 0.0,
+            coulomb_friction: 0.0,
+            ..pendulum.params()
+        });
+        assert_eq!(feedforward.control(&pendulum, PI), 0.0);
+    }
+
+    #[test]
+    fn decimation_bounds_points_and_keeps_spikes() {
+        let mut points: Vec<[f64; 2]> = (0..10_000).map(|i| [i as f64, 0.0]).collect();
+        points[4321][1] = 5.0;
+        points[7777][1] = -3.0;
+
+        let decimated = decimate(points.clone(), 1000);
+        assert!(decimated.len() <= 1000);
+        assert!(decimated.contains(&[4321.0, 5.0]));
+        assert!(decimated.contains(&[7777.0, -3.0]));
+        assert!(decimated.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+
+        let short = points[..500].to_vec();
+        assert_eq!(decimate(short.clone(), 1000), short);
+    }
+
+    #[test]
+    fn periodic_disturbance_completes_cycles() {
+        let mut periodic = PeriodicDisturbance {
+            amplitude: 2.0,
+            frequency: 0.5,
+            ..default()
+        };
+
+        // Two whole periods sampled at step midpoints average out and peak near the amplitude
+        let samples: Vec<f32> = (0..80).map(|_| periodic.advance(DEFAULT_DT)).collect();
+        let mean = samples.iter().sum::<f32>() / samples.len() as f32;
+        let peak = samples.iter().fold(0.0, |m: f32, s| m.max(s.abs()));
+
+        assert!(mean.abs() < 1e-4);
+        assert!((peak - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn model_without_control_power_is_uncontrollable() {
+        let pendulum = Pendulum::default();
+        let (a, b) = pendulum.get_system(PI, DEFAULT_DT);
+        assert_eq!(controllability_rank((a, b)), 2);
+        assert_eq!(observability_rank(&a), 2);
+
+        let unpowered = Pendulum {
+            control_power: 0.0,
+            ..default()
+        };
+        let (a, b) = unpowered.get_system(PI, DEFAULT_DT);
+        assert!(controllability_rank((a, b)) < 2);
+        assert!(!is_controllable((a, b)));
+        assert_eq!(observability_rank(&a), 2);
+    }
+
+    #[test]
+    fn ill_conditioned_lqr_returns_error() {
+        let pendulum = Pendulum::default();
+
+        let mut free_power = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        free_power.set_gains(1.0, 1.0, 0.0);
+        assert!(free_power.gain().is_err());
+
+        let unpowered = Pendulum {
+            control_power: 0.0,
+            ..default()
+        };
+        let mut unstabilizable = LQR::new(PI, unpowered.get_system(PI, DEFAULT_DT));
+        assert!(unstabilizable.gain().is_err());
+        assert!(unstabilizable
+            .control(&unpowered, (unpowered.a, unpowered.da))
+            .is_err());
+
+        let mut broken = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        broken.set_system((A::from_element(f32::NAN), B::zeros()));
+        assert!(broken.gain().is_err());
+    }
+
+    #[test]
+    fn fast_pendulum_does_not_tunnel_through_stop() {
+        // Moves over a radian per step, the step ends on the far side of the stop
+        let mut pendulum = Pendulum {
+            a: PI,
+            da: 30.0,
+            max_angle: Some(PI + 0.5),
+            restitution: 0.5,
+            ..default()
+        };
+
+        pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+
+        assert_eq!(pendulum.a, PI + 0.5);
+        assert!(pendulum.da < 0.0);
+    }
+
+    #[test]
+    fn stops_confine_swing_across_zero() {
+        let mut pendulum = Pendulum {
+            a: 0.0,
+            da: 3.0,
+            min_angle: Some(-0.3),
+            max_angle: Some(0.3),
+            restitution: 1.0,
+            ..default()
+        };
+
+        for _ in 0..400 {
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+            assert!(angle_difference(pendulum.a, 0.0).abs() <= 0.3 + 1e-5);
+        }
+    }
+
+    #[test]
+    fn selection_cycles_through_pendulums() {
+        let entities: Vec<Entity> = (0..3).map(Entity::from_raw).collect();
+
+        assert_eq!(next_selection(&entities, None), Some(entities[0]));
+        assert_eq!(
+            next_selection(&entities, Some(entities[1])),
+            Some(entities[2])
+        );
+        assert_eq!(
+            next_selection(&entities, Some(entities[2])),
+            Some(entities[0])
+        );
+        // A selection that was deleted starts over from the first
+        assert_eq!(
+            next_selection(&entities, Some(Entity::from_raw(7))),
+            Some(entities[0])
+        );
+        assert_eq!(next_selection(&[], None), None);
+    }
+
+    #[test]
+    fn sign_check_flags_flipped_gains() {
+        let pendulum = Pendulum::default();
+        let pid = PID::balancing();
+        assert!(pid.sign_check(&pendulum, IntegratorKind::Rk4, DEFAULT_DT) < 1.0);
+
+        let flipped = PID {
+            proportional_gain: -pid.proportional_gain,
+            integral_gain: -pid.integral_gain,
+            derivative_gain: -pid.derivative_gain,
+            ..pid
+        };
+        assert!(flipped.sign_check(&pendulum, IntegratorKind::Rk4, DEFAULT_DT) > 1.0);
+    }
+
+    #[test]
+    fn real_time_factor_averages_over_the_window() {
+        let mut real_time = RealTimeFactor::default();
+        // Five 0.01 s steps in every 0.1 s frame, the physics runs at half speed
+        for _ in 0..11 {
+            real_time.frame(0.1);
+            for _ in 0..5 {
+                real_time.step(0.01);
+            }
+        }
+
+        let (factor, steps_per_frame) = real_time.last.unwrap();
+        assert!((factor - 0.5).abs() < 1e-4, "{}", factor);
+        assert!((steps_per_frame - 5.0).abs() < 1e-4);
+
+        real_time.clear();
+        assert!(real_time.last.is_none());
+    }
+
+    #[test]
+    fn disabled_control_is_never_due() {
+        let mut pendulum = Pendulum {
+            control_decimation: 1,
+            ..default()
+        };
+        assert!(pendulum.control_due());
+        pendulum.control_enabled = false;
+        for _ in 0..3 {
+            assert!(!pendulum.control_due());
+            pendulum.tick_control();
+        }
+
+        // Nothing queued behind the delay gets applied once control is off
+        let mut delayed = Pendulum {
+            control_delay_steps: 2,
+            ..default()
+        };
+        for _ in 0..2 {
+            delayed.set_control(0.5, DEFAULT_DT);
+            delayed.step_delayed(IntegratorKind::Euler, DEFAULT_DT);
+        }
+        delayed.release_control();
+        for _ in 0..3 {
+            delayed.step_delayed(IntegratorKind::Euler, DEFAULT_DT);
+            assert_eq!(delayed.applied_control, 0.0);
+        }
+
+        let mut pid = PID {
+            accumulator: 3.0,
+            accumulator_enabled: true,
+            ..PID::balancing()
+        };
+        pid.clear_integral();
+        assert_eq!(pid.accumulator, 0.0);
+        assert!(!pid.accumulator_enabled);
+    }
+
+    #[test]
+    fn nudges_wrap_around_the_circle() {
+        let nudge = SetpointNudge { step: 0.1 };
+        assert!((nudge.apply(PI, 1.0) - (PI + 0.1)).abs() < 1e-6);
+        assert!((nudge.apply(0.05, -1.0) - (TAU - 0.05)).abs() < 1e-5);
+        assert!(nudge.apply(TAU - 0.05, 1.0) < 0.1);
+    }
+
+    #[test]
+    fn frequency_sweep_measures_gain_and_phase() {
+        // An output at half the reference amplitude lagging by 30 degrees at every frequency
+        let (gain, lag) = (0.5, -PI / 6.0);
+        let mut reference = ReferenceSignal {
+            kind: ReferenceKind::Square,
+            frequency: 0.7,
+            ..default()
+        };
+        let mut pendulum = Pendulum::default();
+        let mut sweep = FrequencySweep::default();
+
+        let dt = 0.01;
+        while sweep.running() {
+            reference.advance(dt);
+            let phase = TAU * reference.frequency * reference.time + lag;
+            pendulum.a = reference.center + gain * reference.amplitude * phase.sin();
+            sweep.update(&pendulum, &mut reference, dt);
+        }
+
+        assert_eq!(sweep.results.len(), SWEEP_POINTS);
+        for response in &sweep.results {
+            assert!((response.magnitude - gain).abs() < 0.02, "{:?}", response);
+            assert!((response.phase - lag).abs() < 0.02, "{:?}", response);
+        }
+        assert_eq!(sweep.results[0].frequency, SWEEP_RANGE.0);
+        assert!((sweep.results[SWEEP_POINTS - 1].frequency - SWEEP_RANGE.1).abs() < 1e-4);
+        assert_eq!(reference.kind, ReferenceKind::Square);
+        assert_eq!(reference.frequency, 0.7);
+    }
+
+    #[test]
+    fn riccati_iteration_reports_convergence() {
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        assert!(lqr.gain().is_ok());
+        let stats = lqr.riccati.unwrap();
+        assert!(stats.converged);
+        assert!(stats.iterations > 1);
+        assert!(stats.residual < RICCATI_RESIDUAL_LIMIT);
+
+        lqr.max_iterations = 1;
+        lqr.dirty = true;
+        assert_eq!(lqr.gain(), Err("Riccati iteration did not converge"));
+        assert_eq!(lqr.riccati.unwrap().iterations, 1);
+        assert!(!lqr.riccati.unwrap().converged);
+    }
+
+    #[test]
+    fn riccati_recursion_matches_doubling() {
+        let pendulum = Pendulum::default();
+        let (a, b) = pendulum.get_system(PI, DEFAULT_DT);
+        let (q, r) = (Q::<2>::new(10.0, 0.0, 0.0, 1.0), R::new(1.0));
+
+        let (p, stats) = discrete_riccati((&a, &b), (&q, &r), 1e-7, 100).unwrap();
+        assert!(stats.converged);
+        let (k, p_recursion, stats) = dlqr((&a, &b), (&q, &r), 1e-7, 10000).unwrap();
+        assert!(stats.converged, "{:?}", stats);
+        assert!(stats.residual < RICCATI_RESIDUAL_LIMIT);
+        assert!((p_recursion - p).norm() / p.norm() < 1e-3);
+
+        let exact = (r + b.transpose() * p * b).try_inverse().unwrap() * b.transpose() * p * a;
+        assert!((k - exact).norm() / exact.norm() < 1e-3);
+
+        // Switching solvers keeps the gain, also at a step long enough for R^-1 B^T P to be off
+        for dt in [DEFAULT_DT, 0.5] {
+            let gain = |solver: RiccatiSolver| {
+                let mut lqr = LQR::new(PI, pendulum.get_system(PI, dt));
+                lqr.solver = solver;
+                lqr.max_iterations = solver.default_iterations();
+                lqr.set_gains(10.0, 1.0, 1.0);
+                lqr.gain().unwrap()
+            };
+            let (doubling, recursion) = (
+                gain(RiccatiSolver::Doubling),
+                gain(RiccatiSolver::Recursion),
+            );
+            assert!(
+                (doubling - recursion).norm() / recursion.norm() < 1e-3,
+                "{} against {}",
+                doubling,
+                recursion
+            );
+        }
+    }
+
+    #[test]
+    fn integral_action_removes_steady_state_error() {
+        let set_point = PI - 0.3;
+        let final_error = |integral_action: bool| {
+            let mut pendulum = Pendulum::default();
+            let mut lqr = LQR::new(set_point, pendulum.get_system(set_point, DEFAULT_DT));
+            lqr.feedforward.enabled = false;
+            lqr.integral_action = integral_action;
+            lqr.set_gains(10.0, 1.0, 1.0);
+            lqr.update_model(&pendulum, DEFAULT_DT);
+
+            for _ in 0..(20.0 / DEFAULT_DT) as usize {
+                let control = lqr.control(&pendulum, (pendulum.a, pendulum.da)).unwrap();
+                lqr.integrate(&pendulum, pendulum.a, control, DEFAULT_DT);
+                pendulum.set_control(control, DEFAULT_DT);
+                pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+            }
+            angle_difference(pendulum.a, set_point).abs()
+        };
+
+        let (plain, integral) = (final_error(false), final_error(true));
+        assert!(plain > 0.01, "{}", plain);
+        assert!(integral < plain / 10.0, "{} against {}", integral, plain);
+
+        let (a, b) =
+            Pendulum::default().get_augmented_system(PI, DEFAULT_DT, DiscretizationMethod::Euler);
+        assert_eq!(a[(2, 0)], DEFAULT_DT);
+        assert_eq!((a[(2, 2)], b[2]), (1.0, 0.0));
+    }
+
+    #[test]
+    fn lqr_integral_holds_at_saturation_and_restarts_with_the_set_point() {
+        let pendulum = Pendulum {
+            a: PI + 0.5,
+            control_min: -0.5,
+            control_max: 0.5,
+            ..default()
+        };
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        lqr.integral_action = true;
+        lqr.update_model(&pendulum, DEFAULT_DT);
+
+        let control = lqr.control(&pendulum, (pendulum.a, 0.0)).unwrap();
+        assert!(control.abs() > 0.5, "{}", control);
+        for _ in 0..100 {
+            lqr.integrate(&pendulum, pendulum.a, control, DEFAULT_DT);
+        }
+        assert_eq!(lqr.integral, 0.0);
+
+        // Unsaturated it gathers, until the set point moves
+        lqr.integrate(&pendulum, pendulum.a, 0.0, DEFAULT_DT);
+        assert!(lqr.integral > 0.0);
+        lqr.set_point = PI + 0.1;
+        lqr.control(&pendulum, (pendulum.a, 0.0)).unwrap();
+        assert_eq!(lqr.integral, 0.0);
+    }
+
+    #[test]
+    fn baselines_restore_gains_and_costs() {
+        let mut pid = PID::balancing();
+        pid.set_baseline();
+        pid.proportional_gain = 3.0;
+        pid.derivative_gain = 0.0;
+        pid.restore_baseline();
+        assert_eq!(pid.proportional_gain, PID::balancing().proportional_gain);
+        assert_eq!(pid.derivative_gain, PID::balancing().derivative_gain);
+
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        lqr.set_baseline();
+        let k = lqr.gain().unwrap();
+        lqr.set_gains(10.0, 0.1, 3.0);
+        assert_ne!(lqr.gain().unwrap(), k);
+        lqr.restore_baseline();
+        assert!((lqr.gain().unwrap() - k).norm() < 1e-4);
+    }
+
+    #[test]
+    fn disabled_pid_terms_drop_out_in_both_forms() {
+        let pendulum = Pendulum {
+            measured_a: PI + 0.2,
+            measured_da: 0.5,
+            control_min: -10.0,
+            control_max: 10.0,
+            ..default()
+        };
+
+        for form in [PidForm::Positional, PidForm::Incremental] {
+            let mut pid = PID {
+                anti_windup: AntiWindup::BackCalculation,
+                form,
+                ..PID::balancing()
+            };
+            for _ in 0..10 {
+                pid.control(&pendulum, DEFAULT_DT);
+            }
+
+            pid.i_enabled = false;
+            pid.d_enabled = false;
+            let control = pid.control(&pendulum, DEFAULT_DT);
+            assert!(
+                (control - 0.2 * pid.proportional_gain).abs() < 1e-4,
+                "{:?}",
+                form
+            );
+            assert_eq!(pid.accumulator, 0.0);
+            assert_eq!(pid.integral_gain, PID::balancing().integral_gain);
+
+            // Only the integral is left, which starts over from an empty accumulator
+            pid.p_enabled = false;
+            pid.i_enabled = true;
+            let control = pid.control(&pendulum, DEFAULT_DT);
+            assert!(
+                control.abs() <= (0.2 * pid.integral_gain * DEFAULT_DT).abs() + 1e-4,
+                "{:?}: {}",
+                form,
+                control
+            );
+        }
+    }
+
+    #[test]
+    fn acceleration_term_uses_model_acceleration() {
+        let pendulum = Pendulum {
+            measured_a: PI + 0.2,
+            measured_da: 0.5,
+            control_min: -10.0,
+            control_max: 10.0,
+            ..default()
+        };
+        let (_, dda) = derivative(&pendulum, pendulum.measured_a, pendulum.measured_da, 0.0);
+
+        for form in [PidForm::Positional, PidForm::Incremental] {
+            let mut pid = PID {
+                acceleration_gain: 0.1,
+                p_enabled: false,
+                i_enabled: false,
+                d_enabled: false,
+                form,
+                ..PID::balancing()
+            };
+            for _ in 0..3 {
+                let control = pid.control(&pendulum, DEFAULT_DT);
+                assert!((control - 0.1 * dda).abs() < 1e-4, "{:?}", form);
+            }
+        }
+    }
+
+    #[test]
+    fn rate_mode_spins_at_velocity_set_point() {
+        let mut pendulum = Pendulum::default();
+        let mut pid = PID {
+            mode: PidMode::Rate,
+            da_set_point: 2.0,
+            ..PID::balancing()
+        };
+
+        let mut velocities = Vec::new();
+        for _ in 0..3000 {
+            simulate_pid(&mut pid, &mut pendulum, IntegratorKind::Rk4, DEFAULT_DT, 1);
+            velocities.push(pendulum.da);
+        }
+
+        // Gravity speeds it up and slows it down over each turn
+        let recent = &velocities[2000..];
+        let mean = recent.iter().sum::<f32>() / recent.len() as f32;
+        assert!((mean - 2.0).abs() < 0.05, "{}", mean);
+        assert!(recent.iter().all(|&da| (da - 2.0).abs() < 0.5));
+    }
+
+    #[test]
+    fn switching_pid_form_does_not_jump() {
+        let mut pendulum = Pendulum {
+            a: PI + 0.2,
+            da: 0.0,
+            control_min: -10.0,
+            control_max: 10.0,
+            ..default()
+        };
+        let mut reference = PID {
+            anti_windup: AntiWindup::BackCalculation,
+            ..PID::balancing()
+        };
+        let mut switching = reference.clone();
+
+        for step in 0..300 {
+            if step % 20 == 0 {
+                switching.form = match switching.form {
+                    PidForm::Positional => PidForm::Incremental,
+                    PidForm::Incremental => PidForm::Positional,
+                };
+            }
+
+            pendulum.measured_a = pendulum.a;
+            pendulum.measured_da = pendulum.da;
+            let expected = reference.control(&pendulum, DEFAULT_DT);
+            let control = switching.control(&pendulum, DEFAULT_DT);
+            assert!(
+                (control - expected).abs() < 1e-4,
+                "step {}: {} vs {}",
+                step,
+                control,
+                expected
+            );
+
+            pendulum.set_control(expected, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+    }
+
+    #[test]
+    fn effort_integrates_squared_control_over_time() {
+        let mut pendulum = Pendulum::default();
+        pendulum.set_control(-0.5, DEFAULT_DT);
+
+        for _ in 0..(1.0 / DEFAULT_DT) as usize {
+            pendulum.accumulate_effort(DEFAULT_DT);
+        }
+
+        assert!((pendulum.effort - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn gain_schedule_interpolates_between_breakpoints() {
+        let pendulum = Pendulum::default();
+        let mut schedule = GainSchedule::default();
+        schedule.compute_gains(&pendulum, DEFAULT_DT).unwrap();
+
+        let (k0, k1) = (schedule.gains[3], schedule.gains[4]);
+        let midpoint = schedule.gain((schedule.angles[3] + schedule.angles[4]) / 2.0);
+        assert!((midpoint - (k0 + k1) / 2.0).norm() < 1e-4);
+
+        assert_eq!(schedule.gain(0.0), schedule.gains[0]);
+        assert_eq!(schedule.gain(TAU - 0.01), schedule.gains[6]);
+    }
+
+    #[test]
+    fn gain_schedule_balances_from_wide_angle() {
+        let mut pendulum = Pendulum {
+            a: PI + 1.0,
+            da: 0.0,
+            ..default()
+        };
+        let mut schedule = GainSchedule::default();
+        schedule.compute_gains(&pendulum, DEFAULT_DT).unwrap();
+
+        for _ in 0..1000 {
+            let k = schedule.gain(pendulum.a);
+            let x = Matrix2x1::new(
+                angle_difference(pendulum.a, schedule.set_point),
+                pendulum.da,
+            );
+            pendulum.set_control(*(-k * x).index(0), DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+    }
+
+    #[test]
+    fn kick_delivers_torque_times_duration() {
+        let mut disturbance = Disturbance {
+            torque: 10.0,
+            duration: 0.12,
+            ..default()
+        };
+        disturbance.kick();
+
+        let impulse: f32 = (0..10)
+            .map(|_| disturbance.advance(DEFAULT_DT) * DEFAULT_DT)
+            .sum();
+        assert!((impulse - 1.2).abs() < 1e-4);
+        assert_eq!(disturbance.advance(DEFAULT_DT), 0.0);
+    }
+
+    #[test]
+    fn coulomb_friction_holds_pendulum_near_rest() {
+        let start = Pendulum {
+            a: 0.05,
+            da: 0.0,
+            ..default()
+        };
+        let settle = |coulomb_friction: f32| {
+            let mut pendulum = Pendulum {
+                coulomb_friction,
+                ..start.clone()
+            };
+            for _ in 0..40 {
+                pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+            }
+            angle_difference(pendulum.a, start.a).abs()
+        };
+
+        assert!(settle(1.0) < 0.01);
+        assert!(settle(0.0) > 0.05);
+    }
+
+    #[test]
+    fn trail_keeps_the_latest_positions() {
+        let mut pendulum = Pendulum {
+            trail_length: 3,
+            ..default()
+        };
+        for a in [0.0, 1.0, 2.0, 3.0, 4.0] {
+            pendulum.a = a;
+            pendulum.record_trail();
+        }
+
+        assert_eq!(pendulum.trail.len(), 3);
+        let (x, y) = to_rectangular(pendulum.length, 4.0);
+        assert_eq!(pendulum.trail.back(), Some(&Vec2::new(x, y)));
+        let (x, y) = to_rectangular(pendulum.length, 2.0);
+        assert_eq!(pendulum.trail.front(), Some(&Vec2::new(x, y)));
+    }
+
+    #[test]
+    fn thick_arms_are_centered_parallel_lines() {
+        let offsets: Vec<Vec3> = arm_offsets(Vec3::X, 0.3).collect();
+        assert_eq!(offsets.len(), 3);
+        assert!(offsets[1].length() < 1e-6);
+        assert!((offsets[0] + offsets[2]).length() < 1e-6);
+        assert!((offsets[2].x - ARM_LINE_SPACING).abs() < 1e-6);
+
+        // Thinner than the spacing still draws the arm once
+        assert_eq!(arm_offsets(Vec3::X, 0.0).count(), 1);
+    }
+
+    #[test]
+    fn pivot_marker_surrounds_the_pivot() {
+        let center = Vec3::new(7.0, 25.0, 0.0);
+        let segments: Vec<(Vec3, Vec3)> = dot_marker(center, PIVOT_MARKER_RADIUS).collect();
+        assert_eq!(segments.len(), MARKER_SIDES * 3 / 2);
+        for (from, to) in segments {
+            assert!((from.distance(center) - PIVOT_MARKER_RADIUS).abs() < 1e-5);
+            assert!((to.distance(center) - PIVOT_MARKER_RADIUS).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn angle_about_pivot_inverts_to_rectangular() {
+        let pivot = Vec2::new(-7.0, 2.0);
+        for a in [0.0, 0.5, PI / 2.0, PI, 4.0, 6.0] {
+            let (x, y) = to_rectangular(10.0, a);
+            let angle = angle_about(pivot, pivot + Vec2::new(x, y) * 0.3);
+            assert!(angle_difference(angle, a).abs() < 1e-5, "{}: {}", a, angle);
+        }
+    }
+
+    #[test]
+    fn wrap_angle_into_range() {
+        assert!((wrap_angle(-0.1) - (TAU - 0.1)).abs() < 1e-5);
+        assert!((wrap_angle(6.3) - (6.3 - TAU)).abs() < 1e-5);
+        assert!((wrap_angle(100.0) - (100.0 - 15.0 * TAU)).abs() < 1e-4);
+        assert_eq!(wrap_angle(-1e-9), 0.0);
+    }
+
+    #[test]
+    fn angle_difference_takes_shortest_path() {
+        assert!((angle_difference(0.1, TAU - 0.1) - 0.2).abs() < 1e-5);
+        assert!((angle_difference(TAU - 0.1, 0.1) + 0.2).abs() < 1e-5);
+        assert!((angle_difference(PI + 0.5, PI) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn lqr_gain_recomputed_after_set_gains() {
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+
+        let k = lqr.gain().unwrap();
+        assert!(!lqr.dirty);
+        assert_eq!(lqr.gain().unwrap(), k);
+
+        lqr.set_gains(10.0, 1.0, 1.0);
+        assert!(lqr.dirty);
+        assert_ne!(lqr.gain().unwrap(), k);
+        assert!(!lqr.dirty);
+    }
+
+    #[test]
+    fn cross_cost_changes_gain_and_is_checked() {
+        let pendulum = Pendulum::default();
+        let mut lqr = LQR::new(PI, pendulum.get_system(PI, DEFAULT_DT));
+        lqr.set_gains(10.0, 1.0, 1.0);
+        let k = lqr.gain().unwrap();
+
+        lqr.set_q(10.0, 2.0, 1.0);
+        assert!(lqr.dirty);
+        assert_eq!(lqr.q[(0, 1)], lqr.q[(1, 0)]);
+        assert!(lqr.q_semidefinite());
+        assert_ne!(lqr.gain().unwrap(), k);
+
+        lqr.set_q(10.0, 4.0, 1.0);
+        assert!(!lqr.q_semidefinite());
+    }
+
+    #[test]
+    fn app_rng_reseed_repeats_sequence() {
+        let mut rng = AppRng::new(7);
+        let first: Vec<f32> = (0..4).map(|_| rng.normal(1.0)).collect();
+
+        rng.reseed();
+        let again: Vec<f32> = (0..4).map(|_| rng.normal(1.0)).collect();
+        assert_eq!(first, again);
+
+        let mut other = AppRng::new(8);
+        assert_ne!(first[0], other.normal(1.0));
+    }
+
+    #[test]
+    fn seed_is_read_from_the_arguments() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(parse_seed(&args(&["--seed", "7"])), Ok(7));
+        assert_eq!(parse_seed(&args(&["--other"])), Ok(0));
+        assert!(parse_seed(&args(&["--seed"])).is_err());
+        assert!(parse_seed(&args(&["--seed", "seven"])).is_err());
+    }
+
+    #[test]
+    fn randomize_stays_in_range_and_clears_history() {
+        let mut pendulum = Pendulum::default();
+        pendulum.angle_history.push(1.0);
+        let mut rng = AppRng::new(1);
+        let ranges = InitialRanges {
+            angle: (0.5, 1.0),
+            velocity: (-2.0, -1.0),
+        };
+
+        for _ in 0..20 {
+            randomize_pendulum(&mut pendulum, &mut rng, &ranges);
+            assert!((0.5..1.0).contains(&pendulum.a));
+            assert!((-2.0..-1.0).contains(&pendulum.da));
+        }
+        assert_eq!(pendulum.angle_history.end(), 0);
+    }
+
+    #[test]
+    fn reset_event_clears_everything_attached() {
+        let mut app = App::new();
+        app.add_event::<PendulumResetEvent>()
+            .add_system_set(reset_systems());
+
+        let wound_up = || {
+            let mut pid = PID {
+                accumulator: 3.0,
+                ..default()
+            };
+            pid.error_history.push(0.2);
+            pid
+        };
+        let mut sliding = SlidingMode::default();
+        sliding.surface_history.push(0.3);
+        let mut stability = Stability {
+            status: Some(StabilityStatus::Unstable),
+            ..default()
+        };
+        stability.errors.push_back(1.0);
+        let pendulum = Pendulum {
+            controller: ControllerKind::Manual,
+            ..default()
+        };
+        let entity = app
+            .world
+            .spawn((
+                pendulum,
+                wound_up(),
+                sliding,
+                Cascade {
+                    accumulator: 0.5,
+                    ..default()
+                },
+                stability,
+                StepTest {
+                    running: Some((PI, PI + 0.1)),
+                    ..default()
+                },
+                FrictionIdentification::new(ControllerKind::Lqr),
+            ))
+            .id();
+        let untouched = app.world.spawn(wound_up()).id();
+
+        app.world
+            .resource_mut::<Events<PendulumResetEvent>>()
+            .send(PendulumResetEvent(entity));
+        app.update();
+
+        let reset = app.world.entity(entity);
+        assert_eq!(reset.get::<PID>().unwrap().accumulator, 0.0);
+        assert_eq!(reset.get::<PID>().unwrap().error_history.end(), 0);
+        assert_eq!(reset.get::<SlidingMode>().unwrap().surface_history.end(), 0);
+        assert_eq!(reset.get::<Cascade>().unwrap().accumulator, 0.0);
+        let stability = reset.get::<Stability>().unwrap();
+        assert!(stability.errors.is_empty());
+        assert_eq!(stability.status, None);
+        assert!(reset.get::<StepTest>().unwrap().running.is_none());
+        assert!(!reset.get::<FrictionIdentification>().unwrap().running);
+        assert_eq!(
+            reset.get::<Pendulum>().unwrap().controller,
+            ControllerKind::Lqr
+        );
+        let other = app.world.entity(untouched).get::<PID>().unwrap();
+        assert_eq!(other.accumulator, 3.0);
+    }
+
+    #[test]
+    fn saving_preset_replaces_same_name() {
+        let mut presets = InitialPresets::default();
+        let count = presets.presets.len();
+
+        presets.save(InitialPreset::new("Custom", 1.0, 0.0));
+        presets.save(InitialPreset::new("Custom", 2.0, 0.5));
+        assert_eq!(presets.presets.len(), count + 1);
+        assert_eq!(presets.presets.last().unwrap().a, 2.0);
+    }
+
+    #[test]
+    fn derivative_filter_reduces_noise_variance() {
+        fn variance(v: &[f32]) -> f32 {
+            let mean = v.iter().sum::<f32>() / v.len() as f32;
+            v.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / v.len() as f32
+        }
+
+        let mut pid = PID {
+            derivative_filter_tau: 0.2,
+            ..default()
+        };
+
+        // Deterministic pseudo-random noise around a constant velocity
+        let mut seed: u32 = 12345;
+        let raw: Vec<f32> = (0..1000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                1.0 + (seed as f32 / u32::MAX as f32 - 0.5)
+            })
+            .collect();
+        let filtered: Vec<f32> = raw
+            .iter()
+            .map(|da| pid.filter_derivative(*da, DEFAULT_DT))
+            .skip(100)
+            .collect();
+
+        assert!(variance(&filtered) < variance(&raw[100..]) / 2.0);
+    }
+
+    #[test]
+    fn zero_tau_passes_derivative_through() {
+        let mut pid = PID::default();
+        assert_eq!(pid.filter_derivative(0.7, DEFAULT_DT), 0.7);
+        assert_eq!(pid.filter_derivative(-0.3, DEFAULT_DT), -0.3);
+    }
+
+    #[test]
+    fn set_control_applies_dead_zone() {
+        let mut pendulum = Pendulum {
+            dead_zone: 0.1,
+            ..default()
+        };
+
+        pendulum.set_control(0.09, DEFAULT_DT);
+        assert_eq!(pendulum.control, 0.0);
+        pendulum.set_control(-0.09, DEFAULT_DT);
+        assert_eq!(pendulum.control, 0.0);
+        pendulum.set_control(0.1, DEFAULT_DT);
+        assert_eq!(pendulum.control, 0.1);
+        pendulum.set_control(-0.5, DEFAULT_DT);
+        assert_eq!(pendulum.control, -0.5);
+    }
+
+    #[test]
+    fn euler_diverges_past_stable_dt() {
+        let swing = |dt: f32| {
+            let mut pendulum = Pendulum {
+                a: 0.01,
+                da: 0.0,
+                ..default()
+            };
+            for _ in 0..200 {
+                pendulum.step(IntegratorKind::Euler, dt);
+            }
+            pendulum.a
+        };
+        let limit = stable_dt(IntegratorKind::Euler, &Pendulum::default());
+
+        assert!(angle_difference(swing(0.9 * limit), 0.0).abs() < 0.05);
+        assert!(angle_difference(swing(1.1 * limit), 0.0).abs() > 0.05);
+    }
+
+    #[test]
+    fn torque_mode_drives_the_same_after_rescaling() {
+        let mut normalized = Pendulum {
+            a: 0.0,
+            da: 0.0,
+            ..default()
+        };
+        let mut torque = normalized.clone();
+        torque.set_control_mode(ControlMode::Torque);
+        let scale = normalized.control_power * normalized.inertia();
+        assert_eq!(torque.control_max, normalized.control_max * scale);
+
+        normalized.set_control(0.4, DEFAULT_DT);
+        torque.set_control(0.4 * scale, DEFAULT_DT);
+        normalized.step(IntegratorKind::Euler, DEFAULT_DT);
+        torque.step(IntegratorKind::Euler, DEFAULT_DT);
+        assert!((torque.da - normalized.da).abs() < 1e-6);
+        // Same model with the input in different units
+        let (a_t, b_t) = torque.get_system(PI, DEFAULT_DT);
+        let (a_n, b_n) = normalized.get_system(PI, DEFAULT_DT);
+        assert_eq!(a_t, a_n);
+        assert!((b_t * scale - b_n).norm() < 1e-6);
+    }
+
+    #[test]
+    fn friction_identification_recovers_viscous_friction() {
+        let mut pendulum = Pendulum {
+            friction: 0.08,
+            controller: ControllerKind::Pid,
+            ..default()
+        };
+        let mut identification = FrictionIdentification::new(pendulum.controller);
+
+        while identification.running {
+            identification.update(&mut pendulum, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        let estimate = identification.estimate.unwrap();
+        assert!((estimate - 0.08).abs() < 0.005, "estimated {}", estimate);
+        assert_eq!(pendulum.friction, estimate);
+        assert_eq!(pendulum.controller, ControllerKind::Pid);
+    }
+
+    #[test]
+    fn swing_up_reaches_the_handoff_window() {
+        let mut pendulum = Pendulum {
+            a: 0.0,
+            controller: ControllerKind::SwingUp,
+            // Too weak to lift it straight up, so it has to pump
+            control_min: -0.15,
+            control_max: 0.15,
+            ..default()
+        };
+        let swing_up = SwingUp::default();
+        let mut steps = 0;
+        while swing_up.is_active(&pendulum) && steps < (60.0 / DEFAULT_DT) as usize {
+            pendulum.set_control(swing_up.control(&pendulum), DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+            steps += 1;
+        }
+        assert!(!swing_up.is_active(&pendulum));
+    }
+
+    #[test]
+    fn small_swings_oscillate_at_natural_frequency() {
+        for mass in [1.0, 5.0] {
+            let mut pendulum = Pendulum {
+                a: 0.01,
+                da: 0.0,
+                mass,
+                ..default()
+            };
+            let dt = 0.001;
+
+            // Time between successive downward zero crossings is one period
+            let mut crossings = Vec::new();
+            let mut step = 0;
+            while crossings.len() < 2 {
+                let before = angle_difference(pendulum.a, 0.0);
+                pendulum.step(IntegratorKind::Rk4, dt);
+                step += 1;
+                if before > 0.0 && angle_difference(pendulum.a, 0.0) <= 0.0 {
+                    crossings.push(step as f32 * dt);
+                }
+            }
+
+            let frequency = TAU / (crossings[1] - crossings[0]);
+            let expected = (G / pendulum.length).sqrt();
+            assert!((frequency - expected).abs() / expected < 1e-3);
+        }
+    }
+
+    #[test]
+    fn set_control_slews_at_max_rate() {
+        let mut pendulum = Pendulum {
+            max_control_rate: 2.0,
+            ..default()
+        };
+
+        pendulum.set_control(1.0, 0.1);
+        assert!((pendulum.control - 0.2).abs() < 1e-6);
+        pendulum.set_control(1.0, 0.1);
+        assert!((pendulum.control - 0.4).abs() < 1e-6);
+        pendulum.set_control(0.3, 0.1);
+        assert!((pendulum.control - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn delayed_control_lags_by_one_step() {
+        let mut delayed = Pendulum {
+            control_delay_steps: 1,
+            ..default()
+        };
+        let mut applied = Vec::new();
+
+        for value in [0.5, -0.25, 1.0, 0.0] {
+            delayed.set_control(value, DEFAULT_DT);
+            applied.push(delayed.delay_control());
+        }
+        assert_eq!(applied, [0.0, 0.5, -0.25, 1.0]);
+
+        // Stepping with the delay matches stepping without it one control behind
+        let mut delayed = Pendulum {
+            control_delay_steps: 1,
+            ..default()
+        };
+        let mut reference = Pendulum::default();
+        let mut previous = 0.0;
+        for value in [0.5, -0.25, 1.0, 0.0] {
+            delayed.set_control(value, DEFAULT_DT);
+            delayed.step_delayed(IntegratorKind::Rk4, DEFAULT_DT);
+            reference.set_control(previous, DEFAULT_DT);
+            reference.step(IntegratorKind::Rk4, DEFAULT_DT);
+            previous = value;
+            assert_eq!((delayed.a, delayed.da), (reference.a, reference.da));
+        }
+    }
+
+    #[test]
+    fn decimated_control_is_held_between_updates() {
+        let mut pendulum = Pendulum {
+            a: PI + 0.1,
+            control_decimation: 3,
+            ..default()
+        };
+        let mut pid = PID {
+            set_point: PI,
+            proportional_gain: -8.0,
+            derivative_gain: -4.0,
+            ..default()
+        };
+        let mut controls = Vec::new();
+
+        for _ in 0..9 {
+            pendulum.measured_a = pendulum.a;
+            pendulum.measured_da = pendulum.da;
+            if pendulum.control_due() {
+                let period = pendulum.control_period(DEFAULT_DT);
+                let control = pid.control(&pendulum, period);
+                pendulum.set_control(control, period);
+            }
+            controls.push(pendulum.control);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+            pendulum.tick_control();
+        }
+
+        for held in controls.chunks(3) {
+            assert!(held.iter().all(|c| *c == held[0]));
+        }
+        assert_ne!(controls[0], controls[3]);
+    }
+
+    #[test]
+    fn set_control_clamps_asymmetrically() {
+        let mut pendulum = Pendulum {
+            control_min: -0.25,
+            control_max: 1.0,
+            ..default()
+        };
+
+        pendulum.set_control(-1.0, DEFAULT_DT);
+        assert_eq!(pendulum.control, -0.25);
+        pendulum.set_control(2.0, DEFAULT_DT);
+        assert_eq!(pendulum.control, 1.0);
+        pendulum.set_control(-0.25, DEFAULT_DT);
+        assert_eq!(pendulum.control, -0.25);
+        pendulum.set_control(0.5, DEFAULT_DT);
+        assert_eq!(pendulum.control, 0.5);
+    }
+
+    #[test]
+    fn feedforward_reduces_off_vertical_steady_state_error() {
+        let steady_error = |enabled: bool| {
+            let mut pendulum = Pendulum::default();
+            let mut pid = PID {
+                set_point: 2.5,
+                proportional_gain: -8.0,
+                derivative_gain: -4.0,
+                feedforward: Feedforward {
+                    enabled,
+                    ..default()
+                },
+                ..default()
+            };
+
+            simulate_pid(
+                &mut pid,
+                &mut pendulum,
+                IntegratorKind::Rk4,
+                DEFAULT_DT,
+                2000,
+            );
+
+            angle_difference(pendulum.a, pid.set_point).abs()
+        };
+
+        assert!(steady_error(true) < 1e-3);
+        assert!(steady_error(false) > 10.0 * steady_error(true));
+    }
+
+    #[test]
+    fn back_calculation_bounds_accumulator_in_saturation() {
+        // Held a quarter turn away from the set point the output saturates on every step
+        let pendulum = Pendulum {
+            measured_a: PI / 2.0,
+            measured_da: 0.0,
+            ..default()
+        };
+        let mut pid = PID {
+            set_point: PI,
+            proportional_gain: -8.0,
+            integral_gain: -5.5,
+            derivative_gain: -4.0,
+            anti_windup: AntiWindup::BackCalculation,
+            ..default()
+        };
+
+        for _ in 0..10_000 {
+            pid.control(&pendulum, DEFAULT_DT);
+        }
+
+        // The accumulator settles where the integral and tracking terms cancel
+        let error = angle_difference(pendulum.measured_a, pid.set_point);
+        let prop = error * pid.proportional_gain;
+        let bound = (error * pid.integral_gain / pid.tracking_gain).abs()
+            + (pendulum.control_min - prop)
+                .abs()
+                .max((pendulum.control_max - prop).abs());
+        assert!(
+            pid.accumulator.abs() <= bound + 1e-3,
+            "accumulator {} exceeds bound {}",
+            pid.accumulator,
+            bound
+        );
+
+        let mut unbounded = PID {
+            tracking_gain: 0.0,
+            accumulator: 0.0,
+            ..pid
+        };
+        for _ in 0..10_000 {
+            unbounded.control(&pendulum, DEFAULT_DT);
+        }
+        assert!(unbounded.accumulator.abs() > bound);
+    }
+
+    #[test]
+    fn clamp_waits_for_the_threshold() {
+        let pendulum = Pendulum {
+            measured_a: PI + 0.1,
+            measured_da: 0.0,
+            ..default()
+        };
+        let accumulated = |anti_windup: AntiWindup, windup_threshold: f32| {
+            let mut pid = PID {
+                anti_windup,
+                windup_threshold,
+                ..PID::balancing()
+            };
+            for _ in 0..100 {
+                pid.control(&pendulum, DEFAULT_DT);
+            }
+            pid.accumulator
+        };
+
+        assert_eq!(accumulated(AntiWindup::Clamp, 0.05), 0.0);
+        assert_ne!(accumulated(AntiWindup::Clamp, 0.3), 0.0);
+        // Once integrating it still stops where the output saturates
+        assert!(accumulated(AntiWindup::None, 0.05) < accumulated(AntiWindup::Clamp, 0.3));
+    }
+
+    #[test]
+    fn clamp_holds_the_accumulator_in_saturation() {
+        // Inside the threshold, but the output is pinned at `control_max` regardless
+        let pendulum = Pendulum {
+            measured_a: PI - 0.04,
+            measured_da: 0.0,
+            control_min: -0.1,
+            control_max: 0.1,
+            ..default()
+        };
+        let mut pid = PID::balancing();
+        assert_eq!(pid.anti_windup, AntiWindup::Clamp);
+
+        pid.control(&pendulum, DEFAULT_DT);
+        let held = pid.accumulator;
+        for _ in 0..1000 {
+            assert!(pid.control(&pendulum, DEFAULT_DT) >= pendulum.control_max);
+        }
+        assert_eq!(pid.accumulator, held);
+
+        let mut unclamped = PID {
+            anti_windup: AntiWindup::None,
+            ..PID::balancing()
+        };
+        for _ in 0..1000 {
+            unclamped.control(&pendulum, DEFAULT_DT);
+        }
+        assert!(unclamped.accumulator > held + 1.0);
     }
 }
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use bevy_prototype_debug_lines::DebugLines;
+use nalgebra::{DMatrix, DVector, Matrix2x1};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+use crate::{
+    angle_difference, to_rectangular, ControllerKind, Pendulum, ResetState, SimulationClock, A, B,
+    Q, R,
+};
+
+/// Finite horizon model predictive control over the linearized pendulum
+///
+/// Each step the quadratic cost over the horizon is condensed into a QP in the control sequence
+/// alone and solved by projected gradient descent, which keeps every input inside the pendulum's
+/// saturation limits rather than clipping the first one afterwards like LQR effectively does
+#[derive(Component, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mpc {
+    pub set_point: f32,
+    pub horizon: usize,
+    pub q: Q,
+    pub r: R,
+    /// Projected gradient steps per control update
+    pub iterations: usize,
+    /// Control sequence from the previous solve, shifted by one to warm start the next
+    #[serde(skip)]
+    pub plan: Vec<f32>,
+    /// Angles the model expects over the horizon if the plan gets applied
+    #[serde(skip)]
+    pub predicted: Vec<f32>,
+}
+
+impl Default for Mpc {
+    fn default() -> Self {
+        Self {
+            set_point: PI,
+            horizon: 30,
+            q: Q::identity(),
+            r: R::identity(),
+            iterations: 100,
+            plan: Vec::new(),
+            predicted: Vec::new(),
+        }
+    }
+}
+
+impl ResetState for Mpc {
+    /// Solves from scratch next time instead of warm starting from a plan for another state
+    fn reset(&mut self) {
+        self.plan.clear();
+        self.predicted.clear();
+    }
+}
+
+impl Mpc {
+    /// Returns the first input of the optimal control sequence from deviation state `x0`
+    pub fn solve(&mut self, (a, b): (A, B), x0: Matrix2x1<f32>, (u_min, u_max): (f32, f32)) -> f32 {
+        let n = self.horizon.max(1);
+
+        // Condensed prediction x_{k+1} = A^{k+1} x0 + sum_j A^{k-j} B u_j
+        let mut powers = vec![A::identity()];
+        for k in 1..=n {
+            powers.push(a * powers[k - 1]);
+        }
+
+        let mut gamma = DMatrix::<f32>::zeros(2 * n, n);
+        let mut free = DVector::<f32>::zeros(2 * n);
+        for k in 0..n {
+            let x = powers[k + 1] * x0;
+            free[2 * k] = x[0];
+            free[2 * k + 1] = x[1];
+            for j in 0..=k {
+                let column = powers[k - j] * b;
+                gamma[(2 * k, j)] = column[0];
+                gamma[(2 * k + 1, j)] = column[1];
+            }
+        }
+
+        let mut q_bar = DMatrix::<f32>::zeros(2 * n, 2 * n);
+        for k in 0..n {
+            q_bar
+                .fixed_slice_mut::<2, 2>(2 * k, 2 * k)
+                .copy_from(&self.q);
+        }
+
+        let gamma_q = gamma.transpose() * q_bar;
+        let hessian = &gamma_q * &gamma + DMatrix::identity(n, n) * self.r[0];
+        let linear = gamma_q * free;
+
+        // Gershgorin bound on the largest eigenvalue gives a step size that always descends
+        let lipschitz = hessian
+            .row_iter()
+            .map(|row| row.iter().map(|h| h.abs()).sum::<f32>())
+            .fold(f32::EPSILON, f32::max);
+
+        let mut u = DVector::from_fn(n, |i, _| {
+            let warm = self.plan.get(i + 1).or(self.plan.last()).copied();
+            warm.unwrap_or(0.0).clamp(u_min, u_max)
+        });
+        for _ in 0..self.iterations {
+            let gradient = &hessian * &u + &linear;
+            u -= gradient / lipschitz;
+            u.apply(|u| *u = u.clamp(u_min, u_max));
+        }
+
+        self.plan = u.iter().copied().collect();
+
+        let mut x = x0;
+        self.predicted.clear();
+        for u in &self.plan {
+            x = a * x + b * *u;
+            self.predicted.push(self.set_point + x[0]);
+        }
+
+        self.plan[0]
+    }
+}
+
+pub fn control_pendulum_mpc(
+    clock: Res<SimulationClock>,
+    mut query: Query<(&mut Pendulum, &mut Mpc)>,
+) {
+    for (mut pendulum, mut mpc) in query.iter_mut() {
+        if pendulum.controller != ControllerKind::Mpc || !pendulum.control_due() {
+            continue;
+        }
+        let period = pendulum.control_period(clock.dt);
+
+        let x0 = Matrix2x1::new(
+            angle_difference(pendulum.measured_a, mpc.set_point),
+            pendulum.measured_da,
+        );
+        let limits = (pendulum.control_min, pendulum.control_max);
+        let system = pendulum.get_system(mpc.set_point, clock.dt);
+        let control = mpc.solve(system, x0, limits);
+
+        pendulum.set_control(control, period);
+    }
+}
+
+/// Traces where the model expects the bob to go over the horizon
+pub fn draw_mpc_prediction(mut lines: ResMut<DebugLines>, query: Query<(&Pendulum, &Mpc)>) {
+    for (pendulum, mpc) in query.iter() {
+        let tip = |a: f32| {
+            let (x, y) = to_rectangular(pendulum.length, a);
+            pendulum.offset + Vec3::new(x, y, 0.0)
+        };
+
+        let mut from = tip(pendulum.a);
+        for a in &mpc.predicted {
+            let to = tip(*a);
+            lines.line_colored(from, to, 0.0, Color::YELLOW);
+            from = to;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntegratorKind, DEFAULT_DT};
+
+    #[test]
+    fn mpc_balances_within_limits() {
+        let mut pendulum = Pendulum {
+            control_min: -0.5,
+            control_max: 0.5,
+            ..default()
+        };
+        let mut mpc = Mpc::default();
+
+        for _ in 0..600 {
+            let x0 = Matrix2x1::new(angle_difference(pendulum.a, mpc.set_point), pendulum.da);
+            let limits = (pendulum.control_min, pendulum.control_max);
+            let control = mpc.solve(pendulum.get_system(mpc.set_point, DEFAULT_DT), x0, limits);
+
+            assert!(mpc.plan.iter().all(|u| (-0.5..=0.5).contains(u)));
+
+            pendulum.set_control(control, DEFAULT_DT);
+            pendulum.step(IntegratorKind::Rk4, DEFAULT_DT);
+        }
+
+        assert!(angle_difference(pendulum.a, PI).abs() < 0.01);
+    }
+}
@@ -0,0 +1,143 @@
+use image::{Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+/// Space left around the plot area, as a fraction of the image height
+const MARGIN: f64 = 0.05;
+const BACKGROUND: Rgb<u8> = Rgb([255, 255, 255]);
+const AXIS: Rgb<u8> = Rgb([160, 160, 160]);
+/// Series get these colors in order, the same order as the legend of the live plot
+const PALETTE: [Rgb<u8>; 6] = [
+    Rgb([31, 119, 180]),
+    Rgb([255, 127, 14]),
+    Rgb([44, 160, 44]),
+    Rgb([214, 39, 40]),
+    Rgb([148, 103, 189]),
+    Rgb([140, 86, 75]),
+];
+
+/// Rasterizes the series into a `width` by `height` image, scaled so every finite point fits
+///
+/// The frame and the zero line are drawn but no text, the axes go from the smallest to the
+/// largest value over all series
+pub fn render_plot(series: &[(&str, Vec<[f64; 2]>)], (width, height): (u32, u32)) -> RgbImage {
+    let mut image = RgbImage::from_pixel(width.max(1), height.max(1), BACKGROUND);
+
+    let finite = || {
+        series
+            .iter()
+            .flat_map(|(_, points)| points)
+            .filter(|[x, y]| x.is_finite() && y.is_finite())
+    };
+    let bounds = |axis: usize| {
+        let (min, max) = finite().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), p| {
+            (min.min(p[axis]), max.max(p[axis]))
+        });
+        if min > max {
+            (0.0, 1.0)
+        } else if min == max {
+            (min - 1.0, max + 1.0)
+        } else {
+            (min, max)
+        }
+    };
+    let (x_min, x_max) = bounds(0);
+    let (y_min, y_max) = bounds(1);
+
+    let margin = MARGIN * image.height() as f64;
+    let (w, h) = (
+        image.width() as f64 - 2.0 * margin,
+        image.height() as f64 - 2.0 * margin,
+    );
+    let to_pixel = |[x, y]: [f64; 2]| {
+        (
+            margin + (x - x_min) / (x_max - x_min) * w,
+            margin + (y_max - y) / (y_max - y_min) * h,
+        )
+    };
+    let thickness = (image.height() / 400).max(1);
+
+    let corners = [
+        [x_min, y_min],
+        [x_max, y_min],
+        [x_max, y_max],
+        [x_min, y_max],
+    ];
+    for i in 0..corners.len() {
+        let (from, to) = (corners[i], corners[(i + 1) % corners.len()]);
+        draw_line(&mut image, to_pixel(from), to_pixel(to), 1, AXIS);
+    }
+    if y_min < 0.0 && y_max > 0.0 {
+        draw_line(
+            &mut image,
+            to_pixel([x_min, 0.0]),
+            to_pixel([x_max, 0.0]),
+            1,
+            AXIS,
+        );
+    }
+
+    for (i, (_, points)) in series.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        for pair in points.windows(2) {
+            if pair.iter().flatten().all(|v| v.is_finite()) {
+                let (from, to) = (to_pixel(pair[0]), to_pixel(pair[1]));
+                draw_line(&mut image, from, to, thickness, color);
+            }
+        }
+    }
+
+    image
+}
+
+/// Steps along the longer axis of the segment, drawing a `thickness` sized square at each step
+fn draw_line(
+    image: &mut RgbImage,
+    (x0, y0): (f64, f64),
+    (x1, y1): (f64, f64),
+    thickness: u32,
+    color: Rgb<u8>,
+) {
+    let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.0) as u32;
+    for step in 0..=steps {
+        let t = step as f64 / steps as f64;
+        let (x, y) = (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+        for dx in 0..thickness {
+            for dy in 0..thickness {
+                let (px, py) = (x as i64 + dx as i64, y as i64 + dy as i64);
+                if px >= 0 && py >= 0 && px < image.width() as i64 && py < image.height() as i64 {
+                    image.put_pixel(px as u32, py as u32, color);
+                }
+            }
+        }
+    }
+}
+
+/// Writes `render_plot` to `<directory>/<name>.png`
+pub fn save_plot(
+    directory: &str,
+    name: &str,
+    series: &[(&str, Vec<[f64; 2]>)],
+    size: (u32, u32),
+) -> Result<PathBuf, String> {
+    let path = Path::new(directory).join(format!("{}.png", name));
+    render_plot(series, size)
+        .save(&path)
+        .map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_series_at_requested_size() {
+        let series = [("Control", vec![[0.0, -1.0], [1.0, 1.0]])];
+        let image = render_plot(&series, (200, 100));
+
+        assert_eq!(image.dimensions(), (200, 100));
+        // The diagonal passes through the center of the plot area
+        assert_eq!(*image.get_pixel(100, 50), PALETTE[0]);
+        assert_eq!(*image.get_pixel(190, 90), BACKGROUND);
+    }
+}
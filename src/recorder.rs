@@ -0,0 +1,219 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fs, path::Path};
+
+use crate::{ConfigError, ExportSettings, Pendulum, SimulationClock};
+
+const RECORDING_FILE: &str = "recording.ron";
+
+/// State of one pendulum after a physics step
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub a: f32,
+    pub da: f32,
+    pub control: f32,
+}
+
+/// Snapshots of every pendulum for each physics step, pendulums ordered by their offset
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub dt: f32,
+    pub frames: Vec<Vec<Snapshot>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RecorderMode {
+    #[default]
+    Idle,
+    Recording,
+    Replaying,
+}
+
+#[derive(Resource, Default)]
+pub struct Recorder {
+    pub mode: RecorderMode,
+    pub recording: Recording,
+    /// Frame shown next while replaying
+    pub cursor: usize,
+}
+
+impl Recorder {
+    pub fn replaying(&self) -> bool {
+        self.mode == RecorderMode::Replaying
+    }
+
+    /// Moves the replay on by one physics step, going back to idle after the last frame
+    pub fn advance(&mut self) {
+        self.cursor += 1;
+        if self.cursor >= self.recording.frames.len() {
+            self.mode = RecorderMode::Idle;
+        }
+    }
+
+    pub fn frame(&self) -> Option<&[Snapshot]> {
+        self.recording.frames.get(self.cursor).map(Vec::as_slice)
+    }
+}
+
+pub enum RecorderEvent {
+    StartRecording,
+    StopRecording,
+    Replay,
+    StopReplay,
+}
+
+/// Query order is not stable across restarts, the offsets of the tiles are
+pub fn by_offset(a: &Pendulum, b: &Pendulum) -> Ordering {
+    let key = |p: &Pendulum| (p.offset.y, p.offset.x);
+    key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal)
+}
+
+pub fn handle_recorder_events(
+    mut events: EventReader<RecorderEvent>,
+    mut recorder: ResMut<Recorder>,
+    mut error: ResMut<ConfigError>,
+    export: Res<ExportSettings>,
+    clock: Res<SimulationClock>,
+) {
+    let path = Path::new(&export.directory).join(RECORDING_FILE);
+
+    for event in events.iter() {
+        match event {
+            RecorderEvent::StartRecording => {
+                recorder.recording = Recording {
+                    dt: clock.dt,
+                    frames: Vec::new(),
+                };
+                recorder.mode = RecorderMode::Recording;
+            }
+            RecorderEvent::StopRecording => {
+                recorder.mode = RecorderMode::Idle;
+
+                let result = ron::to_string(&recorder.recording)
+                    .map_err(|err| err.to_string())
+                    .and_then(|ron| fs::write(&path, ron).map_err(|err| err.to_string()));
+
+                match result {
+                    Ok(()) => info!("Saved recording to {}", path.display()),
+                    Err(err) => {
+                        error.0 = Some(format!("Failed to save {}: {}", path.display(), err))
+                    }
+                }
+            }
+            RecorderEvent::Replay => {
+                let result = fs::read_to_string(&path)
+                    .map_err(|err| err.to_string())
+                    .and_then(|ron| ron::from_str::<Recording>(&ron).map_err(|err| err.to_string()))
+                    .and_then(|recording| {
+                        if recording.dt == clock.dt {
+                            Ok(recording)
+                        } else {
+                            Err(format!(
+                                "recorded with dt {} but the simulation runs at {}",
+                                recording.dt, clock.dt
+                            ))
+                        }
+                    });
+
+                match result {
+                    Ok(recording) if !recording.frames.is_empty() => {
+                        recorder.recording = recording;
+                        recorder.cursor = 0;
+                        recorder.mode = RecorderMode::Replaying;
+                    }
+                    Ok(_) => info!("Recording in {} is empty", path.display()),
+                    Err(err) => {
+                        error.0 = Some(format!("Failed to load {}: {}", path.display(), err))
+                    }
+                }
+            }
+            RecorderEvent::StopReplay => recorder.mode = RecorderMode::Idle,
+        }
+    }
+}
+
+pub fn record_pendulums(mut recorder: ResMut<Recorder>, query: Query<&Pendulum>) {
+    if recorder.mode != RecorderMode::Recording {
+        return;
+    }
+
+    let mut pendulums: Vec<&Pendulum> = query.iter().collect();
+    pendulums.sort_by(|a, b| by_offset(a, b));
+
+    let frame = pendulums
+        .iter()
+        .map(|p| Snapshot {
+            a: p.a,
+            da: p.da,
+            control: p.control,
+        })
+        .collect();
+    recorder.recording.frames.push(frame);
+}
+
+/// Overwrites the pendulum states with the current replay frame, the physics stage does not step
+/// while replaying so this is all that moves them
+pub fn replay_pendulums(recorder: Res<Recorder>, mut query: Query<&mut Pendulum>) {
+    if !recorder.replaying() {
+        return;
+    }
+    let Some(frame) = recorder.frame() else {
+        return;
+    };
+
+    let mut pendulums: Vec<Mut<Pendulum>> = query.iter_mut().collect();
+    pendulums.sort_by(|a, b| by_offset(a, b));
+
+    for (pendulum, snapshot) in pendulums.iter_mut().zip(frame) {
+        pendulum.a = snapshot.a;
+        pendulum.da = snapshot.da;
+        pendulum.control = snapshot.control;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_stops_after_last_frame() {
+        let snapshot = Snapshot {
+            a: 1.0,
+            da: 0.0,
+            control: 0.5,
+        };
+        let mut recorder = Recorder {
+            mode: RecorderMode::Replaying,
+            recording: Recording {
+                dt: 0.05,
+                frames: vec![vec![snapshot]; 2],
+            },
+            cursor: 0,
+        };
+
+        assert_eq!(recorder.frame(), Some(&[snapshot][..]));
+        recorder.advance();
+        assert!(recorder.replaying());
+        recorder.advance();
+        assert!(!recorder.replaying());
+        assert_eq!(recorder.frame(), None);
+    }
+
+    #[test]
+    fn recording_round_trips_through_ron() {
+        let recording = Recording {
+            dt: 0.05,
+            frames: vec![vec![Snapshot {
+                a: 3.0,
+                da: -0.25,
+                control: 1.0,
+            }]],
+        };
+
+        let ron = ron::to_string(&recording).unwrap();
+        let loaded: Recording = ron::from_str(&ron).unwrap();
+
+        assert_eq!(loaded.dt, recording.dt);
+        assert_eq!(loaded.frames, recording.frames);
+    }
+}
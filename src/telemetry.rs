@@ -0,0 +1,282 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{angle_difference, recorder::by_offset, Pendulum, SimulationClock, LQR, PID};
+
+const DEFAULT_PORT: u16 = 9000;
+/// Bytes queued for a client that is not reading before it gets dropped
+const MAX_PENDING: usize = 1 << 20;
+
+/// One line of the stream, sent for every pendulum after each physics step
+#[derive(Debug, Serialize)]
+pub struct TelemetryRecord {
+    /// Simulated seconds since streaming started
+    pub t: f32,
+    /// Position of the pendulum ordered by offset, the same order recordings use
+    pub index: usize,
+    pub angle: f32,
+    pub velocity: f32,
+    pub control: f32,
+    /// From the PID or LQR set point, or from upright without either
+    pub error: f32,
+}
+
+struct Client {
+    stream: TcpStream,
+    /// Bytes the socket would not take yet, sent before anything newer
+    pending: Vec<u8>,
+}
+
+impl Client {
+    /// Queues `bytes` and writes as much as the socket takes without blocking, `false` once the
+    /// client is gone or too far behind
+    fn send(&mut self, bytes: &[u8]) -> bool {
+        self.pending.extend_from_slice(bytes);
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => return false,
+            }
+        }
+        self.pending.len() <= MAX_PENDING
+    }
+}
+
+/// Newline-delimited JSON over TCP, all sockets are non-blocking so clients that are missing or
+/// slow never hold up the simulation
+#[derive(Resource)]
+pub struct Telemetry {
+    pub enabled: bool,
+    pub port: u16,
+    listener: Option<TcpListener>,
+    clients: Vec<Client>,
+    time: f32,
+    pub error: Option<String>,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_PORT,
+            listener: None,
+            clients: Vec::new(),
+            time: 0.0,
+            error: None,
+        }
+    }
+}
+
+impl Telemetry {
+    pub fn new(port: Option<u16>) -> Self {
+        Self {
+            enabled: port.is_some(),
+            port: port.unwrap_or(DEFAULT_PORT),
+            ..default()
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", self.port))?;
+        listener.set_nonblocking(true)?;
+        info!("Streaming telemetry on port {}", self.port);
+        self.listener = Some(listener);
+        self.time = 0.0;
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.listener = None;
+        self.clients.clear();
+    }
+
+    fn accept(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        loop {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        info!("Telemetry client connected from {}", address);
+                        self.clients.push(Client {
+                            stream,
+                            pending: Vec::new(),
+                        });
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    warn!("Failed to accept telemetry client: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn broadcast(&mut self, lines: &[u8]) {
+        self.clients.retain_mut(|client| client.send(lines));
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+/// Formats the records as JSON, one per line
+pub fn telemetry_lines(records: &[TelemetryRecord]) -> String {
+    records
+        .iter()
+        .filter_map(|record| serde_json::to_string(record).ok())
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Reads the optional `--telemetry PORT` of the windowed app
+pub fn parse_telemetry_port(args: &[String]) -> Result<Option<u16>, String> {
+    match args.iter().position(|arg| arg == "--telemetry") {
+        Some(i) => {
+            let value = args.get(i + 1).ok_or("missing value for --telemetry")?;
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid value {} for --telemetry", value))
+        }
+        None => Ok(None),
+    }
+}
+
+pub fn stream_telemetry(
+    mut telemetry: ResMut<Telemetry>,
+    clock: Res<SimulationClock>,
+    query: Query<(&Pendulum, Option<&PID>, Option<&LQR>)>,
+) {
+    if !telemetry.enabled {
+        if telemetry.listener.is_some() {
+            telemetry.close();
+        }
+        return;
+    }
+    if telemetry.listener.is_none() {
+        if let Err(err) = telemetry.open() {
+            telemetry.error = Some(format!(
+                "Failed to listen on port {}: {}",
+                telemetry.port, err
+            ));
+            telemetry.enabled = false;
+            return;
+        }
+        telemetry.error = None;
+    }
+
+    telemetry.accept();
+    telemetry.time += clock.dt;
+    if telemetry.clients.is_empty() {
+        return;
+    }
+
+    let mut pendulums: Vec<_> = query.iter().collect();
+    pendulums.sort_by(|(a, ..), (b, ..)| by_offset(a, b));
+
+    let records: Vec<TelemetryRecord> = pendulums
+        .into_iter()
+        .enumerate()
+        .map(|(index, (pendulum, pid, lqr))| {
+            let set_point = match (pid, lqr) {
+                (Some(pid), _) => pid.set_point,
+                (None, Some(lqr)) => lqr.set_point,
+                (None, None) => std::f32::consts::PI,
+            };
+            TelemetryRecord {
+                t: telemetry.time,
+                index,
+                angle: pendulum.a,
+                velocity: pendulum.da,
+                control: pendulum.control,
+                error: angle_difference(pendulum.a, set_point),
+            }
+        })
+        .collect();
+    telemetry.broadcast(telemetry_lines(&records).as_bytes());
+}
+
+pub fn ui_telemetry(mut egui_context: ResMut<EguiContext>, mut telemetry: ResMut<Telemetry>) {
+    egui::Window::new("Telemetry")
+        .resizable(false)
+        .default_pos((560.0, 560.0))
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut telemetry.enabled, "Stream over TCP");
+                // The port only takes effect when the listener opens
+                let open = telemetry.listener.is_some();
+                ui.add_enabled(
+                    !open,
+                    egui::DragValue::new(&mut telemetry.port).prefix("port "),
+                );
+            });
+            if telemetry.listener.is_some() {
+                ui.label(format!("{} clients connected", telemetry.client_count()));
+            }
+            if let Some(err) = &telemetry.error {
+                ui.colored_label(egui::Color32::RED, err);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn records_are_one_json_object_per_line() {
+        let records = [0, 1].map(|index| TelemetryRecord {
+            t: 0.5,
+            index,
+            angle: 3.0,
+            velocity: -0.25,
+            control: 1.0,
+            error: 0.1,
+        });
+
+        let lines = telemetry_lines(&records);
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.ends_with('\n'));
+        assert!(lines.starts_with(r#"{"t":0.5,"index":0,"angle":3.0,"#));
+    }
+
+    #[test]
+    fn connected_client_receives_queued_lines() {
+        let mut telemetry = Telemetry {
+            port: 0,
+            ..default()
+        };
+        telemetry.open().unwrap();
+        let address = telemetry.listener.as_ref().unwrap().local_addr().unwrap();
+        let stream = TcpStream::connect(address).unwrap();
+
+        // The connection can take a moment to show up on the non-blocking listener
+        for _ in 0..100 {
+            telemetry.accept();
+            if telemetry.client_count() == 1 {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(telemetry.client_count(), 1);
+
+        telemetry.broadcast(b"{\"t\":0}\n");
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"t\":0}\n");
+    }
+}